@@ -1,29 +1,24 @@
 #[cfg(feature = "esp32")]
-use embedded_svc::{storage::*, wifi::*};
+use embedded_svc::wifi::*;
 #[cfg(feature = "esp32")]
 use esp_idf_svc::{netif::*, nvs::*, sysloop::*, wifi::*};
 use httpwm::*;
 #[cfg(feature = "web")]
 use kvarn::prelude::*;
-use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     sync::{Arc, Mutex},
     thread,
     time::Duration,
 };
 
+type Storage = Arc<Mutex<Box<dyn store::Store>>>;
+
+const STATE_KEY: &str = "state.ron";
+#[cfg(not(feature = "auto-tz"))]
+const TIMEZONE_KEY: &str = "timezone.txt";
 #[cfg(feature = "esp32")]
-type NvsStorage = Arc<Mutex<esp_idf_svc::nvs_storage::EspNvsStorage>>;
-
-const SAVE_PATH: &str = "state.ron";
-static SECOND_FORMAT: &[time::format_description::FormatItem] =
-    time::macros::format_description!("[hour]:[minute]:[second]");
-static MINUTE_FORMAT: &[time::format_description::FormatItem] =
-    time::macros::format_description!("[hour]:[minute]");
-static DATE_FORMAT: &[time::format_description::FormatItem] =
-    time::macros::format_description!("[year]-[month]-[day]");
-static DATE_TIME_FORMAT: &[time::format_description::FormatItem] =
-    time::macros::format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
+const NETWORKS_KEY: &str = "networks.ron";
 
 #[cfg(feature = "esp32")]
 static INDEX_HTML: &str = include_str!("../../web/public/index.html");
@@ -43,18 +38,36 @@ fn main() {
     #[cfg(feature = "esp32")]
     esp_idf_svc::log::EspLogger::initialize_default();
 
-    #[cfg(any(feature = "rpi", feature = "test"))]
+    #[cfg(any(feature = "rpi", feature = "sysfs", feature = "test"))]
     env_logger::init();
 
-    #[cfg(feature = "rpi")]
-    let pwm = rppal::pwm::Pwm::with_period(
-        rppal::pwm::Channel::Pwm0,
-        Duration::from_millis(1),
-        Duration::from_millis(0),
-        rppal::pwm::Polarity::Normal,
-        true,
-    )
-    .expect("failed to get PWM");
+    let config = match config::Config::from_args(std::env::args().skip(1)) {
+        Ok(config::Action::Run(config)) => config,
+        Ok(config::Action::PrintConfig(config)) => {
+            println!("{}", config.to_ron());
+            return;
+        }
+        Ok(config::Action::Help) => {
+            println!("{}", config::USAGE);
+            return;
+        }
+        Err(errors) => {
+            for err in errors {
+                eprintln!("error: {}", err);
+            }
+            eprintln!("Run with `--help` for usage.");
+            std::process::exit(2);
+        }
+    };
+
+    #[cfg(any(feature = "rpi", feature = "sysfs"))]
+    let pwm = match config.open_outputs() {
+        Ok(pwm) => pwm,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    };
 
     #[cfg(feature = "esp32")]
     let mut pwm = {
@@ -62,13 +75,43 @@ fn main() {
             ledc::config::TimerConfig, ledc::Channel, ledc::Resolution, ledc::Timer,
             peripherals::Peripherals, units::FromValueType,
         };
+        let (frequency, bits) = match config.ledc() {
+            Ok(ledc) => ledc,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                std::process::exit(1);
+            }
+        };
+        let resolution = match bits {
+            1 => Resolution::Bits1,
+            2 => Resolution::Bits2,
+            3 => Resolution::Bits3,
+            4 => Resolution::Bits4,
+            5 => Resolution::Bits5,
+            6 => Resolution::Bits6,
+            7 => Resolution::Bits7,
+            8 => Resolution::Bits8,
+            9 => Resolution::Bits9,
+            10 => Resolution::Bits10,
+            11 => Resolution::Bits11,
+            12 => Resolution::Bits12,
+            13 => Resolution::Bits13,
+            _ => Resolution::Bits14,
+        };
         let peripherals = Peripherals::take().unwrap();
-        let config = TimerConfig::default()
-            .frequency(25.kHz().into())
-            .resolution(Resolution::Bits10);
-        let timer = Timer::new(peripherals.ledc.timer0, &config).expect("esp32 timer failed");
-        Channel::new(peripherals.ledc.channel0, timer, peripherals.pins.gpio18)
-            .expect("failed to create a esp32 PWM channel")
+        let timer_config = TimerConfig::default()
+            .frequency((frequency.round() as u32).Hz().into())
+            .resolution(resolution);
+        let timer = Timer::new(peripherals.ledc.timer0, &timer_config).expect("esp32 timer failed");
+        let channel = Channel::new(peripherals.ledc.channel0, timer, peripherals.pins.gpio18)
+            .expect("failed to create a esp32 PWM channel");
+        match config.outputs[0].servo(Box::new(Ledc::new(channel, frequency, bits))) {
+            Ok(pwm) => pwm,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                std::process::exit(1);
+            }
+        }
     };
 
     #[cfg(feature = "esp32")]
@@ -80,21 +123,25 @@ fn main() {
     #[cfg(feature = "esp32")]
     let default_nvs = Arc::new(EspDefaultNvs::new().expect("Failed to create nvs on esp32"));
     #[cfg(feature = "esp32")]
-    let storage = Arc::new(Mutex::new(
+    let storage: Box<dyn store::Store> = Box::new(store::NvsStore::new(
         esp_idf_svc::nvs_storage::EspNvsStorage::new_default(default_nvs.clone(), "icelk", true)
             .expect("Failed to initialize persistent storage"),
     ));
-
-    #[cfg(feature = "esp32")]
-    let get_data = |path: &str| {
-        let storage = storage.lock().unwrap();
-        let mut buf = vec![0; storage.len(path).ok()?? as usize];
-        storage.get_raw(path, &mut buf).ok()?;
-        Some(buf)
+    #[cfg(not(feature = "esp32"))]
+    let storage: Box<dyn store::Store> = Box::new(store::FsStore::new(&config.data_dir));
+    let storage: Storage = Arc::new(Mutex::new(storage));
+
+    #[cfg(any(feature = "esp32", not(feature = "auto-tz")))]
+    let get_data = |key: &str| match storage.lock().unwrap().get(key) {
+        Ok(data) => data,
+        Err(err) => {
+            error!("Failed to read {:?}: {}", key, err);
+            None
+        }
     };
     #[cfg(feature = "esp32")]
     let known_networks = {
-        let mut networks = if let Some(file) = get_data("networks.ron") {
+        let mut networks = if let Some(file) = get_data(NETWORKS_KEY) {
             if let Ok(networks) = ron::de::from_bytes(&file) {
                 networks
             } else {
@@ -114,7 +161,7 @@ fn main() {
     #[cfg(feature = "esp32")]
     let _wifi = {
         let mut blink_light = || {
-            pwm.enable().unwrap();
+            pwm.enable();
             let initial = Instant::now();
             // function for intensity
             let f = |t: f64| (t * 10.).sin().abs() / (4. * t + 1.);
@@ -129,7 +176,7 @@ fn main() {
                 thread::sleep(Duration::from_millis(10));
             }
             pwm.set(Strength::new(0.));
-            pwm.disable().unwrap();
+            pwm.disable();
         };
         loop {
             match wifi(
@@ -168,14 +215,14 @@ fn main() {
         break;
     }
     // try load set timezone
-    #[cfg(feature = "esp32")]
+    #[cfg(not(feature = "auto-tz"))]
     {
-        if let Some(data) = get_data("timezone.txt") {
+        if let Some(data) = get_data(TIMEZONE_KEY) {
             if let Ok(s) = std::str::from_utf8(&data) {
                 if httpwm::env_timezone::try_set_timezone(s).is_err() {
                     error!("Failed to parse saved timezone: {s:?}");
                     let mut storage = storage.lock().unwrap();
-                    let _ = storage.remove("timezone.txt");
+                    let _ = storage.remove(TIMEZONE_KEY);
                 }
             }
         }
@@ -184,157 +231,79 @@ fn main() {
     #[cfg(feature = "test")]
     let pwm = PrintOut(test_output::spawn());
 
-    let time = time::Time::from_hms(7, 00, 00).unwrap();
-    let day_transition = Transition::default();
-
-    let startup_multiplier = Some(0.5);
-    let startup_duration = 1.0;
-    let startup_transition = Transition {
-        from: Strength::new(0.0),
-        to: Strength::new(1.0),
-        time: Duration::from_secs_f64(startup_duration),
-        interpolation: TransitionInterpolation::SineToAndBack(startup_multiplier.unwrap()),
+    let scheduler = scheduler::WeekScheduler::same(config.default_time(), config.day_transition());
+    let defaults = || {
+        let mut data = save_state::Data::from_week_scheduler(&scheduler);
+        data.power_on = config.power_on.clone();
+        data
     };
 
-    let scheduler = scheduler::WeekScheduler::same(time, day_transition);
-
     let (saved_state, week_scheduler) = {
-        #[cfg(not(feature = "esp32"))]
-        let saved_state = save_state::Data::read_from_file(SAVE_PATH, &scheduler);
-
-        #[cfg(feature = "esp32")]
-        let saved_state = {
-            let bytes = get_data(SAVE_PATH);
-            bytes
-                .map(|b| ron::de::from_bytes::<'_, save_state::Data>(&b).ok())
-                .flatten()
-                .map(|mut data| {
-                    if data.week_scheduler.is_none() {
-                        data.week_scheduler =
-                            Some(save_state::WeekSchedulerData::from_scheduler(&scheduler));
-                    }
-                    data
-                })
-                .ok_or(())
-        };
-
-        match saved_state.ok().and_then(|state| {
-            state
-                .ref_week_scheduler()
-                .to_scheduler()
-                .map(|scheduler| (scheduler, state))
-        }) {
-            Some((scheduler, data)) => (data, scheduler),
-            None => {
-                error!("Failed to parse state file. Using defaults.");
-                (save_state::Data::from_week_scheduler(&scheduler), scheduler)
+        let saved_state =
+            save_state::Data::load(&mut **storage.lock().unwrap(), STATE_KEY, &scheduler);
+
+        match saved_state {
+            Ok(Some(data)) => {
+                // `load` replaced a broken default week scheduler
+                let week_scheduler = data.default_week_scheduler().unwrap();
+                (data, week_scheduler)
+            }
+            Ok(None) => {
+                info!("No saved state. Using defaults.");
+                (defaults(), scheduler)
+            }
+            Err(err) => {
+                error!("Failed to load state: {}. Using defaults.", err);
+                (defaults(), scheduler)
             }
         }
     };
     let controller = Controller::new(pwm, week_scheduler);
 
-    controller.send(Command::SetTransition(startup_transition));
+    let startup_sequence = saved_state.power_on.sequence();
+    // the startup sequence changes the data of the saver
+    let restore = saved_state.clone();
 
     let shared = controller.get_state();
 
+    let registry = Arc::new(extra_schedulers::registry());
+    let saver = Arc::new(save_state::Saver::new(
+        saved_state,
+        Arc::clone(&shared),
+        Arc::clone(&storage),
+        STATE_KEY,
+    ));
+    // before anything is sent, so no change is missed
+    saver.subscribe(&controller);
     let controller = Arc::new(Mutex::new(controller));
-    let saved_state = Arc::new(Mutex::new(save_state::DataWrapper::new(saved_state)));
     {
-        let shared = Arc::clone(&shared);
-        let saved = Arc::clone(&saved_state);
         let controller = Arc::clone(&controller);
-        #[cfg(feature = "esp32")]
-        let storage = Arc::clone(&storage);
+        let registry = Arc::clone(&registry);
         thread::spawn(move || {
-            thread::sleep(Duration::from_secs_f64(
-                startup_duration * (startup_multiplier.unwrap_or(0.0) + 1.0),
-            ));
-            saved
-                .lock()
-                .unwrap()
-                .get_ref()
-                .apply(&*controller.lock().unwrap());
-
-            thread::spawn(move || loop {
-                thread::sleep(Duration::from_millis(1000));
-                let mut saved = saved.lock().unwrap();
-
-                let mut changed = false;
-                {
-                    let lock = shared.lock().unwrap();
-                    let present_schedulers = lock.ref_schedulers();
-
-                    let schedulers = saved.no_save_mut().mut_schedulers();
-
-                    let len = schedulers.len();
-                    schedulers.retain(|scheduler| present_schedulers.contains_key(&scheduler.name));
-                    if len != schedulers.len() {
-                        changed = true;
-                    }
-                }
-                {
-                    let shared = shared.lock().unwrap();
-                    match saved.get_ref().eq_transition(shared.get_transition()) {
-                        // Do nothing; they match
-                        true => {}
-                        false => {
-                            saved.no_save_mut().set_transition(shared.get_transition());
-                            changed = true;
-                        }
-                    }
-                }
-
-                if saved.save() || changed {
-                    info!("Saving state!");
-
-                    let data = {
-                        let config = ron::ser::PrettyConfig::default()
-                            .extensions(ron::extensions::Extensions::IMPLICIT_SOME);
-                        match ron::ser::to_string_pretty(saved.get_ref(), config) {
-                            Err(err) => {
-                                error!("Failed to serialize state {}", err);
-                                continue;
-                            }
-                            Ok(s) => s,
-                        }
-                    };
-                    drop(saved);
-
-                    #[cfg(not(feature = "esp32"))]
-                    {
-                        let mut file = match std::fs::File::create(SAVE_PATH) {
-                            Err(err) => {
-                                error!("Failed to create file {}", err);
-                                continue;
-                            }
-                            Ok(f) => f,
-                        };
-                        if let Err(err) = file.write_all(data.as_bytes()) {
-                            error!("Failed to write data to file {}", err);
-                        }
-                    }
-                    #[cfg(feature = "esp32")]
-                    {
-                        let mut storage = storage.lock().unwrap();
-                        if storage.put_raw(SAVE_PATH, data.as_bytes()).is_err() {
-                            error!("Failed to write to NVS.");
-                        }
-                    }
-                }
-            });
+            for transition in startup_sequence {
+                let time = transition.total_time();
+                controller
+                    .lock()
+                    .unwrap()
+                    .send(Command::SetTransition(transition));
+                thread::sleep(time);
+            }
+            restore.apply(&controller.lock().unwrap(), &registry);
         });
     }
 
     #[cfg(feature = "web")]
     run(
+        &config,
         controller,
-        saved_state,
+        Arc::clone(&saver),
         shared,
+        registry,
         #[cfg(feature = "esp32")]
         known_networks,
-        #[cfg(feature = "esp32")]
         storage,
     );
+    saver.flush();
 }
 
 #[cfg(feature = "web")]
@@ -348,31 +317,55 @@ fn get_query_value<'a, T>(req: &'a Request<T>, key: &'a str) -> Option<String> {
 #[cfg(feature = "web")]
 #[tokio::main(flavor = "current_thread")]
 async fn run<T: VariableOut + Send>(
+    config: &config::Config,
     controller: Arc<Mutex<Controller<T>>>,
-    save_state: Arc<Mutex<save_state::DataWrapper>>,
+    save_state: Arc<save_state::Saver>,
     shared: Arc<Mutex<SharedState>>,
+    registry: Arc<registry::Registry>,
     #[cfg(feature = "esp32")] known_networks: HashMap<String, String>,
-    #[cfg(feature = "esp32")] storage: NvsStorage,
+    storage: Storage,
 ) {
-    create_server(
+    let shutdown = create_server(
+        config,
         controller,
         save_state,
         shared,
+        registry,
         #[cfg(feature = "esp32")]
         known_networks,
-        #[cfg(feature = "esp32")]
         storage,
     )
     .execute()
-    .await
-    .wait()
     .await;
+
+    #[cfg(feature = "signal")]
+    tokio::select! {
+        _ = shutdown.wait() => {}
+        _ = shutdown_signal() => info!("Shutting down"),
+    }
+    #[cfg(not(feature = "signal"))]
+    shutdown.wait().await;
+}
+/// Resolves when the daemon is asked to stop, by Ctrl-C or `SIGTERM`.
+#[cfg(all(feature = "web", feature = "signal"))]
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
 }
 // #[cfg(all(feature = "web", feature = "esp32"))]
 // #[tokio::main(flavor = "current_thread")]
 // async fn run<T: VariableOut + Send>(
 // controller: Arc<Mutex<Controller<T>>>,
-// save_state: Arc<Mutex<save_state::DataWrapper>>,
+// save_state: Arc<save_state::Saver>,
 // shared: Arc<Mutex<SharedState>>,
 // ) {
 // let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
@@ -400,11 +393,13 @@ async fn run<T: VariableOut + Send>(
 
 #[cfg(feature = "web")]
 fn create_host<T: VariableOut + Send>(
+    config: &config::Config,
     controller: Arc<Mutex<Controller<T>>>,
-    save_state: Arc<Mutex<save_state::DataWrapper>>,
+    save_state: Arc<save_state::Saver>,
     shared: Arc<Mutex<SharedState>>,
+    registry: Arc<registry::Registry>,
     #[cfg(feature = "esp32")] known_networks: HashMap<String, String>,
-    #[cfg(feature = "esp32")] storage: NvsStorage,
+    storage: Storage,
 ) -> kvarn::host::Host {
     let mut extensions = Extensions::new();
 
@@ -426,6 +421,9 @@ fn create_host<T: VariableOut + Send>(
                 "/set-strength",
                 CorsAllowList::default().allow_all_origins(),
             )
+            .add("/toggle", CorsAllowList::default().allow_all_origins())
+            .add("/adjust", CorsAllowList::default().allow_all_origins())
+            .add("/scale", CorsAllowList::default().allow_all_origins())
             .add(
                 "/set-effect",
                 CorsAllowList::default()
@@ -456,18 +454,17 @@ fn create_host<T: VariableOut + Send>(
             _host,
             _path,
             _addr,
-            move |save: Arc<Mutex<save_state::DataWrapper>>, controller: ControllerSender| {
+            move |save: Arc<save_state::Saver>, controller: ControllerSender| {
+                save.modify(|data| data.mut_schedulers().clear());
                 {
                     controller.send(Command::ClearAllSchedulers);
                 }
-                save.lock().unwrap().get_mut().mut_schedulers().clear();
                 r200()
             }
         ),
     );
 
     let controller = ctl();
-    let save = saved();
     extensions.add_prepare_single(
         "/set-strength",
         prepare!(
@@ -475,14 +472,10 @@ fn create_host<T: VariableOut + Send>(
             host,
             _path,
             _addr,
-            move |save: Arc<Mutex<save_state::DataWrapper>>, controller: ControllerSender| {
+            move |controller: ControllerSender| {
                 match get_query_value(request, "strength").and_then(|value| value.parse().ok()) {
                     Some(f) => {
                         controller.send(Command::Set(Strength::new_clamped(f)));
-                        save.lock()
-                            .unwrap()
-                            .get_mut()
-                            .set_strength(Strength::new_clamped(f));
                     }
                     None => return default_error_response(
                         StatusCode::BAD_REQUEST,
@@ -496,6 +489,79 @@ fn create_host<T: VariableOut + Send>(
         ),
     );
     let controller = ctl();
+    extensions.add_prepare_single(
+        "/toggle",
+        prepare!(
+            _request,
+            _host,
+            _path,
+            _addr,
+            move |controller: ControllerSender| {
+                controller.send(Command::Toggle);
+                // The saver is notified of the change by the controller.
+                r200()
+            }
+        ),
+    );
+    let controller = ctl();
+    extensions.add_prepare_single(
+        "/adjust",
+        prepare!(
+            request,
+            host,
+            _path,
+            _addr,
+            move |controller: ControllerSender| {
+                match get_query_value(request, "delta")
+                    .and_then(|value| value.parse::<f64>().ok())
+                    .filter(|delta| delta.is_finite())
+                {
+                    Some(delta) => controller.send(Command::Adjust(delta)),
+                    None => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some(
+                                "must have query key `delta` with a floating point numeric value.",
+                            ),
+                        )
+                        .await
+                    }
+                }
+                r200()
+            }
+        ),
+    );
+    let controller = ctl();
+    extensions.add_prepare_single(
+        "/scale",
+        prepare!(
+            request,
+            host,
+            _path,
+            _addr,
+            move |controller: ControllerSender| {
+                match get_query_value(request, "factor")
+                    .and_then(|value| value.parse::<f64>().ok())
+                    .filter(|factor| factor.is_finite())
+                {
+                    Some(factor) => controller.send(Command::Scale(factor)),
+                    None => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some(
+                                "must have query key `factor` with a floating point numeric value.",
+                            ),
+                        )
+                        .await
+                    }
+                }
+                r200()
+            }
+        ),
+    );
+    let controller = ctl();
     let save = saved();
     extensions.add_prepare_single(
         "/set-day-time",
@@ -504,7 +570,7 @@ fn create_host<T: VariableOut + Send>(
             host,
             _path,
             _addr,
-            move |save: Arc<Mutex<save_state::DataWrapper>>, controller: ControllerSender| {
+            move |save: Arc<save_state::Saver>, controller: ControllerSender| {
                 let body = match read_body(request).await {
                     Ok(b) => b,
                     Err(_) => {
@@ -524,16 +590,12 @@ fn create_host<T: VariableOut + Send>(
 
                 match command {
                     Some((day, time)) => {
-                        info!("Changed time of {:?} to {:?}", day, time);
-
-                        {
-                            let mut lock = save.lock().unwrap();
-                            let week_scheduler = lock.get_mut().mut_week_scheduler();
-                            *week_scheduler.get_mut(day) =
-                                time.map(|time| time.format(&SECOND_FORMAT).unwrap());
-                        }
+                        let name = day_data
+                            .and_then(|data| data.scheduler)
+                            .unwrap_or_else(|| save.lock().default_week_scheduler.clone());
+                        info!("Changed time of {:?} to {:?} in {:?}", day, time, name);
                         {
-                            controller.send(Command::ChangeDayTimer(day, time));
+                            controller.send(Command::ChangeWeekDayTimer(name, day, time));
                         }
                     }
                     None => {
@@ -559,7 +621,7 @@ fn create_host<T: VariableOut + Send>(
             host,
             _path,
             _addr,
-            move |save: Arc<Mutex<save_state::DataWrapper>>, controller: ControllerSender| {
+            move |save: Arc<save_state::Saver>, controller: ControllerSender| {
                 let body = match read_body(request).await {
                     Ok(b) => b,
                     Err(_) => {
@@ -590,14 +652,11 @@ fn create_host<T: VariableOut + Send>(
 
                 match action.as_deref() {
                     Some("set") => {
-                        save.lock()
-                            .unwrap()
-                            .get_mut()
-                            .mut_week_scheduler()
-                            .transition = datas::TransitionData::from_transition(&transition);
-                        info!("Setting default transition.");
+                        let name = get_query_value(request, "scheduler")
+                            .unwrap_or_else(|| save.lock().default_week_scheduler.clone());
+                        info!("Setting transition of {:?}.", name);
                         {
-                            controller.send(Command::ChangeDayTimerTransition(transition));
+                            controller.send(Command::ChangeWeekTransition(name, transition));
                         }
                     }
                     Some("preview") => {
@@ -621,6 +680,89 @@ fn create_host<T: VariableOut + Send>(
         ),
     );
 
+    let controller = ctl();
+    extensions.add_prepare_single(
+        "/add-week-scheduler",
+        prepare!(
+            request,
+            host,
+            _path,
+            _addr,
+            move |controller: ControllerSender| {
+                let body = match read_body(request).await {
+                    Ok(b) => b,
+                    Err(_) => {
+                        return default_error_response(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            host,
+                            Some("Failed to read request body"),
+                        )
+                        .await
+                    }
+                };
+                let name = match get_query_value(request, "name") {
+                    Some(name) => name,
+                    None => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some("Has to have the query key `name`"),
+                        )
+                        .await
+                    }
+                };
+
+                let scheduler = serde_json::from_slice(&body)
+                    .ok()
+                    .and_then(|data: save_state::WeekSchedulerData| data.to_scheduler());
+
+                match scheduler {
+                    Some(scheduler) => {
+                        controller.send(Command::AddReplaceScheduler(name, Box::new(scheduler)));
+                    }
+                    None => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some("Failed to serialize body"),
+                        )
+                        .await
+                    }
+                }
+
+                r200()
+            }
+        ),
+    );
+
+    let controller = ctl();
+    extensions.add_prepare_single(
+        "/set-default-week-scheduler",
+        prepare!(
+            request,
+            host,
+            _path,
+            _addr,
+            move |controller: ControllerSender| {
+                match get_query_value(request, "name") {
+                    Some(name) => {
+                        controller.send(Command::SetDefaultWeekScheduler(name));
+                    }
+                    None => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some("Has to have the query key `name`"),
+                        )
+                        .await
+                    }
+                }
+
+                r200()
+            }
+        ),
+    );
+
     let local_state = state();
     extensions.add_prepare_single(
         "/get-state",
@@ -637,6 +779,7 @@ fn create_host<T: VariableOut + Send>(
 
     let controller = ctl();
     let save = saved();
+    let add_registry = Arc::clone(&registry);
     extensions.add_prepare_single(
         "/add-scheduler",
         prepare!(
@@ -644,7 +787,9 @@ fn create_host<T: VariableOut + Send>(
             host,
             _path,
             _addr,
-            move |save: Arc<Mutex<save_state::DataWrapper>>, controller: ControllerSender| {
+            move |save: Arc<save_state::Saver>,
+                  controller: ControllerSender,
+                  add_registry: Arc<registry::Registry>| {
                 let body = match read_body(request).await {
                     Ok(b) => b,
                     Err(_) => {
@@ -657,24 +802,34 @@ fn create_host<T: VariableOut + Send>(
                     }
                 };
 
-                let data = serde_json::from_slice(&body).ok();
-                let command = data.and_then(|data: datas::AddSchedulerData| {
-                    let data_clone = data.clone();
-                    data.into_command(false).map(|cmd| (data_clone, cmd))
-                });
+                let mut data: datas::AddSchedulerData = match serde_json::from_slice(&body) {
+                    Ok(data) => data,
+                    Err(_) => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some("Failed to serialize body"),
+                        )
+                        .await
+                    }
+                };
 
-                match command {
-                    Some((data, cmd)) => {
+                match data.to_command(false, add_registry) {
+                    Ok(cmd) => {
+                        save.modify(|saved| {
+                            let schedulers = saved.mut_schedulers();
+                            schedulers.retain(|scheduler| scheduler.name != data.name);
+                            schedulers.push(data);
+                        });
                         {
                             controller.send(cmd);
                         }
-                        save.lock().unwrap().get_mut().mut_schedulers().push(data);
                     }
-                    None => {
+                    Err(err) => {
                         return default_error_response(
                             StatusCode::BAD_REQUEST,
                             host,
-                            Some("Failed to serialize body"),
+                            Some(&err.to_string()),
                         )
                         .await
                     }
@@ -693,25 +848,21 @@ fn create_host<T: VariableOut + Send>(
         >| {
             let mut now = scheduler::LazyNow::new();
 
-            let mut schedulers: Vec<(datas::SchedulerData, Option<Duration>)> = local_state
-                .lock()
-                .unwrap()
+            let lock = local_state.lock().unwrap();
+            let mut schedulers: Vec<(datas::SchedulerData, Option<Duration>)> = lock
                 .ref_schedulers()
-                .iter()
-                .map(|(name, scheduler)| {
+                .keys()
+                .map(|name| {
                     (
-                        datas::SchedulerData::from_scheduler(
-                            scheduler.as_ref(),
-                            name.to_string(),
-                            &mut now,
-                        ),
-                        match scheduler.get_next(&mut now) {
+                        datas::SchedulerData::from_state(&lock, name.to_string(), &mut now),
+                        match lock.get_next(name, &mut now) {
                             Next::At(dur, _) => Some((dur - now.now()).unsigned_abs()),
                             Next::Unknown => None,
                         },
                     )
                 })
                 .collect();
+            drop(lock);
 
             schedulers.sort_by(|(_, d1), (_, d2)| d1.cmp(d2));
 
@@ -725,6 +876,90 @@ fn create_host<T: VariableOut + Send>(
         }),
     );
 
+    let kinds_registry = Arc::clone(&registry);
+    extensions.add_prepare_single(
+        "/get-scheduler-kinds",
+        prepare!(_request, _host, _path, _addr, move |kinds_registry: Arc<
+            registry::Registry,
+        >| {
+            let kinds: BTreeMap<&str, &[registry::Parameter]> = kinds_registry.iter().collect();
+
+            let mut buffer = utils::WriteableBytes::with_capacity(1024);
+            serde_json::to_writer(&mut buffer, &kinds).expect("failed to write to Vec?");
+
+            FatResponse::no_cache(Response::new(buffer.into_inner().freeze()))
+        }),
+    );
+
+    let local_state = state();
+    extensions.add_prepare_single(
+        "/get-timeline",
+        prepare!(
+            request,
+            host,
+            _path,
+            _addr,
+            move |local_state: Arc<Mutex<SharedState>>| {
+                let mut dates = [None, None];
+                for (date, key) in dates.iter_mut().zip(["from", "to"]) {
+                    if let Some(value) = get_query_value(request, key) {
+                        match parse_date_time(&value) {
+                            Some(date_time) => *date = Some(date_time),
+                            None => {
+                                return default_error_response(
+                                    StatusCode::BAD_REQUEST,
+                                    host,
+                                    Some("`from` and `to` have to be a date (2022-01-31) or a date and time (2022-01-31 07:00:00)"),
+                                )
+                                .await
+                            }
+                        }
+                    }
+                }
+                let [from, to] = dates;
+                let from = from.unwrap_or_else(get_now);
+                let count = match get_query_value(request, "count").map(|count| count.parse()) {
+                    Some(Ok(count)) => usize::min(count, MAX_TIMELINE_ENTRIES),
+                    Some(Err(_)) => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some("`count` has to be a number"),
+                        )
+                        .await
+                    }
+                    None if to.is_some() => MAX_TIMELINE_ENTRIES,
+                    None => 10,
+                };
+
+                let lock = local_state.lock().unwrap();
+                let mut occurrences: Vec<(time::OffsetDateTime, &str, Command)> = Vec::new();
+                for name in lock.ref_schedulers().keys() {
+                    occurrences.extend(
+                        lock.occurrences(name, from)
+                            .take_while(|(date_time, _)| !matches!(to, Some(to) if *date_time > to))
+                            .take(count)
+                            .map(|(date_time, command)| (date_time, name.as_str(), command)),
+                    );
+                }
+                occurrences.sort_by_key(|(date_time, _, _)| *date_time);
+                occurrences.truncate(count);
+                let timeline: Vec<datas::TimelineEntry> = occurrences
+                    .into_iter()
+                    .map(|(date_time, name, command)| {
+                        datas::TimelineEntry::new(date_time, name.to_string(), &command)
+                    })
+                    .collect();
+                drop(lock);
+
+                let mut buffer = utils::WriteableBytes::with_capacity(1024);
+                serde_json::to_writer(&mut buffer, &timeline).expect("failed to write to Vec?");
+
+                FatResponse::no_cache(Response::new(buffer.into_inner().freeze()))
+            }
+        ),
+    );
+
     let controller = ctl();
     extensions.add_prepare_single(
         "/remove-scheduler",
@@ -736,10 +971,7 @@ fn create_host<T: VariableOut + Send>(
             move |controller: ControllerSender| {
                 match get_query_value(request, "name") {
                     Some(s) => {
-                        {
-                            controller.send(Command::RemoveScheduler(s));
-                        }
-                        // We don't save since we check if internal schedulers disappeared.
+                        controller.send(Command::RemoveScheduler(s));
                     }
                     None => {
                         return default_error_response(
@@ -757,16 +989,552 @@ fn create_host<T: VariableOut + Send>(
     );
     let controller = ctl();
     extensions.add_prepare_single(
-        "/set-effect",
+        "/set-scheduler-enabled",
         prepare!(
             request,
             host,
             _path,
             _addr,
             move |controller: ControllerSender| {
-                let body = match read_body(request).await {
-                    Ok(b) => b,
-                    Err(_) => {
+                let name = get_query_value(request, "name");
+                let enabled = get_query_value(request, "enabled").and_then(|v| v.parse().ok());
+                let until = match get_query_value(request, "until") {
+                    Some(until) => match parse_date_time(&until) {
+                        Some(until) => Some(until),
+                        None => {
+                            return default_error_response(
+                                StatusCode::BAD_REQUEST,
+                                host,
+                                Some("`until` has to be a date (2022-01-31) or a date and time (2022-01-31 07:00:00)"),
+                            )
+                            .await
+                        }
+                    },
+                    None => None,
+                };
+
+                match (name, enabled) {
+                    (Some(name), Some(true)) => {
+                        controller.send(Command::EnableScheduler(name));
+                    }
+                    (Some(name), Some(false)) => {
+                        controller.send(Command::DisableScheduler(name, until));
+                    }
+                    _ => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some("Has to have the query keys `name` and `enabled` (true or false)"),
+                        )
+                        .await
+                    }
+                }
+                // The saver is notified of the change by the controller.
+
+                r200()
+            }
+        ),
+    );
+    let controller = ctl();
+    extensions.add_prepare_single(
+        "/set-effect",
+        prepare!(
+            request,
+            host,
+            _path,
+            _addr,
+            move |controller: ControllerSender| {
+                let body = match read_body(request).await {
+                    Ok(b) => b,
+                    Err(_) => {
+                        return default_error_response(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            host,
+                            Some("Failed to read request body"),
+                        )
+                        .await
+                    }
+                };
+
+                let data: Option<datas::EffectData> = serde_json::from_slice(&body).ok();
+                let command = data.and_then(datas::EffectData::into_command);
+
+                match command {
+                    Some(cmd) => {
+                        controller.send(cmd);
+                    }
+                    None => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some("Failed to serialize body"),
+                        )
+                        .await
+                    }
+                }
+
+                r200()
+            }
+        ),
+    );
+    let controller = ctl();
+    extensions.add_prepare_single(
+        "/set-scene",
+        prepare!(
+            request,
+            host,
+            _path,
+            _addr,
+            move |controller: ControllerSender| {
+                let body = match read_body(request).await {
+                    Ok(b) => b,
+                    Err(_) => {
+                        return default_error_response(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            host,
+                            Some("Failed to read request body"),
+                        )
+                        .await
+                    }
+                };
+                let name = match get_query_value(request, "name") {
+                    Some(name) => name,
+                    None => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some("Has to have the query key `name`"),
+                        )
+                        .await
+                    }
+                };
+
+                let scene = serde_json::from_slice(&body)
+                    .ok()
+                    .and_then(|data: datas::SceneData| data.to_scene());
+
+                match scene {
+                    Some(scene) => {
+                        controller.send(Command::SetScene(name, scene));
+                    }
+                    None => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some("Failed to serialize body"),
+                        )
+                        .await
+                    }
+                }
+
+                r200()
+            }
+        ),
+    );
+    let controller = ctl();
+    extensions.add_prepare_single(
+        "/remove-scene",
+        prepare!(
+            request,
+            host,
+            _path,
+            _addr,
+            move |controller: ControllerSender| {
+                match get_query_value(request, "name") {
+                    Some(name) => {
+                        controller.send(Command::RemoveScene(name));
+                    }
+                    None => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some("Has to have the query key `name`"),
+                        )
+                        .await
+                    }
+                }
+
+                r200()
+            }
+        ),
+    );
+    let controller = ctl();
+    extensions.add_prepare_single(
+        "/activate-scene",
+        prepare!(
+            request,
+            host,
+            _path,
+            _addr,
+            move |controller: ControllerSender| {
+                match get_query_value(request, "name") {
+                    Some(name) => {
+                        controller.send(Command::ActivateScene(name));
+                    }
+                    None => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some("Has to have the query key `name`"),
+                        )
+                        .await
+                    }
+                }
+
+                r200()
+            }
+        ),
+    );
+    let save = saved();
+    extensions.add_prepare_single(
+        "/get-scenes",
+        prepare!(_request, _host, _path, _addr, move |save: Arc<
+            save_state::Saver,
+        >| {
+            let mut buffer = utils::WriteableBytes::with_capacity(1024);
+            serde_json::to_writer(&mut buffer, &save.lock().scenes)
+                .expect("failed to write to Vec?");
+
+            FatResponse::no_cache(Response::new(buffer.into_inner().freeze()))
+        }),
+    );
+
+    let save = saved();
+    extensions.add_prepare_single(
+        "/set-power-on",
+        prepare!(request, host, _path, _addr, move |save: Arc<
+            save_state::Saver,
+        >| {
+            let body = match read_body(request).await {
+                Ok(b) => b,
+                Err(_) => {
+                    return default_error_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        host,
+                        Some("Failed to read request body"),
+                    )
+                    .await
+                }
+            };
+
+            let data = serde_json::from_slice(&body)
+                .ok()
+                .filter(|data: &datas::PowerOnData| data.validate().is_some());
+            match data {
+                // Used the next time the daemon starts.
+                Some(data) => save.modify(|saved| saved.power_on = data),
+                None => {
+                    return default_error_response(
+                        StatusCode::BAD_REQUEST,
+                        host,
+                        Some("Failed to serialize body"),
+                    )
+                    .await
+                }
+            }
+
+            r200()
+        }),
+    );
+    let save = saved();
+    extensions.add_prepare_single(
+        "/get-power-on",
+        prepare!(_request, _host, _path, _addr, move |save: Arc<
+            save_state::Saver,
+        >| {
+            let mut buffer = utils::WriteableBytes::with_capacity(1024);
+            serde_json::to_writer(&mut buffer, &save.lock().power_on)
+                .expect("failed to write to Vec?");
+
+            FatResponse::no_cache(Response::new(buffer.into_inner().freeze()))
+        }),
+    );
+
+    let local_state = state();
+    extensions.add_prepare_single(
+        "/get-output",
+        prepare!(_request, _host, _path, _addr, move |local_state: Arc<
+            Mutex<SharedState>,
+        >| {
+            let data =
+                datas::OutputData::from_capabilities(local_state.lock().unwrap().get_output());
+            let mut buffer = utils::WriteableBytes::with_capacity(128);
+            serde_json::to_writer(&mut buffer, &data).expect("failed to write to Vec?");
+
+            FatResponse::no_cache(Response::new(buffer.into_inner().freeze()))
+        }),
+    );
+
+    let controller = ctl();
+    let local_state = state();
+    extensions.add_prepare_single(
+        "/set-output",
+        prepare!(
+            request,
+            host,
+            _path,
+            _addr,
+            move |local_state: Arc<Mutex<SharedState>>, controller: ControllerSender| {
+                let frequency = get_query_value(request, "frequency").map(|v| v.parse::<f64>());
+                let resolution = get_query_value(request, "resolution").map(|v| v.parse::<u32>());
+                let (frequency, resolution) = match (frequency, resolution) {
+                    (None, None) | (Some(Err(_)), _) | (_, Some(Err(_))) => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some("must have query key `frequency` (Hz) and/or `resolution` (bits)"),
+                        )
+                        .await
+                    }
+                    (frequency, resolution) => (
+                        frequency.and_then(Result::ok),
+                        resolution.and_then(Result::ok),
+                    ),
+                };
+
+                // check first, as the controller can't respond
+                let result = {
+                    let lock = local_state.lock().unwrap();
+                    let capabilities = lock.get_output();
+                    frequency
+                        .map_or(Ok(()), |f| capabilities.check_frequency(f))
+                        .map_err(|err| format!("frequency {}", err))
+                        .and_then(|()| {
+                            resolution
+                                .map_or(Ok(()), |bits| capabilities.check_resolution(bits))
+                                .map_err(|err| format!("resolution {}", err))
+                        })
+                };
+                if let Err(err) = result {
+                    return default_error_response(StatusCode::BAD_REQUEST, host, Some(&err)).await;
+                }
+
+                // The frequency and resolution aren't saved; set them in the config to keep them.
+                if let Some(frequency) = frequency {
+                    controller.send(Command::SetFrequency(frequency));
+                }
+                if let Some(resolution) = resolution {
+                    controller.send(Command::SetResolution(resolution));
+                }
+                r200()
+            }
+        ),
+    );
+
+    let save = saved();
+    extensions.add_prepare_single(
+        "/export-config",
+        prepare!(request, host, _path, _addr, move |save: Arc<
+            save_state::Saver,
+        >| {
+            #[cfg(not(feature = "auto-tz"))]
+            let timezone = httpwm::get_timezone()
+                .map(|timezone| timezone.format(&httpwm::env_timezone::TZ_FORMAT).unwrap());
+            #[cfg(feature = "auto-tz")]
+            let timezone = None;
+            let export = save.lock().export(timezone);
+
+            let body = match get_query_value(request, "format").as_deref() {
+                None | Some("json") => {
+                    serde_json::to_vec_pretty(&export).expect("failed to write to Vec?")
+                }
+                Some("ron") => match export.to_ron() {
+                    Ok(ron) => ron.into_bytes(),
+                    Err(err) => {
+                        error!("Failed to export configuration: {}", err);
+                        return default_error_response(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            host,
+                            None,
+                        )
+                        .await;
+                    }
+                },
+                Some(_) => {
+                    return default_error_response(
+                        StatusCode::BAD_REQUEST,
+                        host,
+                        Some("`format` has to be `json` or `ron`"),
+                    )
+                    .await
+                }
+            };
+
+            FatResponse::no_cache(Response::new(Bytes::from(body)))
+        }),
+    );
+
+    let controller = ctl();
+    let save = saved();
+    let import_registry = Arc::clone(&registry);
+    let import_storage = Arc::clone(&storage);
+    extensions.add_prepare_single(
+        "/import-config",
+        prepare!(
+            request,
+            host,
+            _path,
+            _addr,
+            move |save: Arc<save_state::Saver>,
+                  controller: ControllerSender,
+                  import_registry: Arc<registry::Registry>,
+                  import_storage: Storage| {
+                let body = match read_body(request).await {
+                    Ok(b) => b,
+                    Err(_) => {
+                        return default_error_response(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            host,
+                            Some("Failed to read request body"),
+                        )
+                        .await
+                    }
+                };
+
+                let mode = match get_query_value(request, "mode").as_deref() {
+                    None => Some(save_state::ImportMode::Merge),
+                    Some(mode) => mode.parse().ok(),
+                };
+                let dry_run = match get_query_value(request, "dry-run") {
+                    None => Some(false),
+                    Some(dry_run) => dry_run.parse().ok(),
+                };
+                let (mode, dry_run) = match mode.zip(dry_run) {
+                    Some(options) => options,
+                    None => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some("`mode` has to be `merge` or `replace` and `dry-run` a bool"),
+                        )
+                        .await
+                    }
+                };
+
+                let export = match get_query_value(request, "format").as_deref() {
+                    None | Some("json") => serde_json::from_slice(&body).map_err(|e| e.to_string()),
+                    Some("ron") => save_state::Export::from_ron(&body).map_err(|e| e.to_string()),
+                    Some(_) => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some("`format` has to be `json` or `ron`"),
+                        )
+                        .await
+                    }
+                };
+                let export: save_state::Export = match export {
+                    Ok(export) => export,
+                    Err(err) => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some(&format!("Failed to serialize body: {}", err)),
+                        )
+                        .await
+                    }
+                };
+
+                // bind the result, so the lock is released before awaiting
+                let import = export.prepare(&save.lock(), mode, import_registry);
+                let import = match import {
+                    Ok(import) => import,
+                    Err(err) => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some(&err.to_string()),
+                        )
+                        .await
+                    }
+                };
+
+                let mut buffer = utils::WriteableBytes::with_capacity(512);
+                if dry_run {
+                    serde_json::to_writer(&mut buffer, import.report())
+                        .expect("failed to write to Vec?");
+                } else {
+                    info!("Importing configuration ({:?}).", import.mode());
+                    #[cfg(not(feature = "auto-tz"))]
+                    if let Some(timezone) = import.timezone() {
+                        // validated by `prepare`
+                        let _ = httpwm::env_timezone::try_set_timezone(timezone);
+                        let mut lock = import_storage.lock().unwrap();
+                        if let Err(err) = lock.put(TIMEZONE_KEY, timezone.as_bytes()) {
+                            error!("Failed to write timezone: {}", err);
+                        }
+                    }
+                    #[cfg(feature = "auto-tz")]
+                    let _ = import_storage;
+                    let report = import.apply(save, controller);
+                    controller.send(Command::UpdateWake);
+                    serde_json::to_writer(&mut buffer, &report).expect("failed to write to Vec?");
+                }
+
+                FatResponse::no_cache(Response::new(buffer.into_inner().freeze()))
+            }
+        ),
+    );
+
+    let controller = ctl();
+    extensions.add_prepare_single(
+        "/sleep-timer",
+        prepare!(
+            request,
+            host,
+            _path,
+            _addr,
+            move |controller: ControllerSender| {
+                fn seconds(request: &FatRequest, key: &str) -> Option<Duration> {
+                    // a year
+                    const MAX: f64 = 60. * 60. * 24. * 365.;
+                    get_query_value(request, key)
+                        .and_then(|value| value.parse::<f64>().ok())
+                        .filter(|seconds| (0.0..=MAX).contains(seconds))
+                        .map(Duration::from_secs_f64)
+                }
+
+                let action = get_query_value(request, "action");
+                let time = seconds(request, "time");
+                let fade = seconds(request, "fade").unwrap_or(Duration::ZERO);
+
+                let command = match (action.as_deref(), time) {
+                    (Some("set"), Some(time)) => {
+                        Command::SetSleepTimer(get_now() + time, fade.min(time))
+                    }
+                    (Some("extend"), Some(time)) => Command::ExtendSleepTimer(time),
+                    (Some("cancel"), _) => Command::CancelSleepTimer,
+                    _ => {
+                        return default_error_response(
+                            StatusCode::BAD_REQUEST,
+                            host,
+                            Some("Has to have a query key `action` (set, extend or cancel). `set` and `extend` also need `time` (and optionally `fade`) in seconds"),
+                        )
+                        .await
+                    }
+                };
+                {
+                    controller.send(command);
+                }
+                // The saver is notified of the change by the controller.
+
+                r200()
+            }
+        ),
+    );
+
+    let controller = ctl();
+    extensions.add_prepare_single(
+        "/set-catch-up",
+        prepare!(
+            request,
+            host,
+            _path,
+            _addr,
+            move |controller: ControllerSender| {
+                let body = match read_body(request).await {
+                    Ok(b) => b,
+                    Err(_) => {
                         return default_error_response(
                             StatusCode::INTERNAL_SERVER_ERROR,
                             host,
@@ -776,14 +1544,9 @@ fn create_host<T: VariableOut + Send>(
                     }
                 };
 
-                let data: Option<datas::EffectData> = serde_json::from_slice(&body).ok();
-                let command = data.and_then(datas::EffectData::into_command);
-
-                match command {
-                    Some(cmd) => {
-                        controller.send(cmd);
-                    }
-                    None => {
+                let data: datas::CatchUpData = match serde_json::from_slice(&body) {
+                    Ok(data) => data,
+                    Err(_) => {
                         return default_error_response(
                             StatusCode::BAD_REQUEST,
                             host,
@@ -791,16 +1554,20 @@ fn create_host<T: VariableOut + Send>(
                         )
                         .await
                     }
+                };
+
+                {
+                    controller.send(Command::SetCatchUp(data.to_catch_up()));
                 }
 
                 r200()
             }
         ),
     );
-    #[cfg(feature = "esp32")]
+    #[cfg(not(feature = "auto-tz"))]
     {
         let controller = ctl();
-        let nvs = storage.clone();
+        let storage = Arc::clone(&storage);
         extensions.add_prepare_single(
             "/set-timezone",
             prepare!(
@@ -808,7 +1575,7 @@ fn create_host<T: VariableOut + Send>(
                 host,
                 _path,
                 _addr,
-                move |nvs: NvsStorage, controller: ControllerSender| {
+                move |storage: Storage, controller: ControllerSender| {
                     match get_query_value(request, "timezone") {
                         Some(timezone) => {
                             if httpwm::env_timezone::try_set_timezone(&timezone).is_err() {
@@ -820,12 +1587,10 @@ fn create_host<T: VariableOut + Send>(
                             .await
                             } else {
                                 controller.send(Command::UpdateWake);
-                                let mut lock = nvs.lock().unwrap();
-                                if lock
-                                    .put_raw("timezone.txt", timezone.trim().as_bytes())
-                                    .is_err()
+                                let mut lock = storage.lock().unwrap();
+                                if let Err(err) = lock.put(TIMEZONE_KEY, timezone.trim().as_bytes())
                                 {
-                                    error!("Failed to write timezone");
+                                    error!("Failed to write timezone: {}", err);
                                 }
                                 r200()
                             }
@@ -854,7 +1619,7 @@ fn create_host<T: VariableOut + Send>(
     // wifi
     #[cfg(feature = "esp32")]
     {
-        let nvs = storage.clone();
+        let storage = Arc::clone(&storage);
         type Networks = Arc<Mutex<HashMap<String, String>>>;
         let networks = Arc::new(Mutex::new(known_networks));
         let get_networks = Arc::clone(&networks);
@@ -869,27 +1634,35 @@ fn create_host<T: VariableOut + Send>(
         );
         extensions.add_prepare_single(
             "/set-wifi",
-            prepare!(req, host, _path, _addr, move |networks: Networks, nvs: NvsStorage| {
-                let Ok(data) = read_body(req).await else {
-                    return default_error_response(StatusCode::BAD_REQUEST, host, None).await
-                };
-                let Ok(parsed_networks) = serde_json::from_slice::<'_, HashMap<String, String>>(&data) else {
-                    return default_error_response(StatusCode::BAD_REQUEST, host, None).await
-                };
-                let ser = ron::to_string(&parsed_networks).unwrap();
-                {
-                    let mut lock = networks.lock().unwrap();
-                    *lock = parsed_networks;
-                }
-                {
-                    let mut lock = nvs.lock().unwrap();
-                    if lock.put_raw("networks.ron", ser.as_bytes()).is_err() {
-                        error!("Failed to write networks.ron: {ser}");
+            prepare!(
+                req,
+                host,
+                _path,
+                _addr,
+                move |networks: Networks, storage: Storage| {
+                    let Ok(data) = read_body(req).await else {
+                        return default_error_response(StatusCode::BAD_REQUEST, host, None).await;
+                    };
+                    let Ok(parsed_networks) =
+                        serde_json::from_slice::<'_, HashMap<String, String>>(&data)
+                    else {
+                        return default_error_response(StatusCode::BAD_REQUEST, host, None).await;
+                    };
+                    let ser = ron::to_string(&parsed_networks).unwrap();
+                    {
+                        let mut lock = networks.lock().unwrap();
+                        *lock = parsed_networks;
+                    }
+                    {
+                        let mut lock = storage.lock().unwrap();
+                        if let Err(err) = lock.put(NETWORKS_KEY, ser.as_bytes()) {
+                            error!("Failed to write networks: {err}: {ser}");
+                        }
                     }
-                }
 
-                r200()
-            }),
+                    r200()
+                }
+            ),
         );
     }
     #[cfg(feature = "esp32")]
@@ -910,7 +1683,7 @@ fn create_host<T: VariableOut + Send>(
 
     let mut localhost = Host::unsecure(
         "localhost",
-        PathBuf::from("web"),
+        config.web_root.clone(),
         extensions,
         host::Options::new(),
     );
@@ -923,32 +1696,32 @@ fn create_host<T: VariableOut + Send>(
 // #[cfg(all(feature = "web", not(feature = "esp32")))]
 #[cfg(feature = "web")]
 fn create_server<T: VariableOut + Send>(
+    config: &config::Config,
     controller: Arc<Mutex<Controller<T>>>,
-    save_state: Arc<Mutex<save_state::DataWrapper>>,
+    save_state: Arc<save_state::Saver>,
     shared: Arc<Mutex<SharedState>>,
+    registry: Arc<registry::Registry>,
     #[cfg(feature = "esp32")] known_networks: HashMap<String, String>,
-    #[cfg(feature = "esp32")] storage: NvsStorage,
+    storage: Storage,
 ) -> kvarn::RunConfig {
-    #[cfg(feature = "esp32")]
-    let default_port = 80;
-    #[cfg(not(feature = "esp32"))]
-    let default_port = 8080;
-    let port = std::env::args()
-        .nth(1)
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(default_port);
-
     let localhost = create_host(
+        config,
         controller,
         save_state,
         shared,
+        registry,
         #[cfg(feature = "esp32")]
         known_networks,
-        #[cfg(feature = "esp32")]
         storage,
     );
     let hosts = HostCollection::builder().default(localhost).build();
-    RunConfig::new().bind(PortDescriptor::new(port, hosts).ipv4_only())
+    let port = PortDescriptor::new(config.port, hosts);
+    let port = match config.bind {
+        config::Bind::Ipv4 => port.ipv4_only(),
+        config::Bind::Ipv6 => port.ipv6_only(),
+        config::Bind::Both => port,
+    };
+    RunConfig::new().bind(port)
 }
 // #[cfg(feature = "esp32")]
 // fn create_server(
@@ -1049,11 +1822,8 @@ fn create_server<T: VariableOut + Send>(
 // registry
 // }
 
-pub fn parse_time(string: &str) -> Option<time::Time> {
-    time::Time::parse(string, &SECOND_FORMAT)
-        .or_else(|_| time::Time::parse(string, &MINUTE_FORMAT))
-        .ok()
-}
+/// The maximum number of entries `/get-timeline` returns.
+const MAX_TIMELINE_ENTRIES: usize = 1000;
 
 #[cfg(feature = "esp32")]
 fn wifi(
@@ -1223,539 +1993,1069 @@ mod test_output {
         tx
     }
 }
-/// Quite nasty code
-pub mod save_state {
-    use super::*;
-
-    #[derive(Debug, Serialize, Deserialize)]
-    pub struct WeekSchedulerData {
-        pub mon: Option<String>,
-        pub tue: Option<String>,
-        pub wed: Option<String>,
-        pub thu: Option<String>,
-        pub fri: Option<String>,
-        pub sat: Option<String>,
-        pub sun: Option<String>,
-        pub transition: datas::TransitionData,
-    }
-    impl WeekSchedulerData {
-        pub fn get_mut(&mut self, day: Weekday) -> &mut Option<String> {
-            match day {
-                Weekday::Mon => &mut self.mon,
-                Weekday::Tue => &mut self.tue,
-                Weekday::Wed => &mut self.wed,
-                Weekday::Thu => &mut self.thu,
-                Weekday::Fri => &mut self.fri,
-                Weekday::Sat => &mut self.sat,
-                Weekday::Sun => &mut self.sun,
-            }
-        }
-        pub fn from_scheduler(scheduler: &WeekScheduler) -> Self {
-            macro_rules! fmt_time {
-                ($e:expr) => {
-                    $e.map(|time| time.format(&SECOND_FORMAT).unwrap())
-                };
-            }
-
-            WeekSchedulerData {
-                mon: fmt_time!(scheduler.mon),
-                tue: fmt_time!(scheduler.tue),
-                wed: fmt_time!(scheduler.wed),
-                thu: fmt_time!(scheduler.thu),
-                fri: fmt_time!(scheduler.fri),
-                sat: fmt_time!(scheduler.sat),
-                sun: fmt_time!(scheduler.sun),
-                transition: datas::TransitionData::from_transition(&scheduler.transition),
-            }
-        }
-        pub fn to_scheduler(&self) -> Option<WeekScheduler> {
-            macro_rules! fmt_time {
-                ($e:expr) => {
-                    match $e.as_ref() {
-                        Some(time) => Some(time::Time::parse(time.as_str(), SECOND_FORMAT).ok()?),
-                        None => None,
-                    }
-                };
-            }
 
-            let mut scheduler = WeekScheduler::empty(self.transition.to_transition()?);
+/// The configuration of the daemon, read from a file, the environment and the command line.
+mod config {
+    use httpwm::{datas, parse_time, Transition};
+    use serde::{Deserialize, Serialize};
+    use std::convert::TryInto;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+
+    /// Read if it exists and no other file is given.
+    pub const DEFAULT_PATH: &str = "httpwmd.ron";
+    /// The environment variable with the path of the config file.
+    pub const PATH_VAR: &str = "HTTPWM_CONFIG";
+    /// The options which can be set on the command line (`--port 80`)
+    /// and in the environment (`HTTPWM_PORT=80`).
+    pub const OPTIONS: &[&str] = &[
+        "port",
+        "bind",
+        "web-root",
+        "data-dir",
+        "outputs",
+        "sysfs-root",
+        "fan-source",
+        "fan-curve",
+        "default-time",
+        "day-transition",
+        "power-on",
+    ];
+    pub const USAGE: &str = "\
+Usage: httpwmd [OPTIONS] [PORT]
+
+Options are read from the config file, the environment (e.g. HTTPWM_PORT)
+and the command line, where the latter take priority.
+
+    --config <PATH>         the config file [default: httpwmd.ron, if it exists]
+    --port <PORT>           the port to listen on
+    --bind <BIND>           the IP versions to listen on: ipv4, ipv6 or both
+    --web-root <PATH>       the directory to serve the web interface from
+    --data-dir <PATH>       the directory to save the state in
+    --outputs <OUTPUTS>     the outputs, separated by commas. `hardware:CHANNEL` or
+                            `software:PIN` on the Raspberry Pi, `sysfs:CHIP:CHANNEL` on
+                            other Linux boards, optionally followed by `:FREQUENCY` and
+                            `:inverse`, e.g. `hardware:0,software:17:200`.
+                            `led:NAME` and `backlight:NAME` for LEDs and displays.
+                            `ledc:FREQUENCY:BITS` on the esp32 (GPIO 18), e.g. `ledc:25000:10`.
+                            Prefix an output with `servo:` to drive a servo or ESC with it.
+    --sysfs-root <PATH>     where the sysfs device classes are [default: /sys/class]
+    --fan-source <SOURCE>   drive the outputs by the temperature in a file (in millidegrees,
+                            e.g. /sys/class/thermal/thermal_zone0/temp) or printed by
+                            `cmd:COMMAND` (in degrees, e.g. `cmd:vcgencmd measure_temp`)
+                            as well as the schedules
+    --fan-curve <CURVE>     the strength of the fan by the temperature, as TEMP:STRENGTH
+                            points separated by commas, e.g. `45:0,50:0.3,70:1`
+    --default-time <TIME>   the time the lights turn on every day, if nothing is saved
+    --day-transition <RON>  the transition of the lights every day, if nothing is saved, e.g.
+                            `(from: 0.0, to: 1.0, time: 900.0, interpolation: \"sine\", extras: [])`
+    --power-on <RON>        what to do when the daemon starts, if nothing is saved, e.g.
+                            `(level: (mode: \"restore\", fade: 2.0))`
+    --print-config          print the resolved config and exit
+    --help                  print this message and exit";
+
+    /// What the daemon should do.
+    pub enum Action {
+        Run(Config),
+        PrintConfig(Config),
+        Help,
+    }
 
-            scheduler.mon = fmt_time!(self.mon);
-            scheduler.tue = fmt_time!(self.tue);
-            scheduler.wed = fmt_time!(self.wed);
-            scheduler.thu = fmt_time!(self.thu);
-            scheduler.fri = fmt_time!(self.fri);
-            scheduler.sat = fmt_time!(self.sat);
-            scheduler.sun = fmt_time!(self.sun);
-            Some(scheduler)
-        }
+    /// The IP versions to listen on.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum Bind {
+        Ipv4,
+        Ipv6,
+        Both,
     }
-    pub struct DataWrapper(Data, bool);
-    impl DataWrapper {
-        pub fn new(data: Data) -> Self {
-            Self(data, false)
-        }
-        pub fn get_ref(&self) -> &Data {
-            &self.0
-        }
-        /// Returns mutable reference to inner [`Data`].
-        /// Sets internal `save` bool true.
-        pub fn get_mut(&mut self) -> &mut Data {
-            self.1 = true;
-            &mut self.0
-        }
-        /// Will not signal that the data has been changed. Use with caution.
-        pub fn no_save_mut(&mut self) -> &mut Data {
-            &mut self.0
+    impl FromStr for Bind {
+        type Err = ();
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            Ok(match s {
+                "ipv4" => Self::Ipv4,
+                "ipv6" => Self::Ipv6,
+                "both" => Self::Both,
+                _ => return Err(()),
+            })
         }
-        pub fn save(&mut self) -> bool {
-            let save = self.1;
-            self.1 = false;
-            save
+    }
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum Polarity {
+        Normal,
+        Inverse,
+    }
+    impl FromStr for Polarity {
+        type Err = ();
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            Ok(match s {
+                "normal" => Self::Normal,
+                "inverse" => Self::Inverse,
+                _ => return Err(()),
+            })
         }
     }
 
-    #[derive(Debug, Serialize, Deserialize)]
-    pub struct Data {
-        pub strength: Option<f64>,
-        pub schedulers: Vec<datas::AddSchedulerData>,
-        pub week_scheduler: Option<WeekSchedulerData>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        pub current_transition: Option<datas::TransitionData>,
+    fn default_frequency() -> f64 {
+        1000.0
+    }
+    /// An output of the Raspberry Pi or a Linux board.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+    pub enum OutputConfig {
+        /// A hardware PWM channel, `0` or `1`.
+        Hardware {
+            channel: u8,
+            /// In Hz.
+            #[serde(default = "default_frequency")]
+            frequency: f64,
+            #[serde(default = "default_polarity")]
+            polarity: Polarity,
+        },
+        /// Software PWM on a GPIO pin, by BCM number.
+        Software {
+            pin: u8,
+            /// In Hz.
+            #[serde(default = "default_frequency")]
+            frequency: f64,
+            #[serde(default = "default_polarity")]
+            polarity: Polarity,
+        },
+        /// A channel of a PWM chip in `pwm` of [`Config::sysfs_root`].
+        Sysfs {
+            chip: u32,
+            channel: u32,
+            /// In Hz.
+            #[serde(default = "default_frequency")]
+            frequency: f64,
+            #[serde(default = "default_polarity")]
+            polarity: Polarity,
+        },
+        /// An LED in `leds` of [`Config::sysfs_root`], e.g. `led0`.
+        Led { name: String },
+        /// A display backlight in `backlight` of [`Config::sysfs_root`], e.g. `rpi_backlight`.
+        Backlight { name: String },
+        /// A hobby servo or ESC on another output. See [`httpwm::Servo`].
+        Servo {
+            output: Box<OutputConfig>,
+            /// The pulse at strength 0, in µs.
+            #[serde(default = "default_servo_min")]
+            min: u64,
+            /// The pulse at strength 1, in µs.
+            #[serde(default = "default_servo_max")]
+            max: u64,
+            #[serde(default)]
+            reversed: bool,
+        },
+        /// The LEDC channel 0 of the esp32, on GPIO 18.
+        Ledc {
+            /// In Hz.
+            #[serde(default = "default_ledc_frequency")]
+            frequency: f64,
+            /// The resolution of the duty cycle, in bits.
+            #[serde(default = "default_ledc_resolution")]
+            resolution: u32,
+        },
+    }
+    fn default_polarity() -> Polarity {
+        Polarity::Normal
+    }
+    fn default_servo_min() -> u64 {
+        1000
+    }
+    fn default_servo_max() -> u64 {
+        2000
+    }
+    fn default_ledc_frequency() -> f64 {
+        25_000.0
     }
-    impl Data {
-        pub fn read_from_file<P: AsRef<Path>>(
-            path: P,
-            week_scheduler: &WeekScheduler,
-        ) -> io::Result<Self> {
-            fn read(path: &Path) -> io::Result<Data> {
-                let file = std::fs::File::open(path)?;
-                ron::de::from_reader(file)
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    fn default_ledc_resolution() -> u32 {
+        10
+    }
+    /// Parses `hardware:CHANNEL`, `software:PIN` or `sysfs:CHIP:CHANNEL`, optionally followed by
+    /// `:FREQUENCY` and `:inverse`, e.g. `software:17:200:inverse`,
+    /// `led:NAME`, `backlight:NAME` or `ledc`, optionally followed by `:FREQUENCY` and `:BITS`.
+    /// `servo:` followed by any of them drives a servo with the default pulse range.
+    impl FromStr for OutputConfig {
+        type Err = ();
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            // the names of LEDs often contain colons, e.g. `beaglebone:green:usr0`
+            match s.split_once(':') {
+                Some(("servo", output)) => {
+                    return match output.parse()? {
+                        Self::Servo { .. } => Err(()),
+                        output => Ok(Self::Servo {
+                            output: Box::new(output),
+                            min: default_servo_min(),
+                            max: default_servo_max(),
+                            reversed: false,
+                        }),
+                    }
+                }
+                Some(("led", name)) => {
+                    return Ok(Self::Led {
+                        name: name.to_string(),
+                    })
+                }
+                Some(("backlight", name)) => {
+                    return Ok(Self::Backlight {
+                        name: name.to_string(),
+                    })
+                }
+                _ => {}
             }
-            read(path.as_ref()).map(|mut data| {
-                if data.week_scheduler.is_none() {
-                    data.week_scheduler = Some(WeekSchedulerData::from_scheduler(week_scheduler));
+            let mut parts = s.split(':');
+            let kind = parts.next().ok_or(())?;
+            if kind == "ledc" {
+                let frequency = match parts.next() {
+                    Some(frequency) => frequency.parse().map_err(|_| ())?,
+                    None => default_ledc_frequency(),
+                };
+                let resolution = match parts.next() {
+                    Some(resolution) => resolution.parse().map_err(|_| ())?,
+                    None => default_ledc_resolution(),
+                };
+                if parts.next().is_some() {
+                    return Err(());
                 }
-                data
+                return Ok(Self::Ledc {
+                    frequency,
+                    resolution,
+                });
+            }
+            let chip = match kind {
+                "sysfs" => parts.next().ok_or(())?.parse().map_err(|_| ())?,
+                _ => 0,
+            };
+            let id: u32 = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+            let frequency = match parts.next() {
+                Some(frequency) => frequency.parse().map_err(|_| ())?,
+                None => default_frequency(),
+            };
+            let polarity = match parts.next() {
+                Some(polarity) => polarity.parse()?,
+                None => Polarity::Normal,
+            };
+            if parts.next().is_some() {
+                return Err(());
+            }
+            Ok(match kind {
+                "hardware" => Self::Hardware {
+                    channel: id.try_into().map_err(|_| ())?,
+                    frequency,
+                    polarity,
+                },
+                "software" => Self::Software {
+                    pin: id.try_into().map_err(|_| ())?,
+                    frequency,
+                    polarity,
+                },
+                "sysfs" => Self::Sysfs {
+                    chip,
+                    channel: id,
+                    frequency,
+                    polarity,
+                },
+                _ => return Err(()),
             })
         }
-        pub fn from_week_scheduler(scheduler: &WeekScheduler) -> Self {
-            Self {
-                strength: None,
-                schedulers: Vec::new(),
-                week_scheduler: Some(WeekSchedulerData::from_scheduler(scheduler)),
-                current_transition: None,
+    }
+    impl OutputConfig {
+        fn name(&self) -> String {
+            match self {
+                Self::Hardware { channel, .. } => format!("PWM channel {}", channel),
+                Self::Software { pin, .. } => format!("GPIO pin {}", pin),
+                Self::Sysfs { chip, channel, .. } => {
+                    format!("sysfs PWM chip {} channel {}", chip, channel)
+                }
+                Self::Led { name } => format!("LED {}", name),
+                Self::Backlight { name } => format!("backlight {}", name),
+                Self::Ledc { .. } => "LEDC channel 0".to_string(),
+                Self::Servo { output, .. } => format!("servo on {}", output.name()),
             }
         }
-
-        pub fn apply<T: VariableOut + Send>(&self, controller: &Controller<T>) {
-            if let Some(s) = self.strength {
-                controller.send(Command::Set(Strength::new_clamped(s)));
-            }
-            for scheduler in self
-                .schedulers
-                .iter()
-                .filter_map(|s| s.clone().into_command(true))
-            {
-                controller.send(scheduler);
-            }
-            if let Some(transition) = self
-                .current_transition
-                .as_ref()
-                .and_then(datas::TransitionData::to_transition)
-            {
-                controller.send(Command::SetTransition(transition));
+        /// Returns the problem with the output.
+        fn validate(&self) -> Option<String> {
+            let frequency = match *self {
+                Self::Servo {
+                    ref output,
+                    min,
+                    max,
+                    ..
+                } => {
+                    if let Self::Servo { .. } = **output {
+                        return Some(format!("{} can't be another servo", self.name()));
+                    }
+                    // the period at 50 Hz is 20 ms
+                    if min > max || max > 20_000 {
+                        return Some(format!(
+                            "the pulses of {} have to be at most 20000 µs with min ≤ max, not {}-{}",
+                            self.name(),
+                            min,
+                            max
+                        ));
+                    }
+                    return output.validate();
+                }
+                Self::Hardware { channel, .. } if channel > 1 => {
+                    return Some(format!("PWM channel has to be 0 or 1, not {}", channel));
+                }
+                // the esp32-c3 has at most 14 bits
+                Self::Ledc { resolution, .. } if !(1..=14).contains(&resolution) => {
+                    return Some(format!(
+                        "the resolution of {} has to be 1 to 14 bits, not {}",
+                        self.name(),
+                        resolution
+                    ));
+                }
+                Self::Hardware { frequency, .. }
+                | Self::Software { frequency, .. }
+                | Self::Sysfs { frequency, .. }
+                | Self::Ledc { frequency, .. } => frequency,
+                Self::Led { ref name } | Self::Backlight { ref name } => {
+                    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+                        return Some(format!("invalid device name {:?}", name));
+                    }
+                    return None;
+                }
+            };
+            if !(frequency.is_finite() && frequency > 0.0) {
+                return Some(format!(
+                    "the frequency of {} has to be a positive number, not {}",
+                    self.name(),
+                    frequency
+                ));
             }
+            None
         }
-
-        pub fn ref_strength(&self) -> Option<f64> {
-            self.strength
-        }
-        pub fn set_strength(&mut self, strength: Strength) -> Option<Strength> {
-            self.strength
-                .replace(strength.into_inner())
-                .map(Strength::new_clamped)
-        }
-
-        pub fn ref_schedulers(&self) -> &Vec<datas::AddSchedulerData> {
-            &self.schedulers
-        }
-        pub fn mut_schedulers(&mut self) -> &mut Vec<datas::AddSchedulerData> {
-            &mut self.schedulers
-        }
-
-        pub fn ref_week_scheduler(&self) -> &WeekSchedulerData {
-            // ok, since it must be `Some`, it's just an option for parsing from file.
-            self.week_scheduler.as_ref().unwrap()
-        }
-        pub fn mut_week_scheduler(&mut self) -> &mut WeekSchedulerData {
-            // ok, since it must be `Some`, it's just an option for parsing from file.
-            self.week_scheduler.as_mut().unwrap()
-        }
-        pub fn set_week_scheduler(&mut self, new: &WeekScheduler) -> Option<WeekSchedulerData> {
-            self.week_scheduler
-                .replace(WeekSchedulerData::from_scheduler(new))
-        }
-        pub fn eq_transition(&self, other: Option<&Transition>) -> bool {
-            match self.current_transition.as_ref() {
-                Some(transition) => match transition.to_transition() {
-                    Some(transition) => match other {
-                        Some(other) => &transition == other,
-                        None => false,
-                    },
-                    None => false,
-                },
-                None => other.is_none(),
+        /// Opens the output. `sysfs_root` is where the sysfs device classes are.
+        ///
+        /// Returns an error if the channel or pin is unavailable
+        /// or support for the kind of output isn't compiled in.
+        #[cfg(any(feature = "rpi", feature = "sysfs"))]
+        pub fn open(
+            &self,
+            sysfs_root: &std::path::Path,
+        ) -> Result<Box<dyn httpwm::VariableOut + Send>, String> {
+            match *self {
+                #[cfg(feature = "rpi")]
+                Self::Hardware {
+                    channel,
+                    frequency,
+                    polarity,
+                } => {
+                    let (channel, name) = match channel {
+                        0 => (rppal::pwm::Channel::Pwm0, "Pwm0"),
+                        _ => (rppal::pwm::Channel::Pwm1, "Pwm1"),
+                    };
+                    let polarity = match polarity {
+                        Polarity::Normal => rppal::pwm::Polarity::Normal,
+                        Polarity::Inverse => rppal::pwm::Polarity::Inverse,
+                    };
+                    let pwm =
+                        rppal::pwm::Pwm::with_frequency(channel, frequency, 0.0, polarity, true)
+                            .map_err(|err| {
+                                format!(
+                                "PWM channel {} is unavailable: {}. Is the PWM overlay enabled?",
+                                name, err
+                            )
+                            })?;
+                    Ok(Box::new(pwm))
+                }
+                #[cfg(feature = "rpi")]
+                Self::Software {
+                    pin,
+                    frequency,
+                    polarity,
+                } => {
+                    let pin = rppal::gpio::Gpio::new()
+                        .and_then(|gpio| gpio.get(pin))
+                        .map_err(|err| format!("GPIO pin {} is unavailable: {}", pin, err))?
+                        .into_output();
+                    Ok(Box::new(httpwm::SoftwarePwm::new(
+                        pin,
+                        frequency,
+                        polarity == Polarity::Inverse,
+                    )))
+                }
+                #[cfg(not(feature = "rpi"))]
+                Self::Hardware { .. } | Self::Software { .. } => {
+                    Err(format!("{} needs the `rpi` feature of httpwm", self.name()))
+                }
+                #[cfg(feature = "sysfs")]
+                Self::Sysfs {
+                    chip,
+                    channel,
+                    frequency,
+                    polarity,
+                } => {
+                    let pwm = httpwm::sysfs::SysfsPwm::with_root(
+                        sysfs_root.join("pwm"),
+                        chip,
+                        channel,
+                        frequency,
+                        polarity == Polarity::Inverse,
+                    )
+                    .map_err(|err| format!("{} is unavailable: {}", self.name(), err))?;
+                    Ok(Box::new(pwm))
+                }
+                #[cfg(feature = "sysfs")]
+                Self::Led { ref name } | Self::Backlight { ref name } => {
+                    use httpwm::sysfs::{Brightness, BrightnessKind};
+                    let (class, kind) = match self {
+                        Self::Led { .. } => ("leds", BrightnessKind::Led),
+                        _ => ("backlight", BrightnessKind::Backlight),
+                    };
+                    let out = Brightness::with_root(sysfs_root.join(class), name, kind)
+                        .map_err(|err| format!("{} is unavailable: {}", self.name(), err))?;
+                    Ok(Box::new(out))
+                }
+                #[cfg(not(feature = "sysfs"))]
+                Self::Sysfs { .. } | Self::Led { .. } | Self::Backlight { .. } => {
+                    let _ = sysfs_root;
+                    Err(format!(
+                        "{} needs the `sysfs` feature of httpwm",
+                        self.name()
+                    ))
+                }
+                Self::Ledc { .. } => Err(format!("{} is only on the esp32", self.name())),
+                Self::Servo { ref output, .. } => self.servo(output.open(sysfs_root)?),
             }
         }
-        pub fn set_transition(
-            &mut self,
-            new: Option<&Transition>,
-        ) -> Option<datas::TransitionData> {
-            match new {
-                None => self.current_transition.take(),
-                Some(transition) => self
-                    .current_transition
-                    .replace(datas::TransitionData::from_transition(transition)),
+        /// Drives `output` as a servo, if `self` is [`OutputConfig::Servo`].
+        #[cfg(any(feature = "rpi", feature = "sysfs", feature = "esp32"))]
+        pub fn servo(
+            &self,
+            output: Box<dyn httpwm::VariableOut + Send>,
+        ) -> Result<Box<dyn httpwm::VariableOut + Send>, String> {
+            match *self {
+                Self::Servo {
+                    min, max, reversed, ..
+                } => {
+                    let servo = httpwm::Servo::with_range(
+                        output,
+                        httpwm::Servo::<Box<dyn httpwm::VariableOut + Send>>::FREQUENCY,
+                        std::time::Duration::from_micros(min),
+                        std::time::Duration::from_micros(max),
+                        reversed,
+                    )
+                    .map_err(|err| format!("{} can't be set up: {}", self.name(), err))?;
+                    Ok(Box::new(servo))
+                }
+                _ => Ok(output),
             }
         }
     }
-}
-
-pub mod datas {
-    use httpwm::primitive_to_tz;
 
-    use super::*;
-    #[derive(Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
-    pub struct DayData {
-        day: String,
-        time: Option<String>,
+    /// Where to read the temperature from. See [`httpwm::fan::TemperatureSource`].
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub enum TemperatureSource {
+        /// A file with the temperature in millidegrees.
+        File(PathBuf),
+        /// A shell command which prints the temperature in degrees.
+        Command(String),
     }
-    impl DayData {
-        pub fn parse(&self) -> Option<(Weekday, Option<time::Time>)> {
-            let day: Weekday = self.day.parse().ok()?;
-            let time = match self.time.as_ref() {
-                Some(time) => Some(parse_time(time)?),
-                None => None,
-            };
-            Some((day, time))
-        }
-    }
-
-    #[derive(Deserialize, Serialize, Debug, Clone)]
-    pub struct TransitionData {
-        from: f64,
-        to: f64,
-        time: f64,
-        interpolation: String,
-        extras: Vec<String>,
+    /// Drives the outputs by a temperature. See [`httpwm::fan::FanControl`].
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    pub struct FanConfig {
+        pub source: TemperatureSource,
+        /// `(temperature, strength)` points, with increasing temperatures.
+        pub curve: Vec<(f64, f64)>,
+        /// How many degrees the temperature has to fall before the fan slows down.
+        pub hysteresis: f64,
+        /// How often to read the temperature. In seconds.
+        pub interval: f64,
     }
-    impl TransitionData {
-        pub fn to_transition(&self) -> Option<Transition> {
-            let from = Strength::new_clamped(self.from);
-            let to = Strength::new_clamped(self.to);
-            let time = Duration::from_secs_f64(self.time);
-
-            let interpolation =
-                TransitionInterpolation::from_str(&self.interpolation, &self.extras)?;
-            Some(Transition {
-                from,
-                to,
-                time,
-                interpolation,
-            })
-        }
-
-        pub fn from_transition(transition: &Transition) -> Self {
-            let mut extras = Vec::with_capacity(4);
-
-            transition.interpolation.apply_extras(&mut extras);
-
+    impl Default for FanConfig {
+        fn default() -> Self {
             Self {
-                from: Strength::clone(&transition.from).into_inner(),
-                to: Strength::clone(&transition.to).into_inner(),
-                time: transition.time.as_secs_f64(),
-                interpolation: transition.interpolation.as_str().to_string(),
-                extras,
+                source: TemperatureSource::File(PathBuf::from(
+                    "/sys/class/thermal/thermal_zone0/temp",
+                )),
+                curve: vec![(45.0, 0.0), (50.0, 0.3), (70.0, 1.0)],
+                hysteresis: 3.0,
+                interval: 5.0,
             }
         }
     }
-
-    #[derive(Debug, Serialize)]
-    pub struct StateData {
-        strength: f64,
-        days: HashMap<String, Option<String>>,
-        transition: TransitionData,
-    }
-    impl StateData {
-        pub fn from_shared_state(state: &SharedState) -> Self {
-            let mut days = HashMap::with_capacity(7);
-            let mut day = Weekday::Mon;
-            for _ in 0..7 {
-                days.insert(
-                    weekday_to_lowercase_str(&day).to_string(),
-                    state
-                        .ref_week_schedule()
-                        .get(day)
-                        .map(|time| time.format(&SECOND_FORMAT).unwrap()),
+    impl FanConfig {
+        /// Returns the problems with the fan config.
+        fn validate(&self) -> Vec<String> {
+            let mut errors = Vec::new();
+            if httpwm::fan::FanCurve::new(self.curve.iter().copied()).is_none() {
+                errors.push(
+                    "the fan curve needs points with increasing temperatures \
+                    and strengths between 0 and 1"
+                        .to_string(),
                 );
-                day = day.succ();
             }
-
-            Self {
-                strength: Strength::clone(state.get_strength()).into_inner(),
-                days,
-                transition: TransitionData::from_transition(&state.ref_week_schedule().transition),
+            if !(self.hysteresis.is_finite() && self.hysteresis >= 0.0) {
+                errors.push(format!(
+                    "the fan hysteresis has to be a positive number, not {}",
+                    self.hysteresis
+                ));
+            }
+            if !(self.interval.is_finite() && self.interval > 0.0) {
+                errors.push(format!(
+                    "the fan interval has to be a positive number, not {}",
+                    self.interval
+                ));
             }
+            errors
         }
-    }
-    #[derive(Debug, Deserialize, Serialize, Clone)]
-    pub struct AddSchedulerData {
-        pub kind: String,
-        pub time: String,
-        pub name: String,
-        pub description: String,
-        pub extras: Vec<String>,
-        pub transition: TransitionData,
-    }
-    impl AddSchedulerData {
-        pub fn into_command(self, allow_past: bool) -> Option<Command> {
-            let transition = self.transition.to_transition()?;
-            let time = parse_time(&self.time)?;
-            // Unwrap is ok, since we know `SetTransition` is clonable
-            let run_command = ClonableCommand::new(Command::SetTransition(transition)).unwrap();
-            let common = extra_schedulers::Common::new(self.description, run_command);
-
-            let scheduler: Box<dyn Scheduler> = match self.kind.as_str() {
-                "at" if self.extras.len() == 1 => {
-                    let date_time = time::Date::parse(self.extras[0].as_str(), &DATE_FORMAT)
-                        .ok()?
-                        .with_time(time);
-                    if has_occurred(primitive_to_tz(date_time)) && !allow_past {
-                        return None;
-                    }
-                    Box::new(extra_schedulers::At::new(
-                        common,
-                        primitive_to_tz(date_time),
-                    ))
+        /// Panics if the config isn't [valid](Self::validate).
+        #[cfg(any(feature = "rpi", feature = "sysfs"))]
+        fn control<T: httpwm::VariableOut + Send + 'static>(
+            &self,
+            output: T,
+        ) -> httpwm::fan::FanControl<T> {
+            let source = match &self.source {
+                TemperatureSource::File(path) => httpwm::fan::TemperatureSource::File(path.clone()),
+                TemperatureSource::Command(command) => {
+                    httpwm::fan::TemperatureSource::Command(command.clone())
                 }
-                "every-week" if self.extras.len() == 1 => Box::new(
-                    extra_schedulers::EveryWeek::new(common, time, self.extras[0].parse().ok()?),
-                ),
-                "every-day" => Box::new(extra_schedulers::EveryDay::new(common, time)),
-                _ => return None,
             };
-            Some(Command::AddReplaceScheduler(self.name, scheduler))
+            let curve = httpwm::fan::FanCurve::new(self.curve.iter().copied())
+                .expect("config wasn't validated");
+            httpwm::fan::FanControl::new(
+                output,
+                source,
+                curve,
+                self.hysteresis,
+                std::time::Duration::from_secs_f64(self.interval),
+            )
         }
     }
-    #[derive(Debug, Serialize)]
-    pub struct SchedulerData {
-        name: String,
-        description: String,
-        kind: String,
-        next_occurrence: String,
-    }
-    impl SchedulerData {
-        pub fn from_scheduler(
-            scheduler: &dyn Scheduler,
-            name: String,
-            now: &mut scheduler::LazyNow,
-        ) -> Self {
-            let dur = scheduler.get_next(now);
-
-            let next_occurrence = match dur {
-                Next::At(date_time, _) => {
-                    let dur = date_time - now.now();
-                    if dur.whole_days() > 0 {
-                        (now.now() + dur).format(&DATE_TIME_FORMAT).unwrap()
-                    } else if dur.whole_hours() > 0 {
-                        format!("In {} hours", dur.whole_hours())
-                    } else if dur.whole_minutes() > 0 {
-                        format!("In {} minutes", dur.whole_minutes())
-                    } else {
-                        format!("In {} seconds", dur.whole_seconds())
-                    }
-                }
-                Next::Unknown => "unknown".to_string(),
-            };
 
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    pub struct Config {
+        pub port: u16,
+        pub bind: Bind,
+        pub web_root: PathBuf,
+        /// Where the state is saved.
+        pub data_dir: PathBuf,
+        /// The outputs. All are set to the same strength.
+        pub outputs: Vec<OutputConfig>,
+        /// Where the sysfs device classes (`pwm`, `leds` and `backlight`) are.
+        /// Only change this to test against a fake tree.
+        pub sysfs_root: PathBuf,
+        /// The time the default week scheduler runs every day, as `hh:mm:ss`.
+        /// Only used if no state is saved.
+        pub default_time: String,
+        /// The transition of the default week scheduler. Only used if no state is saved.
+        pub day_transition: datas::TransitionData,
+        /// What to do when the daemon starts. Only used if no state is saved;
+        /// change it later using `/set-power-on`.
+        pub power_on: datas::PowerOnData,
+        /// Drive the outputs by a temperature, in addition to the schedules.
+        pub fan: Option<FanConfig>,
+    }
+    impl Default for Config {
+        fn default() -> Self {
             Self {
-                name,
-                description: scheduler.description().to_string(),
-                kind: scheduler.kind().to_string(),
-                next_occurrence,
+                #[cfg(feature = "esp32")]
+                port: 80,
+                #[cfg(not(feature = "esp32"))]
+                port: 8080,
+                bind: Bind::Ipv4,
+                web_root: PathBuf::from("web"),
+                data_dir: PathBuf::from("."),
+                #[cfg(feature = "esp32")]
+                outputs: vec![OutputConfig::Ledc {
+                    frequency: default_ledc_frequency(),
+                    resolution: default_ledc_resolution(),
+                }],
+                #[cfg(not(feature = "esp32"))]
+                outputs: vec![OutputConfig::Hardware {
+                    channel: 0,
+                    frequency: default_frequency(),
+                    polarity: Polarity::Normal,
+                }],
+                sysfs_root: PathBuf::from("/sys/class"),
+                default_time: "07:00:00".to_string(),
+                day_transition: datas::TransitionData::from_transition(&Transition::default()),
+                power_on: datas::PowerOnData::default(),
+                fan: None,
             }
         }
     }
+    impl Config {
+        /// Reads the config file and applies the overrides from the environment and `args`
+        /// (without the name of the program).
+        ///
+        /// Returns all the errors found.
+        pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Action, Vec<String>> {
+            Self::parse_args(
+                args,
+                |var| std::env::var(var).ok(),
+                |path| std::fs::read(path),
+            )
+        }
+        /// Same as [`Config::from_args`], but gets the environment variables from `env`
+        /// and reads the config file using `read`.
+        fn parse_args(
+            args: impl IntoIterator<Item = String>,
+            env: impl Fn(&str) -> Option<String>,
+            read: impl Fn(&Path) -> io::Result<Vec<u8>>,
+        ) -> Result<Action, Vec<String>> {
+            let mut errors = Vec::new();
+            let mut path = env(PATH_VAR).map(PathBuf::from);
+            let mut overrides = Vec::new();
+            let mut print = false;
+
+            let mut args = args.into_iter();
+            let mut first = true;
+            while let Some(arg) = args.next() {
+                let flag = match arg.strip_prefix("--") {
+                    Some(flag) => flag,
+                    // the port used to be the only argument
+                    None if first => {
+                        overrides.push(("port", arg));
+                        first = false;
+                        continue;
+                    }
+                    None => {
+                        errors.push(format!("unexpected argument {:?}", arg));
+                        continue;
+                    }
+                };
+                first = false;
+                let (flag, value) = match flag.split_once('=') {
+                    Some((flag, value)) => (flag, Some(value.to_string())),
+                    None => (flag, None),
+                };
+                match flag {
+                    "help" => return Ok(Action::Help),
+                    "print-config" => print = true,
+                    "config" => match value.or_else(|| args.next()) {
+                        Some(value) => path = Some(PathBuf::from(value)),
+                        None => errors.push("--config needs a value".to_string()),
+                    },
+                    _ => match OPTIONS.iter().find(|option| **option == flag) {
+                        Some(option) => match value.or_else(|| args.next()) {
+                            Some(value) => overrides.push((option, value)),
+                            None => errors.push(format!("--{} needs a value", option)),
+                        },
+                        None => errors.push(format!("unknown flag --{}", flag)),
+                    },
+                }
+            }
 
-    #[derive(Debug, Deserialize, Serialize, Clone)]
-    pub struct EffectData {
-        pub kind: String,
-        pub nums: Vec<f64>,
-    }
-    impl EffectData {
-        pub fn into_command(self) -> Option<Command> {
-            match self.kind.as_str() {
-                "radar" => {
-                    let offset = *self.nums.first()?;
-                    let speed = *self.nums.get(1)?;
-                    Some(Command::SetEffect(Effect::Radar { offset, speed }))
+            let explicit = path.is_some();
+            let path = path.unwrap_or_else(|| PathBuf::from(DEFAULT_PATH));
+            let mut config = match read(&path) {
+                Ok(bytes) => match ron::de::from_bytes(&bytes) {
+                    Ok(config) => config,
+                    Err(err) => {
+                        errors.push(format!("invalid config file {}: {}", path.display(), err));
+                        Self::default()
+                    }
+                },
+                Err(err) if explicit || err.kind() != io::ErrorKind::NotFound => {
+                    errors.push(format!("failed to read {}: {}", path.display(), err));
+                    Self::default()
+                }
+                Err(_) => Self::default(),
+            };
+
+            for option in OPTIONS {
+                let var = format!("HTTPWM_{}", option.to_uppercase().replace('-', "_"));
+                if let Some(value) = env(&var) {
+                    if let Err(err) = config.set(option, &value) {
+                        errors.push(format!("{}: {}", var, err));
+                    }
                 }
-                _ => None,
             }
-        }
-    }
-}
-pub mod extra_schedulers {
-    use httpwm::scheduler::Keep;
-
-    use super::*;
-    use httpwm::primitive_to_tz;
-    use time::OffsetDateTime;
-
-    pub(crate) fn get_next_day<F: Fn(Weekday) -> Option<time::Time>>(
-        from: Weekday,
-        get: F,
-    ) -> Option<(time::Time, u8)> {
-        let mut day = from;
-
-        for passed in 0..7 {
-            day = day.succ();
-            let time = get(day);
-            if time.is_some() {
-                return time.map(|t| (t, passed + 1));
+            for (option, value) in overrides {
+                if let Err(err) = config.set(option, &value) {
+                    errors.push(format!("--{}: {}", option, err));
+                }
             }
-        }
-        None
-    }
+            errors.extend(config.validate());
 
-    #[derive(Debug)]
-    pub struct Common {
-        description: String,
-        command: ClonableCommand,
-    }
-    impl Common {
-        /// Returns `Err` when command is not clonable
-        pub fn new(description: String, command: ClonableCommand) -> Self {
-            Self {
-                description,
-                command,
+            if !errors.is_empty() {
+                Err(errors)
+            } else if print {
+                Ok(Action::PrintConfig(config))
+            } else {
+                Ok(Action::Run(config))
             }
         }
-        pub fn get_command(&self) -> ClonableCommand {
-            // Ok, since it's guaranteed the command in `Common` is clonable.
-            ClonableCommand::clone(&self.command)
+        /// Sets one of the [`OPTIONS`] to `value`.
+        pub fn set(&mut self, option: &str, value: &str) -> Result<(), String> {
+            fn parse<T: FromStr>(value: &str, expected: &str) -> Result<T, String> {
+                value
+                    .parse()
+                    .map_err(|_| format!("expected {}, got {:?}", expected, value))
+            }
+            fn parse_ron<T: serde::de::DeserializeOwned>(
+                value: &str,
+                expected: &str,
+            ) -> Result<T, String> {
+                ron::de::from_str(value).map_err(|err| {
+                    format!(
+                        "expected {} in the format of the config file: {}",
+                        expected, err
+                    )
+                })
+            }
+            match option {
+                "port" => self.port = parse(value, "a port")?,
+                "bind" => self.bind = parse(value, "ipv4, ipv6 or both")?,
+                "web-root" => self.web_root = PathBuf::from(value),
+                "data-dir" => self.data_dir = PathBuf::from(value),
+                "outputs" => {
+                    self.outputs = value
+                        .split(',')
+                        .map(|output| parse(output, "outputs like `hardware:0,sysfs:0:1`"))
+                        .collect::<Result<_, _>>()?
+                }
+                "sysfs-root" => self.sysfs_root = PathBuf::from(value),
+                "fan-source" => {
+                    self.fan.get_or_insert_with(FanConfig::default).source =
+                        match value.strip_prefix("cmd:") {
+                            Some(command) => TemperatureSource::Command(command.to_string()),
+                            None => TemperatureSource::File(PathBuf::from(value)),
+                        }
+                }
+                "fan-curve" => {
+                    self.fan.get_or_insert_with(FanConfig::default).curve = value
+                        .split(',')
+                        .map(|point| {
+                            point
+                                .split_once(':')
+                                .and_then(|(temperature, strength)| {
+                                    Some((temperature.parse().ok()?, strength.parse().ok()?))
+                                })
+                                .ok_or_else(|| {
+                                    format!(
+                                        "expected TEMP:STRENGTH points like `45:0,70:1`, got {:?}",
+                                        point
+                                    )
+                                })
+                        })
+                        .collect::<Result<_, _>>()?
+                }
+                "default-time" => self.default_time = value.to_string(),
+                "day-transition" => self.day_transition = parse_ron(value, "a transition")?,
+                "power-on" => self.power_on = parse_ron(value, "a power on action")?,
+                _ => return Err(format!("unknown option {:?}", option)),
+            }
+            Ok(())
         }
-    }
-
-    #[derive(Debug)]
-    pub struct At {
-        common: Common,
-        moment: OffsetDateTime,
-    }
-    impl At {
-        pub fn new(common: Common, moment: OffsetDateTime) -> Self {
-            Self { common, moment }
+        /// Returns the problems with the config.
+        pub fn validate(&self) -> Vec<String> {
+            let mut errors = Vec::new();
+            if self.outputs.is_empty() {
+                errors.push("at least one output is needed".to_string());
+            }
+            for (i, output) in self.outputs.iter().enumerate() {
+                errors.extend(output.validate());
+                if self.outputs[..i]
+                    .iter()
+                    .any(|other| other.name() == output.name())
+                {
+                    errors.push(format!("{} is used more than once", output.name()));
+                }
+            }
+            if parse_time(&self.default_time).is_none() {
+                errors.push(format!(
+                    "default_time has to be a time (07:00:00), not {:?}",
+                    self.default_time
+                ));
+            }
+            if self.day_transition.to_transition().is_none() {
+                errors.push(
+                    "day_transition needs a known interpolation and a time of 0 or more"
+                        .to_string(),
+                );
+            }
+            if self.power_on.validate().is_none() {
+                errors.push("invalid power_on".to_string());
+            }
+            if let Some(fan) = &self.fan {
+                errors.extend(fan.validate());
+            }
+            errors
         }
-    }
-    impl Scheduler for At {
-        fn get_next(&self, _: &mut scheduler::LazyNow) -> Next {
-            Next::At(self.moment, self.common.get_command().into_inner())
+        /// Opens all the [outputs](Self::outputs).
+        ///
+        /// Returns the first which is unavailable.
+        #[cfg(any(feature = "rpi", feature = "sysfs"))]
+        pub fn open_outputs(&self) -> Result<Box<dyn httpwm::VariableOut + Send>, String> {
+            let outputs = self
+                .outputs
+                .iter()
+                .map(|output| output.open(&self.sysfs_root))
+                .collect::<Result<_, _>>()?;
+            let outputs = httpwm::MultiOut(outputs);
+            Ok(match &self.fan {
+                Some(fan) => Box::new(fan.control(outputs)),
+                None => Box::new(outputs),
+            })
         }
-        fn advance(&mut self) -> Keep {
-            Keep::Remove
+        /// The frequency and resolution of the LEDC output of the esp32.
+        ///
+        /// Returns an error if the outputs aren't exactly one [`OutputConfig::Ledc`],
+        /// optionally driving a [servo](OutputConfig::Servo).
+        #[cfg(feature = "esp32")]
+        pub fn ledc(&self) -> Result<(f64, u32), String> {
+            let output = match self.outputs.as_slice() {
+                [OutputConfig::Servo { output, .. }] => &**output,
+                [output] => output,
+                _ => return Err("the esp32 has exactly one output, `ledc`".to_string()),
+            };
+            match *output {
+                OutputConfig::Ledc {
+                    frequency,
+                    resolution,
+                } => Ok((frequency, resolution)),
+                _ => Err("the esp32 only has the output `ledc`".to_string()),
+            }
         }
-        fn description(&self) -> &str {
-            self.common.description.as_str()
+        /// Panics if the config isn't [valid](Self::validate).
+        pub fn default_time(&self) -> time::Time {
+            parse_time(&self.default_time).expect("config wasn't validated")
         }
-        fn kind(&self) -> &str {
-            "At"
+        /// Panics if the config isn't [valid](Self::validate).
+        pub fn day_transition(&self) -> Transition {
+            self.day_transition
+                .to_transition()
+                .expect("config wasn't validated")
         }
-    }
-    #[derive(Debug)]
-    pub struct EveryWeek {
-        common: Common,
-        time: time::Time,
-        day: Weekday,
-    }
-    impl EveryWeek {
-        pub fn new(common: Common, time: time::Time, day: Weekday) -> Self {
-            Self { common, time, day }
+        pub fn to_ron(&self) -> String {
+            ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+                .expect("failed to serialize config")
         }
     }
-    impl Scheduler for EveryWeek {
-        fn get_next(&self, now: &mut scheduler::LazyNow) -> Next {
-            let now = now.now();
-            if self.day == Weekday::from(now.weekday()) && now.time() < self.time {
-                // Unwrap is OK, now will never be over self.time.
-                Next::At(
-                    now.replace_time(self.time),
-                    self.common.get_command().into_inner(),
-                )
-            } else {
-                // Unwrap is ok, we must have one day containing a date.
-                let (time, offset): (time::Time, _) = get_next_day(now.weekday().into(), |day| {
-                    if day == self.day {
-                        Some(self.time)
-                    } else {
-                        None
-                    }
-                })
-                .unwrap();
-                Next::At(
-                    primitive_to_tz(
-                        now.date().with_time(time) + time::Duration::days(offset as i64),
-                    ),
-                    self.common.get_command().into_inner(),
-                )
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const FILE: &str = r#"(
+    port: 81,
+    day_transition: (from: 0.0, to: 1.0, time: 600.0, interpolation: "sine", extras: []),
+    power_on: (level: (mode: "off")),
+    fan: Some((curve: [(40.0, 0.0), (60.0, 1.0)])),
+)"#;
+        const TRANSITION: &str =
+            r#"(from: 0.0, to: 0.5, time: 60.0, interpolation: "linear", extras: [])"#;
+
+        /// Resolves the config from `args`, the environment `env` and the config file `file`.
+        fn resolve(
+            args: &[&str],
+            env: &[(&str, &str)],
+            file: Option<&str>,
+        ) -> Result<Config, Vec<String>> {
+            let action = Config::parse_args(
+                args.iter().map(|arg| arg.to_string()),
+                |var| {
+                    env.iter()
+                        .find(|(name, _)| *name == var)
+                        .map(|(_, value)| value.to_string())
+                },
+                |path| match file {
+                    Some(file) if path == Path::new(DEFAULT_PATH) => Ok(file.into()),
+                    _ => Err(io::ErrorKind::NotFound.into()),
+                },
+            )?;
+            match action {
+                Action::Run(config) => Ok(config),
+                _ => panic!("expected the config to run"),
             }
         }
-        fn advance(&mut self) -> Keep {
-            Keep::Keep
+        fn curve(config: &Config) -> &[(f64, f64)] {
+            &config.fan.as_ref().unwrap().curve
         }
-        fn description(&self) -> &str {
-            self.common.description.as_str()
+
+        #[test]
+        fn file_only() {
+            let config = resolve(&[], &[], Some(FILE)).unwrap();
+            assert_eq!(config.port, 81);
+            assert_eq!(config.day_transition().time.as_secs(), 600);
+            assert!(matches!(
+                config.power_on.level,
+                datas::PowerOnLevelData::Off
+            ));
+            assert_eq!(curve(&config), [(40.0, 0.0), (60.0, 1.0)]);
+
+            let config = resolve(&[], &[], None).unwrap();
+            assert_eq!(config.day_transition(), Transition::default());
+            assert!(config.fan.is_none());
         }
-        fn kind(&self) -> &str {
-            "Every week at"
+        #[test]
+        fn flags_over_file() {
+            let transition = TRANSITION.replace("60.0", "300.0");
+            let env = [
+                ("HTTPWM_DAY_TRANSITION", transition.as_str()),
+                ("HTTPWM_FAN_CURVE", "10:0,20:1"),
+            ];
+            let config = resolve(
+                &[
+                    "--day-transition",
+                    TRANSITION,
+                    "--power-on=(level: (mode: \"fixed\", strength: 0.5))",
+                    "--fan-curve",
+                    "30:0.2,80:1",
+                ],
+                &env,
+                Some(FILE),
+            )
+            .unwrap();
+            let transition = config.day_transition();
+            assert_eq!(transition.time.as_secs(), 60);
+            assert_eq!(transition.to.into_inner(), 0.5);
+            assert!(matches!(
+                config.power_on.level,
+                datas::PowerOnLevelData::Fixed { strength, .. } if strength == 0.5
+            ));
+            assert_eq!(curve(&config), [(30.0, 0.2), (80.0, 1.0)]);
         }
-    }
-    #[derive(Debug)]
-    pub struct EveryDay {
-        common: Common,
-        time: time::Time,
-    }
-    impl EveryDay {
-        pub fn new(common: Common, time: time::Time) -> Self {
-            Self { common, time }
+        #[test]
+        fn env_over_file() {
+            let env = [
+                ("HTTPWM_DAY_TRANSITION", TRANSITION),
+                ("HTTPWM_POWER_ON", "(level: (mode: \"restore\", fade: 2.0))"),
+                ("HTTPWM_FAN_CURVE", "30:0.2,80:1"),
+            ];
+            let config = resolve(&[], &env, Some(FILE)).unwrap();
+            assert_eq!(config.port, 81);
+            assert_eq!(config.day_transition().time.as_secs(), 60);
+            assert!(matches!(
+                config.power_on.level,
+                datas::PowerOnLevelData::Restore { fade } if fade == 2.0
+            ));
+            assert_eq!(curve(&config), [(30.0, 0.2), (80.0, 1.0)]);
+
+            // the fan is enabled with the default source
+            let config = resolve(&[], &env[2..], None).unwrap();
+            assert_eq!(config.fan.unwrap().source, FanConfig::default().source);
         }
-    }
-    impl Scheduler for EveryDay {
-        fn get_next(&self, now: &mut scheduler::LazyNow) -> Next {
-            let now = now.now();
-            if now.time() < self.time {
-                // Unwrap is OK, now will never be over self.time.
-                Next::At(
-                    now.replace_time(self.time),
-                    self.common.get_command().into_inner(),
-                )
-            } else {
-                // Unwrap is OK, it's one day ahead!
-                Next::At(
-                    now.replace_time(self.time) + time::Duration::days(1),
-                    self.common.get_command().into_inner(),
-                )
-            }
+        #[test]
+        fn ledc_output() {
+            assert_eq!(
+                "ledc".parse(),
+                Ok(OutputConfig::Ledc {
+                    frequency: 25_000.0,
+                    resolution: 10
+                })
+            );
+            let config = resolve(&["--outputs", "ledc:50:14"], &[], None).unwrap();
+            assert_eq!(
+                config.outputs,
+                [OutputConfig::Ledc {
+                    frequency: 50.0,
+                    resolution: 14
+                }]
+            );
+            assert_eq!(
+                resolve(&["--outputs", "ledc:50:16"], &[], None).unwrap_err(),
+                ["the resolution of LEDC channel 0 has to be 1 to 14 bits, not 16"]
+            );
         }
-        fn advance(&mut self) -> Keep {
-            Keep::Keep
+        #[test]
+        fn servo_output() {
+            let hardware = OutputConfig::Hardware {
+                channel: 0,
+                frequency: 1000.0,
+                polarity: Polarity::Normal,
+            };
+            assert_eq!(
+                "servo:hardware:0".parse(),
+                Ok(OutputConfig::Servo {
+                    output: Box::new(hardware.clone()),
+                    min: 1000,
+                    max: 2000,
+                    reversed: false,
+                })
+            );
+            assert_eq!("servo:servo:hardware:0".parse::<OutputConfig>(), Err(()));
+
+            let file = r#"(outputs: [(
+    kind: "servo",
+    output: (kind: "hardware", channel: 0),
+    min: 500,
+    max: 2500,
+    reversed: true,
+)])"#;
+            let config = resolve(&[], &[], Some(file)).unwrap();
+            assert_eq!(
+                config.outputs,
+                [OutputConfig::Servo {
+                    output: Box::new(hardware),
+                    min: 500,
+                    max: 2500,
+                    reversed: true,
+                }]
+            );
+
+            let file = file.replace("2500", "25000");
+            assert_eq!(
+                resolve(&[], &[], Some(&file)).unwrap_err(),
+                [
+                    "the pulses of servo on PWM channel 0 have to be at most 20000 µs \
+                with min ≤ max, not 500-25000"
+                ]
+            );
         }
-        fn description(&self) -> &str {
-            self.common.description.as_str()
+        /// A servo can't be driven by an output with an unknown frequency.
+        #[test]
+        #[cfg(feature = "sysfs")]
+        fn servo_needs_frequency() {
+            let root = tempfile::tempdir().unwrap();
+            let backlight = root.path().join("backlight/lcd");
+            std::fs::create_dir_all(&backlight).unwrap();
+            std::fs::write(backlight.join("max_brightness"), "255").unwrap();
+            std::fs::write(backlight.join("brightness"), "0").unwrap();
+
+            let output: OutputConfig = "servo:backlight:lcd".parse().unwrap();
+            assert_eq!(
+                output.open(root.path()).err().unwrap(),
+                "servo on backlight lcd can't be set up: not supported by the output"
+            );
         }
-        fn kind(&self) -> &str {
-            "Every day at"
+        #[test]
+        fn override_errors() {
+            let errors = resolve(
+                &["--fan-curve", "30:0.2,80"],
+                &[("HTTPWM_DAY_TRANSITION", "(time: 60.0)")],
+                Some(FILE),
+            )
+            .unwrap_err();
+            assert_eq!(errors.len(), 2, "{:?}", errors);
+            assert!(errors[0].starts_with(
+                "HTTPWM_DAY_TRANSITION: expected a transition in the format of the config file: "
+            ));
+            assert_eq!(
+                errors[1],
+                "--fan-curve: expected TEMP:STRENGTH points like `45:0,70:1`, got \"80\""
+            );
+
+            let errors = resolve(&["--power-on", "off"], &[], None).unwrap_err();
+            assert!(errors[0].starts_with(
+                "--power-on: expected a power on action in the format of the config file: "
+            ));
+            // parsed, but invalid
+            let transition = TRANSITION.replace("60.0", "-1.0");
+            let errors = resolve(
+                &["--fan-curve=70:0,50:1", "--day-transition", &transition],
+                &[],
+                None,
+            )
+            .unwrap_err();
+            assert_eq!(
+                errors,
+                [
+                    "day_transition needs a known interpolation and a time of 0 or more",
+                    "the fan curve needs points with increasing temperatures \
+                    and strengths between 0 and 1",
+                ]
+            );
         }
     }
 }
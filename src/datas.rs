@@ -0,0 +1,619 @@
+//! Serializable representations of the types in this crate,
+//! as used in the save file and by the HTTP API.
+use crate::{
+    get_now, parse_time, registry, scheduler, weekday_to_lowercase_str, Capabilities, CatchUp,
+    ClockJump, ClonableCommand, Command, Effect, Next, Scene, SharedState, SleepTimer, Strength,
+    Transition, TransitionInterpolation, Weekday, DATE_TIME_FORMAT, SECOND_FORMAT,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use time::format_description::well_known::Rfc3339;
+
+#[derive(Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DayData {
+    day: String,
+    time: Option<String>,
+    /// The name of the week scheduler to change. If [`None`], the default is used.
+    #[serde(default)]
+    pub scheduler: Option<String>,
+}
+impl DayData {
+    pub fn parse(&self) -> Option<(Weekday, Option<time::Time>)> {
+        let day: Weekday = self.day.parse().ok()?;
+        let time = match self.time.as_ref() {
+            Some(time) => Some(parse_time(time)?),
+            None => None,
+        };
+        Some((day, time))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TransitionData {
+    from: f64,
+    to: f64,
+    time: f64,
+    interpolation: String,
+    extras: Vec<String>,
+}
+impl TransitionData {
+    /// Returns [`None`] if the interpolation is unknown or `time` is negative.
+    pub fn to_transition(&self) -> Option<Transition> {
+        if !self.time.is_finite() || self.time < 0.0 {
+            return None;
+        }
+        let from = Strength::new_clamped(self.from);
+        let to = Strength::new_clamped(self.to);
+        let time = Duration::from_secs_f64(self.time);
+
+        let interpolation = TransitionInterpolation::from_str(&self.interpolation, &self.extras)?;
+        Some(Transition {
+            from,
+            to,
+            time,
+            interpolation,
+        })
+    }
+
+    pub fn from_transition(transition: &Transition) -> Self {
+        let mut extras = Vec::with_capacity(4);
+
+        transition.interpolation.apply_extras(&mut extras);
+
+        Self {
+            from: Strength::clone(&transition.from).into_inner(),
+            to: Strength::clone(&transition.to).into_inner(),
+            time: transition.time.as_secs_f64(),
+            interpolation: transition.interpolation.as_str().to_string(),
+            extras,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StateData {
+    strength: f64,
+    /// The name of the week scheduler `days` and `transition` are from.
+    week_scheduler: String,
+    days: HashMap<String, Option<String>>,
+    transition: TransitionData,
+    catch_up: CatchUpData,
+    last_clock_jump: Option<ClockJumpData>,
+    sleep_timer: Option<SleepTimerStateData>,
+    active_scene: Option<String>,
+}
+impl StateData {
+    pub fn from_shared_state(state: &SharedState) -> Self {
+        let week_scheduler = state.ref_default_week_scheduler();
+        let mut days = HashMap::with_capacity(7);
+        let mut day = Weekday::Mon;
+        for _ in 0..7 {
+            days.insert(
+                weekday_to_lowercase_str(&day).to_string(),
+                week_scheduler
+                    .and_then(|scheduler| *scheduler.get(day))
+                    .map(|time| time.format(&SECOND_FORMAT).unwrap()),
+            );
+            day = day.succ();
+        }
+        let transition = week_scheduler
+            .map(|scheduler| Transition::clone(&scheduler.transition))
+            .unwrap_or_default();
+
+        Self {
+            strength: Strength::clone(state.get_strength()).into_inner(),
+            week_scheduler: state.get_default_week_scheduler().to_string(),
+            days,
+            transition: TransitionData::from_transition(&transition),
+            catch_up: CatchUpData::from_catch_up(state.get_catch_up()),
+            last_clock_jump: state.get_last_clock_jump().map(ClockJumpData::from_jump),
+            sleep_timer: state
+                .get_sleep_timer()
+                .map(|sleep_timer| SleepTimerStateData::new(sleep_timer, get_now())),
+            active_scene: state.get_active_scene().map(str::to_string),
+        }
+    }
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SceneData {
+    strength: f64,
+    /// In seconds.
+    #[serde(default)]
+    fade: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    effect: Option<EffectData>,
+}
+impl SceneData {
+    pub fn from_scene(scene: &Scene) -> Self {
+        Self {
+            strength: Strength::clone(&scene.strength).into_inner(),
+            fade: scene.fade.as_secs_f64(),
+            effect: scene.effect.as_ref().map(EffectData::from_effect),
+        }
+    }
+    pub fn to_scene(&self) -> Option<Scene> {
+        if !self.fade.is_finite() || self.fade < 0.0 {
+            return None;
+        }
+        let effect = match &self.effect {
+            Some(effect) => Some(effect.to_effect()?),
+            None => None,
+        };
+        Some(Scene {
+            strength: Strength::new_clamped(self.strength),
+            fade: Duration::from_secs_f64(self.fade),
+            effect,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SleepTimerData {
+    /// RFC 3339
+    end: String,
+    /// In seconds.
+    fade: f64,
+}
+impl SleepTimerData {
+    pub fn from_sleep_timer(sleep_timer: &SleepTimer) -> Self {
+        Self {
+            end: sleep_timer.end.format(&Rfc3339).unwrap(),
+            fade: sleep_timer.fade.as_secs_f64(),
+        }
+    }
+    pub fn to_sleep_timer(&self) -> Option<SleepTimer> {
+        if !self.fade.is_finite() || self.fade < 0.0 {
+            return None;
+        }
+        Some(SleepTimer {
+            end: time::OffsetDateTime::parse(&self.end, &Rfc3339).ok()?,
+            fade: Duration::from_secs_f64(self.fade),
+        })
+    }
+}
+/// The [`SleepTimer`] in `/get-state`.
+#[derive(Debug, Serialize)]
+pub struct SleepTimerStateData {
+    /// RFC 3339
+    end: String,
+    /// In seconds.
+    remaining: f64,
+    /// In seconds.
+    fade: f64,
+}
+impl SleepTimerStateData {
+    pub fn new(sleep_timer: &SleepTimer, now: time::OffsetDateTime) -> Self {
+        Self {
+            end: sleep_timer.end.format(&Rfc3339).unwrap(),
+            remaining: sleep_timer.remaining(now).as_secs_f64(),
+            fade: sleep_timer.fade.as_secs_f64(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClockJumpData {
+    /// RFC 3339
+    at: String,
+    /// In seconds. Negative if the clock went backwards.
+    offset: f64,
+}
+impl ClockJumpData {
+    pub fn from_jump(jump: &ClockJump) -> Self {
+        Self {
+            at: jump.at.format(&Rfc3339).unwrap(),
+            offset: jump.offset.as_seconds_f64(),
+        }
+    }
+}
+
+/// What to do when the daemon starts.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PowerOnData {
+    /// Transitions played in order, before [`PowerOnData::level`] is applied.
+    #[serde(default)]
+    pub sequence: Vec<TransitionData>,
+    pub level: PowerOnLevelData,
+    /// If the transition running when the daemon stopped is resumed.
+    #[serde(default = "default_true")]
+    pub resume_transition: bool,
+}
+impl PowerOnData {
+    /// Returns [`None`] if any part is invalid.
+    pub fn validate(&self) -> Option<()> {
+        for transition in &self.sequence {
+            transition.to_transition()?;
+        }
+        let fade = match self.level {
+            PowerOnLevelData::Restore { fade } | PowerOnLevelData::Fixed { fade, .. } => fade,
+            PowerOnLevelData::Off => 0.0,
+        };
+        if !fade.is_finite() || fade < 0.0 {
+            return None;
+        }
+        Some(())
+    }
+    /// The transitions of [`PowerOnData::sequence`]. Invalid ones are skipped.
+    pub fn sequence(&self) -> Vec<Transition> {
+        self.sequence
+            .iter()
+            .filter_map(TransitionData::to_transition)
+            .collect()
+    }
+}
+impl Default for PowerOnData {
+    fn default() -> Self {
+        // a short blink, to show we've started
+        let blink = Transition {
+            from: Strength::new(0.0),
+            to: Strength::new(1.0),
+            time: Duration::from_secs(1),
+            interpolation: TransitionInterpolation::SineToAndBack(0.5),
+        };
+        Self {
+            sequence: vec![TransitionData::from_transition(&blink)],
+            level: PowerOnLevelData::Restore { fade: 0.0 },
+            resume_transition: true,
+        }
+    }
+}
+/// The strength to set when the daemon starts.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "mode", rename_all = "kebab-case")]
+pub enum PowerOnLevelData {
+    /// The strength when the daemon stopped.
+    Restore {
+        /// In seconds.
+        #[serde(default)]
+        fade: f64,
+    },
+    Off,
+    Fixed {
+        strength: f64,
+        /// In seconds.
+        #[serde(default)]
+        fade: f64,
+    },
+}
+impl PowerOnLevelData {
+    /// `saved` is the strength when the daemon stopped.
+    pub fn to_command(&self, saved: Option<f64>) -> Option<Command> {
+        let (strength, fade) = match self {
+            Self::Restore { fade } => (saved?, *fade),
+            Self::Off => (0.0, 0.0),
+            Self::Fixed { strength, fade } => (*strength, *fade),
+        };
+        let strength = Strength::new_clamped(strength);
+        if fade.is_finite() && fade > 0.0 {
+            Some(Command::FadeTo(
+                strength,
+                Duration::from_secs_f64(fade),
+                TransitionInterpolation::Sine,
+            ))
+        } else {
+            Some(Command::Set(strength))
+        }
+    }
+}
+fn default_true() -> bool {
+    true
+}
+
+/// The [`CatchUp`] policy.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(tag = "policy", rename_all = "kebab-case")]
+pub enum CatchUpData {
+    Skip,
+    RunLate {
+        /// In seconds.
+        grace: f64,
+    },
+}
+impl CatchUpData {
+    pub fn from_catch_up(catch_up: CatchUp) -> Self {
+        match catch_up {
+            CatchUp::Skip => Self::Skip,
+            CatchUp::RunLate(grace) => Self::RunLate {
+                grace: grace.as_secs_f64(),
+            },
+        }
+    }
+    pub fn to_catch_up(self) -> CatchUp {
+        match self {
+            Self::Skip => CatchUp::Skip,
+            Self::RunLate { grace } => CatchUp::RunLate(Duration::from_secs_f64(grace.max(0.0))),
+        }
+    }
+}
+impl Default for CatchUpData {
+    fn default() -> Self {
+        Self::from_catch_up(CatchUp::default())
+    }
+}
+fn default_interpolation() -> String {
+    TransitionInterpolation::Sine.as_str().to_string()
+}
+
+/// What a scheduler does when it's triggered.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum ActionData {
+    Transition(TransitionData),
+    Set {
+        strength: f64,
+    },
+    /// Fade from the current strength.
+    FadeTo {
+        strength: f64,
+        time: f64,
+        #[serde(default = "default_interpolation")]
+        interpolation: String,
+        #[serde(default)]
+        extras: Vec<String>,
+    },
+    Effect(EffectData),
+    ClearEffect,
+    Scene {
+        name: String,
+    },
+}
+impl ActionData {
+    pub fn to_command(&self) -> Option<ClonableCommand> {
+        let command = match self {
+            Self::Transition(transition) => Command::SetTransition(transition.to_transition()?),
+            Self::Set { strength } => Command::Set(Strength::new_clamped(*strength)),
+            Self::FadeTo {
+                strength,
+                time,
+                interpolation,
+                extras,
+            } => {
+                if !time.is_finite() || *time < 0.0 {
+                    return None;
+                }
+                Command::FadeTo(
+                    Strength::new_clamped(*strength),
+                    Duration::from_secs_f64(*time),
+                    TransitionInterpolation::from_str(interpolation, extras)?,
+                )
+            }
+            Self::Effect(effect) => effect.clone().into_command()?,
+            Self::ClearEffect => Command::ClearEffect,
+            Self::Scene { name } => Command::ActivateScene(name.clone()),
+        };
+        ClonableCommand::new(command)
+    }
+    /// Returns [`None`] if `command` isn't something a scheduler can do.
+    pub fn from_command(command: &Command) -> Option<Self> {
+        let data = match command {
+            Command::SetTransition(transition) => {
+                Self::Transition(TransitionData::from_transition(transition))
+            }
+            Command::Set(strength) => Self::Set {
+                strength: Strength::clone(strength).into_inner(),
+            },
+            Command::FadeTo(strength, time, interpolation) => {
+                let mut extras = Vec::with_capacity(4);
+                interpolation.apply_extras(&mut extras);
+                Self::FadeTo {
+                    strength: Strength::clone(strength).into_inner(),
+                    time: time.as_secs_f64(),
+                    interpolation: interpolation.as_str().to_string(),
+                    extras,
+                }
+            }
+            Command::SetEffect(effect) => Self::Effect(EffectData::from_effect(effect)),
+            Command::ClearEffect => Self::ClearEffect,
+            Command::ActivateScene(name) => Self::Scene { name: name.clone() },
+            _ => return None,
+        };
+        Some(data)
+    }
+}
+
+/// An occurrence of a scheduler, returned by `/get-timeline`.
+#[derive(Debug, Serialize)]
+pub struct TimelineEntry {
+    /// RFC 3339
+    time: String,
+    scheduler: String,
+    /// [`None`] if the action can't be represented.
+    action: Option<ActionData>,
+}
+impl TimelineEntry {
+    pub fn new(date_time: time::OffsetDateTime, scheduler: String, command: &Command) -> Self {
+        Self {
+            time: date_time.format(&Rfc3339).unwrap(),
+            scheduler,
+            action: ActionData::from_command(command),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AddSchedulerData {
+    /// The name of the kind in the [`registry::Registry`].
+    pub kind: String,
+    pub name: String,
+    pub description: String,
+    /// The parameters of [`AddSchedulerData::kind`].
+    /// See [`registry::Registry::get_parameters`].
+    #[serde(default)]
+    pub params: serde_json::Value,
+    /// Superseded by [`AddSchedulerData::params`]; used if it's null.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+    /// Superseded by [`AddSchedulerData::params`]; used if it's null.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extras: Vec<String>,
+    /// Superseded by [`AddSchedulerData::action`]; used if it's [`None`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transition: Option<TransitionData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<ActionData>,
+}
+impl AddSchedulerData {
+    /// Moves the legacy fields to [`AddSchedulerData::params`] and [`AddSchedulerData::action`].
+    pub fn normalize(&mut self) {
+        if let Some(transition) = self.transition.take() {
+            self.action
+                .get_or_insert(ActionData::Transition(transition));
+        }
+        if let Some(time) = self.time.take() {
+            if self.params.is_null() {
+                let mut params = serde_json::Map::new();
+                params.insert("time".into(), time.into());
+                let extra = match self.kind.as_str() {
+                    "at" => Some("date"),
+                    "every-week" => Some("day"),
+                    _ => None,
+                };
+                if let (Some(key), Some(value)) = (extra, self.extras.first()) {
+                    params.insert(key.into(), value.clone().into());
+                }
+                self.params = params.into();
+            }
+        }
+        self.extras.clear();
+    }
+    /// Normalizes `self` and gets the command adding the scheduler.
+    ///
+    /// The parameters are normalized by the `registry`.
+    pub fn to_command(
+        &mut self,
+        allow_past: bool,
+        registry: &registry::Registry,
+    ) -> Result<Command, registry::Error> {
+        self.normalize();
+        let command = self
+            .action
+            .as_ref()
+            .and_then(ActionData::to_command)
+            .ok_or_else(|| registry::Error::Invalid("invalid action".into()))?;
+        let context = registry::Context {
+            description: self.description.clone(),
+            command,
+            allow_past,
+        };
+        let (scheduler, params) =
+            registry.build(&self.kind, std::mem::take(&mut self.params), context)?;
+        self.params = params;
+        Ok(Command::AddReplaceScheduler(self.name.clone(), scheduler))
+    }
+}
+/// Parameters of [`extra_schedulers::At`].
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AtParams {
+    pub time: String,
+    pub date: String,
+}
+/// Parameters of [`extra_schedulers::EveryWeek`].
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EveryWeekParams {
+    pub time: String,
+    pub day: String,
+}
+/// Parameters of [`extra_schedulers::EveryDay`].
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EveryDayParams {
+    pub time: String,
+}
+#[derive(Debug, Serialize)]
+pub struct SchedulerData {
+    name: String,
+    description: String,
+    kind: String,
+    next_occurrence: String,
+    enabled: bool,
+    disabled_until: Option<String>,
+}
+impl SchedulerData {
+    /// `name` has to be a scheduler in `state`.
+    pub fn from_state(state: &SharedState, name: String, now: &mut scheduler::LazyNow) -> Self {
+        let scheduler = &state.ref_schedulers()[&name];
+        let dur = state.get_next(&name, now);
+
+        let next_occurrence = match dur {
+            Next::At(date_time, _) => {
+                let dur = date_time - now.now();
+                if dur.whole_days() > 0 {
+                    (now.now() + dur).format(&DATE_TIME_FORMAT).unwrap()
+                } else if dur.whole_hours() > 0 {
+                    format!("In {} hours", dur.whole_hours())
+                } else if dur.whole_minutes() > 0 {
+                    format!("In {} minutes", dur.whole_minutes())
+                } else {
+                    format!("In {} seconds", dur.whole_seconds())
+                }
+            }
+            Next::Unknown => "unknown".to_string(),
+        };
+
+        let disabled_until = state
+            .ref_disabled_schedulers()
+            .get(&name)
+            .copied()
+            .flatten()
+            .map(|until| until.format(&DATE_TIME_FORMAT).unwrap());
+
+        Self {
+            description: scheduler.description().to_string(),
+            kind: scheduler.kind().to_string(),
+            next_occurrence,
+            enabled: state.is_scheduler_enabled(&name),
+            disabled_until,
+            name,
+        }
+    }
+}
+
+/// The [`Capabilities`] of the output. The ranges are `[min, max]`.
+#[derive(Debug, Serialize, Clone)]
+pub struct OutputData {
+    /// In Hz.
+    frequency: Option<f64>,
+    frequency_range: Option<(f64, f64)>,
+    /// In bits.
+    resolution: Option<u32>,
+    resolution_range: Option<(u32, u32)>,
+}
+impl OutputData {
+    pub fn from_capabilities(capabilities: &Capabilities) -> Self {
+        Self {
+            frequency: capabilities.frequency,
+            frequency_range: capabilities.frequency_range,
+            resolution: capabilities.resolution,
+            resolution_range: capabilities.resolution_range,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EffectData {
+    pub kind: String,
+    pub nums: Vec<f64>,
+}
+impl EffectData {
+    pub fn from_effect(effect: &Effect) -> Self {
+        match effect {
+            Effect::Radar { offset, speed } => Self {
+                kind: "radar".to_string(),
+                nums: vec![*offset, *speed],
+            },
+        }
+    }
+    pub fn to_effect(&self) -> Option<Effect> {
+        match self.kind.as_str() {
+            "radar" => {
+                let offset = *self.nums.first()?;
+                let speed = *self.nums.get(1)?;
+                Some(Effect::Radar { offset, speed })
+            }
+            _ => None,
+        }
+    }
+    pub fn into_command(self) -> Option<Command> {
+        self.to_effect().map(Command::SetEffect)
+    }
+}
@@ -0,0 +1,322 @@
+//! [`Scheduler`]s which run a command once or repeatedly,
+//! and a [`registry::Registry`] of them.
+use crate::scheduler::{self, Keep};
+use crate::{
+    datas, has_occurred, parse_time, primitive_to_tz, registry, ClonableCommand, Next, Scheduler,
+    Weekday, DATE_FORMAT,
+};
+use time::OffsetDateTime;
+
+/// Creates a [`registry::Registry`] with the kinds of this module.
+pub fn registry() -> registry::Registry {
+    use registry::{Error, Parameter, ParameterKind};
+
+    fn time(time: &str) -> Result<time::Time, Error> {
+        parse_time(time).ok_or_else(|| Error::Invalid(format!("invalid time {:?}", time)))
+    }
+
+    let mut registry = registry::Registry::new();
+    registry.register(
+        "at",
+        vec![
+            Parameter::new("time", ParameterKind::Time),
+            Parameter::new("date", ParameterKind::Date),
+        ],
+        |params: &datas::AtParams, context| {
+            let date = time::Date::parse(&params.date, &DATE_FORMAT)
+                .map_err(|_| Error::Invalid(format!("invalid date {:?}", params.date)))?;
+            let date_time = primitive_to_tz(date.with_time(time(&params.time)?));
+            if has_occurred(date_time) && !context.allow_past {
+                return Err(Error::Invalid("the time has already passed".into()));
+            }
+            Ok(Box::new(At::new(Common::from_context(context), date_time)))
+        },
+    );
+    registry.register(
+        "every-week",
+        vec![
+            Parameter::new("time", ParameterKind::Time),
+            Parameter::new("day", ParameterKind::Weekday),
+        ],
+        |params: &datas::EveryWeekParams, context| {
+            let day = params
+                .day
+                .parse()
+                .map_err(|_| Error::Invalid(format!("invalid weekday {:?}", params.day)))?;
+            Ok(Box::new(EveryWeek::new(
+                Common::from_context(context),
+                time(&params.time)?,
+                day,
+            )))
+        },
+    );
+    registry.register(
+        "every-day",
+        vec![Parameter::new("time", ParameterKind::Time)],
+        |params: &datas::EveryDayParams, context| {
+            Ok(Box::new(EveryDay::new(
+                Common::from_context(context),
+                time(&params.time)?,
+            )))
+        },
+    );
+    registry
+}
+
+pub(crate) fn get_next_day<F: Fn(Weekday) -> Option<time::Time>>(
+    from: Weekday,
+    get: F,
+) -> Option<(time::Time, u8)> {
+    let mut day = from;
+
+    for passed in 0..7 {
+        day = day.succ();
+        let time = get(day);
+        if time.is_some() {
+            return time.map(|t| (t, passed + 1));
+        }
+    }
+    None
+}
+
+#[derive(Debug)]
+pub struct Common {
+    description: String,
+    command: ClonableCommand,
+}
+impl Common {
+    /// Returns `Err` when command is not clonable
+    pub fn new(description: String, command: ClonableCommand) -> Self {
+        Self {
+            description,
+            command,
+        }
+    }
+    pub fn from_context(context: registry::Context) -> Self {
+        Self::new(context.description, context.command)
+    }
+    pub fn get_command(&self) -> ClonableCommand {
+        // Ok, since it's guaranteed the command in `Common` is clonable.
+        ClonableCommand::clone(&self.command)
+    }
+}
+
+#[derive(Debug)]
+pub struct At {
+    common: Common,
+    moment: OffsetDateTime,
+}
+impl At {
+    pub fn new(common: Common, moment: OffsetDateTime) -> Self {
+        Self { common, moment }
+    }
+}
+impl Scheduler for At {
+    fn get_next(&self, _: &mut scheduler::LazyNow) -> Next {
+        Next::At(self.moment, self.common.get_command().into_inner())
+    }
+    fn advance(&mut self) -> Keep {
+        Keep::Remove
+    }
+    fn description(&self) -> &str {
+        self.common.description.as_str()
+    }
+    fn kind(&self) -> &str {
+        "At"
+    }
+}
+#[derive(Debug)]
+pub struct EveryWeek {
+    common: Common,
+    time: time::Time,
+    day: Weekday,
+}
+impl EveryWeek {
+    pub fn new(common: Common, time: time::Time, day: Weekday) -> Self {
+        Self { common, time, day }
+    }
+}
+impl Scheduler for EveryWeek {
+    fn get_next(&self, now: &mut scheduler::LazyNow) -> Next {
+        let now = now.now();
+        if self.day == Weekday::from(now.weekday()) && now.time() < self.time {
+            Next::At(
+                primitive_to_tz(now.date().with_time(self.time)),
+                self.common.get_command().into_inner(),
+            )
+        } else {
+            // Unwrap is ok, we must have one day containing a date.
+            let (time, offset): (time::Time, _) = get_next_day(now.weekday().into(), |day| {
+                if day == self.day {
+                    Some(self.time)
+                } else {
+                    None
+                }
+            })
+            .unwrap();
+            Next::At(
+                primitive_to_tz(now.date().with_time(time) + time::Duration::days(offset as i64)),
+                self.common.get_command().into_inner(),
+            )
+        }
+    }
+    fn advance(&mut self) -> Keep {
+        Keep::Keep
+    }
+    fn description(&self) -> &str {
+        self.common.description.as_str()
+    }
+    fn kind(&self) -> &str {
+        "Every week at"
+    }
+}
+#[derive(Debug)]
+pub struct EveryDay {
+    common: Common,
+    time: time::Time,
+}
+impl EveryDay {
+    pub fn new(common: Common, time: time::Time) -> Self {
+        Self { common, time }
+    }
+}
+impl Scheduler for EveryDay {
+    fn get_next(&self, now: &mut scheduler::LazyNow) -> Next {
+        let now = now.now();
+        // the offset of `now` might be from before a DST change
+        let date = if now.time() < self.time {
+            now.date()
+        } else {
+            now.date() + time::Duration::days(1)
+        };
+        Next::At(
+            primitive_to_tz(date.with_time(self.time)),
+            self.common.get_command().into_inner(),
+        )
+    }
+    fn advance(&mut self) -> Keep {
+        Keep::Keep
+    }
+    fn description(&self) -> &str {
+        self.common.description.as_str()
+    }
+    fn kind(&self) -> &str {
+        "Every day at"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Command;
+    use time::macros::{date, time};
+
+    fn local(date: time::Date, time: time::Time) -> OffsetDateTime {
+        primitive_to_tz(date.with_time(time))
+    }
+    fn common() -> Common {
+        Common::new(
+            String::new(),
+            ClonableCommand::new(Command::Toggle).unwrap(),
+        )
+    }
+    fn timeline(scheduler: &dyn Scheduler, from: OffsetDateTime) -> Vec<OffsetDateTime> {
+        scheduler
+            .occurrences(from)
+            .take(3)
+            .map(|(date_time, _)| date_time)
+            .collect()
+    }
+
+    #[test]
+    fn at_timeline() {
+        let moment = local(date!(2030 - 01 - 08), time!(7:00));
+        let at = At::new(common(), moment);
+        assert_eq!(
+            timeline(&at, local(date!(2030 - 01 - 07), time!(0:00))),
+            [moment]
+        );
+        assert!(timeline(&at, local(date!(2030 - 01 - 08), time!(7:01))).is_empty());
+    }
+    #[test]
+    fn every_day_timeline() {
+        let every_day = EveryDay::new(common(), time!(7:00));
+        assert_eq!(
+            timeline(&every_day, local(date!(2030 - 01 - 07), time!(12:00))),
+            [
+                local(date!(2030 - 01 - 08), time!(7:00)),
+                local(date!(2030 - 01 - 09), time!(7:00)),
+                local(date!(2030 - 01 - 10), time!(7:00)),
+            ]
+        );
+        assert_eq!(
+            timeline(&every_day, local(date!(2030 - 01 - 07), time!(6:00)))[0],
+            local(date!(2030 - 01 - 07), time!(7:00))
+        );
+    }
+    #[test]
+    fn every_week_timeline() {
+        // 2030-01-09 is a Wednesday
+        let every_week = EveryWeek::new(common(), time!(7:00), Weekday::Wed);
+        assert_eq!(
+            timeline(&every_week, local(date!(2030 - 01 - 09), time!(6:00))),
+            [
+                local(date!(2030 - 01 - 09), time!(7:00)),
+                local(date!(2030 - 01 - 16), time!(7:00)),
+                local(date!(2030 - 01 - 23), time!(7:00)),
+            ]
+        );
+        assert_eq!(
+            timeline(&every_week, local(date!(2030 - 01 - 09), time!(8:00)))[0],
+            local(date!(2030 - 01 - 16), time!(7:00))
+        );
+    }
+    /// `from` has another offset than the local time, as after a DST change.
+    #[test]
+    #[cfg(not(feature = "auto-tz"))]
+    fn occurrences_follow_local_time() {
+        let summer = time::UtcOffset::from_hms(2, 0, 0).unwrap();
+        let from = local(date!(2030 - 01 - 07), time!(12:00)).to_offset(summer);
+        let every_day = EveryDay::new(common(), time!(7:00));
+        assert_eq!(
+            timeline(&every_day, from),
+            [
+                local(date!(2030 - 01 - 08), time!(7:00)),
+                local(date!(2030 - 01 - 09), time!(7:00)),
+                local(date!(2030 - 01 - 10), time!(7:00)),
+            ]
+        );
+        let every_week = EveryWeek::new(common(), time!(7:00), Weekday::Tue);
+        assert_eq!(
+            timeline(&every_week, from)[..2],
+            [
+                local(date!(2030 - 01 - 08), time!(7:00)),
+                local(date!(2030 - 01 - 15), time!(7:00)),
+            ]
+        );
+    }
+    /// The occurrences keep the local time across the DST changes of the system timezone.
+    #[test]
+    #[cfg(feature = "auto-tz")]
+    fn occurrences_across_dst() {
+        use time_tz::OffsetDateTimeExt;
+
+        let tz = match crate::get_timezone() {
+            Some(tz) => tz,
+            None => return,
+        };
+        let from = local(date!(2030 - 01 - 01), time!(12:00));
+        let every_day = EveryDay::new(common(), time!(7:00));
+        let every_week = EveryWeek::new(common(), time!(7:00), Weekday::Sun);
+        let mut week = crate::scheduler::WeekScheduler::empty(crate::Transition {
+            time: std::time::Duration::ZERO,
+            ..Default::default()
+        });
+        week.sun = Some(time!(7:00));
+        for scheduler in [&every_day as &dyn Scheduler, &every_week, &week] {
+            for (date_time, _) in scheduler.occurrences(from).take(366) {
+                assert_eq!(date_time.to_timezone(tz).time(), time!(7:00));
+            }
+        }
+    }
+}
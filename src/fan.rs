@@ -0,0 +1,414 @@
+//! Drive an output (e.g. a fan) by a temperature.
+//!
+//! [`FanControl`] reads a [`TemperatureSource`] at an interval, maps the temperature through a
+//! [`FanCurve`] and sets the output to the highest of that and the strength from the schedules.
+//! Keep the schedules at `0` to only follow the temperature.
+use crate::{Capabilities, OutputError, Strength, VariableOut};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Where the thermal zones of a Linux system are, each with a file `temp`.
+pub const THERMAL_ROOT: &str = "/sys/class/thermal";
+
+/// Where to read the temperature from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemperatureSource {
+    /// A file with the temperature in millidegrees, like
+    /// `/sys/class/thermal/thermal_zone0/temp` and `/sys/class/hwmon/hwmon0/temp1_input`.
+    File(PathBuf),
+    /// A shell command which prints the temperature in degrees, e.g. `vcgencmd measure_temp`.
+    /// The first number in the output is used.
+    Command(String),
+}
+impl TemperatureSource {
+    /// The thermal zone `zone` in [`THERMAL_ROOT`].
+    pub fn thermal_zone(zone: u32) -> Self {
+        Self::File(PathBuf::from(format!(
+            "{}/thermal_zone{}/temp",
+            THERMAL_ROOT, zone
+        )))
+    }
+    /// Reads the temperature, in degrees.
+    pub fn read(&self) -> io::Result<f64> {
+        let invalid = |output: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no temperature in {:?}", output.trim()),
+            )
+        };
+        match self {
+            Self::File(path) => {
+                let content = std::fs::read_to_string(path).map_err(|err| {
+                    io::Error::new(
+                        err.kind(),
+                        format!("failed to read {}: {}", path.display(), err),
+                    )
+                })?;
+                let millidegrees: f64 = content.trim().parse().map_err(|_| invalid(&content))?;
+                Ok(millidegrees / 1000.0)
+            }
+            Self::Command(command) => {
+                let output = std::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .output()?;
+                if !output.status.success() {
+                    return Err(io::Error::other(format!(
+                        "{:?} failed: {}",
+                        command, output.status
+                    )));
+                }
+                let output = String::from_utf8_lossy(&output.stdout);
+                // `temp=48.3'C`
+                output
+                    .split(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+                    .find_map(|s| s.parse().ok())
+                    .ok_or_else(|| invalid(&output))
+            }
+        }
+    }
+}
+
+/// Maps a temperature to a [`Strength`], by interpolating between points.
+///
+/// Below the first point, the strength of the first point is used,
+/// and above the last, the strength of the last.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FanCurve {
+    points: Vec<(f64, Strength)>,
+}
+impl FanCurve {
+    /// `points` are `(temperature, strength)`.
+    ///
+    /// Returns [`None`] if `points` is empty, the temperatures aren't increasing
+    /// or a strength isn't between `0` and `1`.
+    pub fn new(points: impl IntoIterator<Item = (f64, f64)>) -> Option<Self> {
+        let points = points
+            .into_iter()
+            .map(|(temperature, strength)| {
+                if temperature.is_finite() && (0.0..=1.0).contains(&strength) {
+                    Some((temperature, Strength::new(strength)))
+                } else {
+                    None
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if points.is_empty() || points.windows(2).any(|w| w[0].0 >= w[1].0) {
+            return None;
+        }
+        Some(Self { points })
+    }
+    pub fn points(&self) -> impl Iterator<Item = (f64, Strength)> + '_ {
+        self.points.iter().copied()
+    }
+    pub fn strength(&self, temperature: f64) -> Strength {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if temperature <= first.0 {
+            return first.1;
+        }
+        if temperature >= last.0 {
+            return last.1;
+        }
+        let i = self
+            .points
+            .iter()
+            .position(|(t, _)| *t > temperature)
+            .expect("temperature is below the last point");
+        let (from, to) = (self.points[i - 1], self.points[i]);
+        let progress = (temperature - from.0) / (to.0 - from.0);
+        let (from_strength, to_strength) = (from.1.into_inner(), to.1.into_inner());
+        Strength::new_clamped(from_strength + (to_strength - from_strength) * progress)
+    }
+}
+
+#[derive(Debug)]
+struct State<T> {
+    output: T,
+    /// The strength from the schedules.
+    scheduled: Option<Strength>,
+    /// The strength from the temperature.
+    cooling: Strength,
+    /// If the output is enabled.
+    enabled: bool,
+    temperature: Option<f64>,
+    stop: bool,
+}
+impl<T: VariableOut> State<T> {
+    fn apply(&mut self) {
+        let scheduled = self.scheduled.map_or(0.0, Strength::into_inner);
+        let strength = scheduled.max(self.cooling.into_inner());
+        if strength == 0.0 {
+            if self.enabled {
+                self.output.set(Strength::new(0.0));
+                self.output.disable();
+                self.enabled = false;
+            }
+            return;
+        }
+        if !self.enabled {
+            self.output.enable();
+            self.enabled = true;
+        }
+        self.output.set(Strength::new(strength));
+    }
+}
+
+/// Sets `output` to the highest of the strength from the schedules and the [`FanCurve`].
+///
+/// A thread reads the temperature at an interval. If it can't be read, the fan runs at full strength.
+/// The curve is followed when the temperature rises, but it has to fall `hysteresis` degrees
+/// before the fan slows down, so it doesn't keep changing speed.
+#[derive(Debug)]
+pub struct FanControl<T> {
+    state: Arc<(Mutex<State<T>>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+impl<T: VariableOut + Send + 'static> FanControl<T> {
+    /// Starts reading `source` every `interval`.
+    pub fn new(
+        output: T,
+        source: TemperatureSource,
+        curve: FanCurve,
+        hysteresis: f64,
+        interval: Duration,
+    ) -> Self {
+        let state = Arc::new((
+            Mutex::new(State {
+                output,
+                scheduled: None,
+                cooling: Strength::new(0.0),
+                enabled: false,
+                temperature: None,
+                stop: false,
+            }),
+            Condvar::new(),
+        ));
+        let thread_state = Arc::clone(&state);
+        let handle = thread::spawn(move || {
+            let (state, condvar) = &*thread_state;
+            // the temperature the curve is evaluated at
+            let mut effective: Option<f64> = None;
+            loop {
+                let temperature = source.read();
+                let mut state = state.lock().unwrap();
+                if state.stop {
+                    break;
+                }
+                state.cooling = match temperature {
+                    Ok(temperature) => {
+                        let current = match effective {
+                            Some(effective) if temperature < effective => {
+                                effective.min(temperature + hysteresis)
+                            }
+                            _ => temperature,
+                        };
+                        effective = Some(current);
+                        state.temperature = Some(temperature);
+                        curve.strength(current)
+                    }
+                    Err(err) => {
+                        println!(
+                            "Failed to read temperature, running at full strength: {}",
+                            err
+                        );
+                        state.temperature = None;
+                        Strength::new(1.0)
+                    }
+                };
+                state.apply();
+                let state = condvar.wait_timeout(state, interval).unwrap().0;
+                if state.stop {
+                    break;
+                }
+            }
+        });
+        Self {
+            state,
+            handle: Some(handle),
+        }
+    }
+}
+impl<T> FanControl<T> {
+    /// The last temperature read, in degrees. [`None`] if it couldn't be read.
+    pub fn temperature(&self) -> Option<f64> {
+        self.state.0.lock().unwrap().temperature
+    }
+}
+impl<T: VariableOut> VariableOut for FanControl<T> {
+    fn set(&mut self, value: Strength) {
+        let mut state = self.state.0.lock().unwrap();
+        state.scheduled = Some(value);
+        state.apply();
+    }
+    fn enable(&mut self) {}
+    fn disable(&mut self) {
+        let mut state = self.state.0.lock().unwrap();
+        state.scheduled = None;
+        state.apply();
+    }
+    fn prepare(&mut self) {
+        self.state.0.lock().unwrap().output.prepare();
+    }
+    fn capabilities(&self) -> Capabilities {
+        self.state.0.lock().unwrap().output.capabilities()
+    }
+    fn set_frequency(&mut self, frequency: f64) -> Result<(), OutputError> {
+        self.state.0.lock().unwrap().output.set_frequency(frequency)
+    }
+    fn set_resolution(&mut self, bits: u32) -> Result<(), OutputError> {
+        self.state.0.lock().unwrap().output.set_resolution(bits)
+    }
+}
+impl<T> Drop for FanControl<T> {
+    fn drop(&mut self) {
+        let (state, condvar) = &*self.state;
+        state.lock().unwrap().stop = true;
+        condvar.notify_one();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn read_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("temp");
+        let source = TemperatureSource::File(path.clone());
+        assert_eq!(source.read().unwrap_err().kind(), io::ErrorKind::NotFound);
+        fs::write(&path, "48300\n").unwrap();
+        assert!(close(source.read().unwrap(), 48.3));
+        fs::write(&path, "-5000").unwrap();
+        assert!(close(source.read().unwrap(), -5.0));
+        for garbage in ["", "hot", "48.3'C"] {
+            fs::write(&path, garbage).unwrap();
+            assert_eq!(
+                source.read().unwrap_err().kind(),
+                io::ErrorKind::InvalidData
+            );
+        }
+    }
+    #[test]
+    fn read_command() {
+        let source = |command: &str| TemperatureSource::Command(command.to_string());
+        assert!(close(source("echo \"temp=48.3'C\"").read().unwrap(), 48.3));
+        assert!(close(source("echo -4").read().unwrap(), -4.0));
+        assert_eq!(
+            source("echo none").read().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert!(source("echo 40; exit 1").read().is_err());
+    }
+    #[test]
+    fn curve_rejections() {
+        assert!(FanCurve::new([]).is_none());
+        assert!(FanCurve::new([(50.0, 0.5), (40.0, 1.0)]).is_none());
+        assert!(FanCurve::new([(50.0, 0.5), (50.0, 1.0)]).is_none());
+        assert!(FanCurve::new([(50.0, 1.5)]).is_none());
+        assert!(FanCurve::new([(50.0, -0.1)]).is_none());
+        assert!(FanCurve::new([(50.0, f64::NAN)]).is_none());
+        assert!(FanCurve::new([(f64::NAN, 0.5)]).is_none());
+        assert!(FanCurve::new([(f64::INFINITY, 0.5)]).is_none());
+        assert!(FanCurve::new([(50.0, 0.5)]).is_some());
+    }
+    #[test]
+    fn curve_strength() {
+        let curve = FanCurve::new([(40.0, 0.2), (60.0, 0.6), (80.0, 1.0)]).unwrap();
+        for (temperature, strength) in [
+            (-10.0, 0.2),
+            (40.0, 0.2),
+            (50.0, 0.4),
+            (60.0, 0.6),
+            (70.0, 0.8),
+            (80.0, 1.0),
+            (200.0, 1.0),
+        ] {
+            let got = curve.strength(temperature).into_inner();
+            assert!(close(got, strength), "{} gave {}", temperature, got);
+        }
+        let single = FanCurve::new([(50.0, 0.5)]).unwrap();
+        assert!(close(single.strength(0.0).into_inner(), 0.5));
+        assert!(close(single.strength(100.0).into_inner(), 0.5));
+    }
+
+    /// Records if it's enabled and the last strength.
+    #[derive(Debug, Clone, Default)]
+    struct Recorder(Arc<Mutex<(bool, f64)>>);
+    impl VariableOut for Recorder {
+        fn set(&mut self, value: Strength) {
+            self.0.lock().unwrap().1 = value.into_inner();
+        }
+        fn enable(&mut self) {
+            self.0.lock().unwrap().0 = true;
+        }
+        fn disable(&mut self) {
+            self.0.lock().unwrap().0 = false;
+        }
+        fn prepare(&mut self) {}
+        fn capabilities(&self) -> Capabilities {
+            Capabilities::default()
+        }
+    }
+
+    #[test]
+    fn control_hysteresis() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("temp");
+        fs::write(&path, "50000").unwrap();
+        let output = Recorder::default();
+        let mut control = FanControl::new(
+            output.clone(),
+            TemperatureSource::File(path.clone()),
+            FanCurve::new([(40.0, 0.0), (60.0, 1.0)]).unwrap(),
+            5.0,
+            Duration::from_millis(10),
+        );
+        let set = |millidegrees: &str| {
+            fs::write(&path, millidegrees).unwrap();
+            thread::sleep(Duration::from_millis(60));
+            *output.0.lock().unwrap()
+        };
+        let strength = |expected: f64, (enabled, strength): (bool, f64)| {
+            assert!(enabled);
+            assert!(close(strength, expected), "{} isn't {}", strength, expected);
+        };
+
+        strength(0.5, set("50000"));
+        assert_eq!(control.temperature(), Some(50.0));
+        // falling less than the hysteresis keeps the speed
+        strength(0.5, set("48000"));
+        assert_eq!(control.temperature(), Some(48.0));
+        strength(0.45, set("44000"));
+        // rising follows the curve
+        strength(0.75, set("55000"));
+        strength(0.75, set("53000"));
+        strength(0.65, set("48000"));
+
+        // the highest of the schedules and the temperature
+        control.set(Strength::new(0.9));
+        strength(0.9, *output.0.lock().unwrap());
+        control.set(Strength::new(0.2));
+        strength(0.65, *output.0.lock().unwrap());
+        control.disable();
+
+        // full strength if the temperature can't be read
+        strength(1.0, set("hot"));
+        assert_eq!(control.temperature(), None);
+
+        assert_eq!(set("30000"), (false, 0.0));
+        drop(control);
+    }
+}
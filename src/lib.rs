@@ -1,4 +1,17 @@
+#[cfg(feature = "json")]
+pub mod datas;
+#[cfg(feature = "json")]
+pub mod extra_schedulers;
+pub mod fan;
+#[cfg(feature = "json")]
+pub mod registry;
+pub mod relay;
+#[cfg(feature = "json")]
+pub mod save_state;
 pub mod scheduler;
+pub mod store;
+#[cfg(feature = "sysfs")]
+pub mod sysfs;
 
 #[cfg(feature = "esp32")]
 use esp_idf_hal::{
@@ -7,11 +20,14 @@ use esp_idf_hal::{
 };
 #[cfg(feature = "rpi")]
 use rppal::{gpio::OutputPin, pwm::Pwm};
-pub use scheduler::{Next, Scheduler, WeekScheduler};
+use scheduler::Keep;
+pub use scheduler::{Next, Occurrences, Scheduler, WeekScheduler};
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::str::FromStr;
 use std::time::{Duration, Instant};
 use std::{
+    fmt,
     sync::{mpsc, Arc, Mutex},
     thread,
 };
@@ -71,6 +87,14 @@ pub use env_timezone::{OffsetExt, PrimitiveOffsetExt};
 static DATE_TIME_TZ_FORMAT: &[time::format_description::FormatItem] = time::macros::format_description!(
     "[year]-[month]-[day] [hour]:[minute]:[second] [offset_hour sign:mandatory]:[offset_minute]"
 );
+pub static SECOND_FORMAT: &[time::format_description::FormatItem] =
+    time::macros::format_description!("[hour]:[minute]:[second]");
+pub static MINUTE_FORMAT: &[time::format_description::FormatItem] =
+    time::macros::format_description!("[hour]:[minute]");
+pub static DATE_FORMAT: &[time::format_description::FormatItem] =
+    time::macros::format_description!("[year]-[month]-[day]");
+pub static DATE_TIME_FORMAT: &[time::format_description::FormatItem] =
+    time::macros::format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
 
 #[cfg(feature = "auto-tz")]
 pub fn get_timezone() -> Option<&'static time_tz::Tz> {
@@ -96,6 +120,19 @@ pub fn primitive_to_tz(datetime: time::PrimitiveDateTime) -> time::OffsetDateTim
     };
     v.unwrap_or_else(|| datetime.assume_utc())
 }
+/// Parses a time with or without seconds.
+pub fn parse_time(string: &str) -> Option<time::Time> {
+    time::Time::parse(string, &SECOND_FORMAT)
+        .or_else(|_| time::Time::parse(string, &MINUTE_FORMAT))
+        .ok()
+}
+/// Parses a date and time, or only a date (then at midnight), in the local timezone.
+pub fn parse_date_time(string: &str) -> Option<time::OffsetDateTime> {
+    time::PrimitiveDateTime::parse(string, &DATE_TIME_FORMAT)
+        .or_else(|_| time::Date::parse(string, &DATE_FORMAT).map(time::Date::midnight))
+        .ok()
+        .map(primitive_to_tz)
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Weekday {
@@ -234,6 +271,19 @@ pub struct Transition {
     pub time: Duration,
     pub interpolation: TransitionInterpolation,
 }
+impl Transition {
+    /// The time until the transition is finished, including going back for the
+    /// `*ToAndBack` interpolations.
+    pub fn total_time(&self) -> Duration {
+        match self.interpolation {
+            TransitionInterpolation::LinearToAndBack(multiplier)
+            | TransitionInterpolation::SineToAndBack(multiplier) => {
+                self.time.mul_f64(1.0 + multiplier.max(0.0))
+            }
+            TransitionInterpolation::Linear | TransitionInterpolation::Sine => self.time,
+        }
+    }
+}
 impl Default for Transition {
     fn default() -> Self {
         Self {
@@ -245,6 +295,77 @@ impl Default for Transition {
     }
 }
 
+/// What to do with occurrences of schedulers missed while the daemon was down
+/// or the clock jumped.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CatchUp {
+    /// Skip missed occurrences.
+    Skip,
+    /// Run the latest missed occurrence, if it was missed by at most the [`Duration`].
+    /// Transitions are resumed from when they should have started.
+    RunLate(Duration),
+}
+impl CatchUp {
+    /// Occurrences aren't missed if they're this late, since the controller doesn't wake up exactly on time.
+    pub const SLACK: Duration = Duration::from_secs(5);
+
+    /// Returns true if an occurrence `late` should be run.
+    pub fn should_run(&self, late: Duration) -> bool {
+        let grace = match self {
+            Self::Skip => Duration::ZERO,
+            Self::RunLate(grace) => *grace,
+        };
+        late <= grace + Self::SLACK
+    }
+}
+impl Default for CatchUp {
+    fn default() -> Self {
+        Self::RunLate(Duration::from_secs(60 * 60))
+    }
+}
+
+/// Turns the output off at [`SleepTimer::end`], fading from the current strength
+/// over [`SleepTimer::fade`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct SleepTimer {
+    pub end: OffsetDateTime,
+    pub fade: Duration,
+}
+impl SleepTimer {
+    /// When the fade starts.
+    pub fn fade_start(&self) -> OffsetDateTime {
+        self.end - time::Duration::try_from(self.fade).unwrap_or(time::Duration::MAX)
+    }
+    /// The time left until [`SleepTimer::end`].
+    pub fn remaining(&self, now: OffsetDateTime) -> Duration {
+        (self.end - now).max(time::Duration::ZERO).unsigned_abs()
+    }
+    /// The command to run at `now`, which should be at or after [`SleepTimer::fade_start`].
+    /// If it's later, the fade is shortened.
+    pub fn command(&self, now: OffsetDateTime) -> Command {
+        let remaining = self.remaining(now);
+        if remaining.is_zero() {
+            Command::Set(Strength::new(0.0))
+        } else {
+            Command::FadeTo(
+                Strength::new(0.0),
+                remaining.min(self.fade),
+                TransitionInterpolation::Sine,
+            )
+        }
+    }
+}
+
+/// A named preset. See [`Command::ActivateScene`].
+#[derive(Debug, PartialEq, Clone)]
+pub struct Scene {
+    pub strength: Strength,
+    /// The time to fade from the current strength.
+    pub fade: Duration,
+    /// Started when the fade is done.
+    pub effect: Option<Effect>,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Effect {
     Radar { offset: f64, speed: f64 },
@@ -253,13 +374,61 @@ pub enum Effect {
 #[derive(Debug)]
 pub enum Command {
     Set(Strength),
+    /// Turns the output off if it's on, and else on to the last strength which wasn't zero.
+    /// See [`SharedState::get_last_on_strength`].
+    Toggle,
+    /// Sets the output to the current strength plus the value, clamped.
+    Adjust(f64),
+    /// Sets the output to the current strength times the value, clamped.
+    Scale(f64),
+    /// Sets the strength [`Command::Toggle`] turns on to.
+    SetLastOnStrength(Strength),
     SetTransition(Transition),
+    /// Continues the [`Transition`] as if it started at the date time.
+    /// If it would have ended, the end strength is set.
+    ResumeTransition(Transition, OffsetDateTime),
+    /// Starts a [`Transition`] from the current output to the [`Strength`],
+    /// taking the [`Duration`].
+    /// Clears any [`Effect`].
+    FadeTo(Strength, Duration, TransitionInterpolation),
+    /// Change the time of `day` of the default [`WeekScheduler`].
+    /// See [`SharedState::get_default_week_scheduler`].
     ChangeDayTimer(Weekday, Option<time::Time>),
     ChangeDayTimerTransition(Transition),
+    /// Same as [`Command::ChangeDayTimer`], but for the [`WeekScheduler`] with the supplied name.
+    ChangeWeekDayTimer(String, Weekday, Option<time::Time>),
+    ChangeWeekTransition(String, Transition),
+    /// Sets which [`WeekScheduler`] [`Command::ChangeDayTimer`] and
+    /// [`Command::ChangeDayTimerTransition`] act upon.
+    SetDefaultWeekScheduler(String),
     AddReplaceScheduler(String, Box<dyn Scheduler>),
     RemoveScheduler(String),
+    /// Enables the scheduler with the name, if it exists.
+    EnableScheduler(String),
+    /// Disables the scheduler with the name, if it exists.
+    /// If a date is supplied, the scheduler is enabled again at that point.
+    DisableScheduler(String, Option<OffsetDateTime>),
     ClearAllSchedulers,
+    /// Sets when the schedulers last ran.
+    /// Their occurrences missed since are handled according to the [`CatchUp`] policy.
+    SetLastRuns(HashMap<String, OffsetDateTime>),
+    SetCatchUp(CatchUp),
+    /// Sets the [`SleepTimer`] to end at the date time, fading over the [`Duration`].
+    SetSleepTimer(OffsetDateTime, Duration),
+    /// Postpones the end of the [`SleepTimer`], if any.
+    ExtendSleepTimer(Duration),
+    CancelSleepTimer,
+    /// Adds or replaces the [`Scene`] with the name.
+    SetScene(String, Scene),
+    RemoveScene(String),
+    /// Fades to the [`Scene`] with the name, if it exists.
+    ActivateScene(String),
     SetEffect(Effect),
+    ClearEffect,
+    /// Sets the frequency of the output, in Hz. See [`VariableOut::set_frequency`].
+    SetFrequency(f64),
+    /// Sets the resolution of the output, in bits. See [`VariableOut::set_resolution`].
+    SetResolution(u32),
     Finish,
     UpdateWake,
 }
@@ -267,12 +436,34 @@ impl Command {
     pub fn can_clone(&self) -> bool {
         match self {
             Self::Set(_)
+            | Self::Toggle
+            | Self::Adjust(_)
+            | Self::Scale(_)
+            | Self::SetLastOnStrength(_)
             | Self::SetTransition(_)
+            | Self::ResumeTransition(_, _)
+            | Self::FadeTo(_, _, _)
             | Self::ChangeDayTimer(_, _)
             | Self::ChangeDayTimerTransition(_)
+            | Self::ChangeWeekDayTimer(_, _, _)
+            | Self::ChangeWeekTransition(_, _)
+            | Self::SetDefaultWeekScheduler(_)
             | Self::RemoveScheduler(_)
+            | Self::EnableScheduler(_)
+            | Self::DisableScheduler(_, _)
             | Self::ClearAllSchedulers
+            | Self::SetLastRuns(_)
+            | Self::SetCatchUp(_)
+            | Self::SetSleepTimer(_, _)
+            | Self::ExtendSleepTimer(_)
+            | Self::CancelSleepTimer
+            | Self::SetScene(_, _)
+            | Self::RemoveScene(_)
+            | Self::ActivateScene(_)
             | Self::SetEffect(_)
+            | Self::ClearEffect
+            | Self::SetFrequency(_)
+            | Self::SetResolution(_)
             | Self::Finish
             | Self::UpdateWake => true,
             Self::AddReplaceScheduler(_, _) => false,
@@ -298,14 +489,42 @@ impl Clone for ClonableCommand {
     fn clone(&self) -> Self {
         Self(match &self.0 {
             Command::Set(s) => Command::Set(Strength::clone(s)),
+            Command::Toggle => Command::Toggle,
+            Command::Adjust(d) => Command::Adjust(*d),
+            Command::Scale(f) => Command::Scale(*f),
+            Command::SetLastOnStrength(s) => Command::SetLastOnStrength(Strength::clone(s)),
             Command::SetTransition(t) => Command::SetTransition(Transition::clone(t)),
+            Command::ResumeTransition(t, d) => Command::ResumeTransition(Transition::clone(t), *d),
+            Command::FadeTo(s, d, i) => Command::FadeTo(*s, *d, i.clone()),
             Command::ChangeDayTimer(d, t) => Command::ChangeDayTimer(*d, *t),
             Command::ChangeDayTimerTransition(t) => {
                 Command::ChangeDayTimerTransition(Transition::clone(t))
             }
+            Command::ChangeWeekDayTimer(n, d, t) => {
+                Command::ChangeWeekDayTimer(String::clone(n), *d, *t)
+            }
+            Command::ChangeWeekTransition(n, t) => {
+                Command::ChangeWeekTransition(String::clone(n), Transition::clone(t))
+            }
+            Command::SetDefaultWeekScheduler(n) => {
+                Command::SetDefaultWeekScheduler(String::clone(n))
+            }
             Command::RemoveScheduler(s) => Command::RemoveScheduler(String::clone(s)),
+            Command::EnableScheduler(s) => Command::EnableScheduler(String::clone(s)),
+            Command::DisableScheduler(s, d) => Command::DisableScheduler(String::clone(s), *d),
             Command::ClearAllSchedulers => Command::ClearAllSchedulers,
+            Command::SetLastRuns(l) => Command::SetLastRuns(l.clone()),
+            Command::SetCatchUp(c) => Command::SetCatchUp(*c),
+            Command::SetSleepTimer(e, f) => Command::SetSleepTimer(*e, *f),
+            Command::ExtendSleepTimer(d) => Command::ExtendSleepTimer(*d),
+            Command::CancelSleepTimer => Command::CancelSleepTimer,
+            Command::SetScene(n, s) => Command::SetScene(String::clone(n), s.clone()),
+            Command::RemoveScene(n) => Command::RemoveScene(String::clone(n)),
+            Command::ActivateScene(n) => Command::ActivateScene(String::clone(n)),
             Command::SetEffect(e) => Command::SetEffect(e.clone()),
+            Command::ClearEffect => Command::ClearEffect,
+            Command::SetFrequency(f) => Command::SetFrequency(*f),
+            Command::SetResolution(r) => Command::SetResolution(*r),
             Command::Finish => Command::Finish,
             Command::UpdateWake => Command::UpdateWake,
 
@@ -324,8 +543,66 @@ pub enum Action {
     Set(Strength),
     /// Stop execution of loop
     Break,
+    /// Set the frequency of the output. See [`VariableOut::set_frequency`].
+    SetFrequency(f64),
+    /// Set the resolution of the output. See [`VariableOut::set_resolution`].
+    SetResolution(u32),
 }
 
+/// The frequency and resolution of a [`VariableOut`] and what they can be set to.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Capabilities {
+    /// In Hz. [`None`] if it's unknown.
+    pub frequency: Option<f64>,
+    /// The lowest and highest frequency [`VariableOut::set_frequency`] accepts, in Hz.
+    /// [`None`] if the frequency can't be changed.
+    pub frequency_range: Option<(f64, f64)>,
+    /// The resolution of the duty cycle, in bits. [`None`] if it's unknown.
+    pub resolution: Option<u32>,
+    /// The lowest and highest resolution [`VariableOut::set_resolution`] accepts, in bits.
+    /// [`None`] if the resolution can't be changed.
+    pub resolution_range: Option<(u32, u32)>,
+}
+impl Capabilities {
+    /// Returns [`OutputError::Unsupported`] or [`OutputError::OutOfRange`]
+    /// if `frequency` can't be set.
+    pub fn check_frequency(&self, frequency: f64) -> Result<(), OutputError> {
+        match self.frequency_range {
+            None => Err(OutputError::Unsupported),
+            Some((min, max)) if !(min..=max).contains(&frequency) => Err(OutputError::OutOfRange),
+            Some(_) => Ok(()),
+        }
+    }
+    /// Returns [`OutputError::Unsupported`] or [`OutputError::OutOfRange`]
+    /// if `resolution` can't be set.
+    pub fn check_resolution(&self, resolution: u32) -> Result<(), OutputError> {
+        match self.resolution_range {
+            None => Err(OutputError::Unsupported),
+            Some((min, max)) if !(min..=max).contains(&resolution) => Err(OutputError::OutOfRange),
+            Some(_) => Ok(()),
+        }
+    }
+}
+#[derive(Debug)]
+pub enum OutputError {
+    /// The output doesn't support changing the setting.
+    Unsupported,
+    /// The value is outside the range in the [`Capabilities`].
+    OutOfRange,
+    /// The device failed.
+    Device(String),
+}
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported => f.write_str("not supported by the output"),
+            Self::OutOfRange => f.write_str("out of the supported range"),
+            Self::Device(err) => write!(f, "device error: {}", err),
+        }
+    }
+}
+impl std::error::Error for OutputError {}
+
 pub trait VariableOut {
     /// Main function. Used to set output.
     fn set(&mut self, value: Strength);
@@ -337,12 +614,33 @@ pub trait VariableOut {
 
     /// Used to prepare the out device. Used for optimizing; internal guarantees.
     fn prepare(&mut self);
+
+    /// The current frequency and resolution, and what they can be set to.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+    /// Sets the frequency of the PWM signal, in Hz.
+    /// The duty cycle is kept.
+    fn set_frequency(&mut self, frequency: f64) -> Result<(), OutputError> {
+        let _ = frequency;
+        Err(OutputError::Unsupported)
+    }
+    /// Sets the resolution of the duty cycle, in bits.
+    fn set_resolution(&mut self, bits: u32) -> Result<(), OutputError> {
+        let _ = bits;
+        Err(OutputError::Unsupported)
+    }
 }
+/// The frequencies the hardware PWM of the Raspberry Pi accepts.
+#[cfg(feature = "rpi")]
+const RPI_PWM_FREQUENCY: (f64, f64) = (1.0, 1_000_000.0);
+/// The frequencies [`SoftwarePwm`] accepts. It gets jittery above a few kHz.
+#[cfg(feature = "rpi")]
+const SOFTWARE_PWM_FREQUENCY: (f64, f64) = (1.0, 10_000.0);
 #[cfg(feature = "rpi")]
 impl VariableOut for Pwm {
     fn set(&mut self, value: Strength) {
-        self.set_pulse_width(Duration::from_nanos((value.0 * 1000000.0).round() as u64))
-            .unwrap();
+        self.set_duty_cycle(value.0).unwrap();
     }
     fn enable(&mut self) {
         println!("Enabling hardware PWM.");
@@ -353,10 +651,22 @@ impl VariableOut for Pwm {
         Pwm::disable(self).expect("failed to disable hardware PWM");
     }
     fn prepare(&mut self) {
-        self.set_pulse_width(Duration::new(0, 0))
-            .expect("failed to set pulse width to guarantee period");
-        self.set_period(Duration::new(0, 1000000))
-            .expect("failed to set period in `enable()`");
+        // keep the period the channel was created with
+        self.set_duty_cycle(0.0)
+            .expect("failed to set duty cycle of hardware PWM");
+    }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            frequency: Pwm::frequency(self).ok(),
+            frequency_range: Some(RPI_PWM_FREQUENCY),
+            ..Default::default()
+        }
+    }
+    fn set_frequency(&mut self, frequency: f64) -> Result<(), OutputError> {
+        self.capabilities().check_frequency(frequency)?;
+        let device = |err: rppal::pwm::Error| OutputError::Device(err.to_string());
+        let duty_cycle = self.duty_cycle().map_err(device)?;
+        Pwm::set_frequency(self, frequency, duty_cycle).map_err(device)
     }
 }
 #[cfg(feature = "rpi")]
@@ -374,24 +684,453 @@ impl VariableOut for OutputPin {
     }
     fn prepare(&mut self) {}
 }
+/// Software PWM on a GPIO pin, with a configurable frequency and polarity.
+///
+/// The [`VariableOut`] implementation of [`OutputPin`] always uses 1 kHz.
+#[cfg(feature = "rpi")]
+#[derive(Debug)]
+pub struct SoftwarePwm {
+    pin: OutputPin,
+    frequency: f64,
+    inverted: bool,
+    /// The duty cycle currently output, if any.
+    duty_cycle: Option<f64>,
+}
+#[cfg(feature = "rpi")]
+impl SoftwarePwm {
+    /// If `inverted`, the pin is low when the strength is `1`.
+    pub fn new(pin: OutputPin, frequency: f64, inverted: bool) -> Self {
+        Self {
+            pin,
+            frequency,
+            inverted,
+            duty_cycle: None,
+        }
+    }
+    pub fn into_inner(self) -> OutputPin {
+        self.pin
+    }
+}
+#[cfg(feature = "rpi")]
+impl VariableOut for SoftwarePwm {
+    fn set(&mut self, value: Strength) {
+        let duty_cycle = if self.inverted {
+            1.0 - value.0
+        } else {
+            value.0
+        };
+        self.pin
+            .set_pwm_frequency(self.frequency, duty_cycle)
+            .unwrap();
+        self.duty_cycle = Some(duty_cycle);
+    }
+    fn enable(&mut self) {}
+    fn disable(&mut self) {
+        self.pin.clear_pwm().expect("failed to stop software PWM");
+        self.duty_cycle = None;
+        // leave the output off
+        if self.inverted {
+            self.pin.set_high();
+        } else {
+            self.pin.set_low();
+        }
+    }
+    fn prepare(&mut self) {}
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            frequency: Some(self.frequency),
+            frequency_range: Some(SOFTWARE_PWM_FREQUENCY),
+            ..Default::default()
+        }
+    }
+    fn set_frequency(&mut self, frequency: f64) -> Result<(), OutputError> {
+        self.capabilities().check_frequency(frequency)?;
+        self.frequency = frequency;
+        if let Some(duty_cycle) = self.duty_cycle {
+            self.pin
+                .set_pwm_frequency(frequency, duty_cycle)
+                .map_err(|err| OutputError::Device(err.to_string()))?;
+        }
+        Ok(())
+    }
+}
+/// The clock of the LEDC timers of the esp32, in Hz.
+#[cfg(feature = "esp32")]
+const LEDC_CLOCK: f64 = 80_000_000.0;
+/// A LEDC channel of the esp32, which knows the frequency and resolution of its timer.
+#[cfg(feature = "esp32")]
+pub struct Ledc<C: HwChannel, H: HwTimer, T: std::borrow::Borrow<Timer<H>>, P: OutputPin> {
+    channel: Channel<C, H, T, P>,
+    frequency: f64,
+    resolution: u32,
+}
+#[cfg(feature = "esp32")]
+impl<C: HwChannel, H: HwTimer, T: std::borrow::Borrow<Timer<H>>, P: OutputPin> Ledc<C, H, T, P> {
+    /// `frequency` (in Hz) and `resolution` (in bits) are what the timer of `channel`
+    /// was configured with.
+    pub fn new(channel: Channel<C, H, T, P>, frequency: f64, resolution: u32) -> Self {
+        Self {
+            channel,
+            frequency,
+            resolution,
+        }
+    }
+    pub fn into_inner(self) -> Channel<C, H, T, P> {
+        self.channel
+    }
+}
 #[cfg(feature = "esp32")]
 impl<C: HwChannel, H: HwTimer, T: std::borrow::Borrow<Timer<H>>, P: OutputPin> VariableOut
-    for Channel<C, H, T, P>
+    for Ledc<C, H, T, P>
 {
     fn set(&mut self, value: Strength) {
-        let max_duty = self.get_max_duty();
-        self.set_duty((max_duty as f64 * value.into_inner()) as u32)
+        let max_duty = self.channel.get_max_duty();
+        self.channel
+            .set_duty((max_duty as f64 * value.into_inner()) as u32)
             .expect("Failed to set PWM duty cycle on esp32");
     }
     fn enable(&mut self) {
         println!("Enabling hardware PWM.");
-        Channel::enable(self).expect("failed to enable hardware PWM");
+        self.channel
+            .enable()
+            .expect("failed to enable hardware PWM");
     }
     fn disable(&mut self) {
         println!("Disabling hardware PWM.");
-        Channel::disable(self).expect("failed to disable hardware PWM");
+        self.channel
+            .disable()
+            .expect("failed to disable hardware PWM");
     }
     fn prepare(&mut self) {}
+    fn capabilities(&self) -> Capabilities {
+        // The resolution can't be changed, as the channel keeps the max duty
+        // of the timer it was created with.
+        // The timer divides the clock by at most 1023 and counts to 2^bits.
+        let steps = 2_f64.powi(self.resolution as i32);
+        Capabilities {
+            frequency: Some(self.frequency),
+            frequency_range: Some((LEDC_CLOCK / (1023.0 * steps), LEDC_CLOCK / steps)),
+            resolution: Some(self.resolution),
+            ..Default::default()
+        }
+    }
+    fn set_frequency(&mut self, frequency: f64) -> Result<(), OutputError> {
+        self.capabilities().check_frequency(frequency)?;
+        esp_idf_sys::esp!(unsafe {
+            esp_idf_sys::ledc_set_freq(
+                esp_idf_sys::ledc_mode_t_LEDC_LOW_SPEED_MODE,
+                H::timer(),
+                frequency.round() as u32,
+            )
+        })
+        .map_err(|err| OutputError::Device(err.to_string()))?;
+        self.frequency = frequency;
+        Ok(())
+    }
+}
+
+/// Any PWM output of an [`embedded-hal`](embedded_hal) driver, e.g. for the RP2040,
+/// STM32, nRF or a PCA9685 over I²C.
+///
+/// ```ignore
+/// let pwm = HalPwm::new(pca9685_channel);
+/// let controller = Controller::new(pwm, week_scheduler);
+/// ```
+#[cfg(feature = "embedded-hal")]
+#[derive(Debug)]
+pub struct HalPwm<P> {
+    pwm: P,
+    inverted: bool,
+}
+#[cfg(feature = "embedded-hal")]
+impl<P: embedded_hal::pwm::SetDutyCycle> HalPwm<P> {
+    pub fn new(pwm: P) -> Self {
+        Self {
+            pwm,
+            inverted: false,
+        }
+    }
+    /// The output is low when the strength is `1`.
+    pub fn inverted(pwm: P) -> Self {
+        Self {
+            pwm,
+            inverted: true,
+        }
+    }
+    pub fn into_inner(self) -> P {
+        self.pwm
+    }
+}
+#[cfg(feature = "embedded-hal")]
+impl<P: embedded_hal::pwm::SetDutyCycle> VariableOut for HalPwm<P> {
+    fn set(&mut self, value: Strength) {
+        let value = if self.inverted {
+            1.0 - value.into_inner()
+        } else {
+            value.into_inner()
+        };
+        let duty = (self.pwm.max_duty_cycle() as f64 * value).round() as u16;
+        if let Err(err) = self.pwm.set_duty_cycle(duty) {
+            println!("Failed to set PWM duty cycle: {:?}", err);
+        }
+    }
+    // embedded-hal has no way to turn the output off
+    fn enable(&mut self) {}
+    fn disable(&mut self) {
+        self.set(Strength::new(0.0));
+    }
+    fn prepare(&mut self) {
+        self.set(Strength::new(0.0));
+    }
+    fn capabilities(&self) -> Capabilities {
+        // the max duty is usually 2^bits - 1, but can be anything
+        let max_duty = self.pwm.max_duty_cycle();
+        Capabilities {
+            resolution: Some(16 - max_duty.leading_zeros()),
+            ..Default::default()
+        }
+    }
+}
+
+impl<T: VariableOut + ?Sized> VariableOut for Box<T> {
+    fn set(&mut self, value: Strength) {
+        (**self).set(value)
+    }
+    fn enable(&mut self) {
+        (**self).enable()
+    }
+    fn disable(&mut self) {
+        (**self).disable()
+    }
+    fn prepare(&mut self) {
+        (**self).prepare()
+    }
+    fn capabilities(&self) -> Capabilities {
+        (**self).capabilities()
+    }
+    fn set_frequency(&mut self, frequency: f64) -> Result<(), OutputError> {
+        (**self).set_frequency(frequency)
+    }
+    fn set_resolution(&mut self, bits: u32) -> Result<(), OutputError> {
+        (**self).set_resolution(bits)
+    }
+}
+
+/// Sets all the outputs to the same strength.
+pub struct MultiOut(pub Vec<Box<dyn VariableOut + Send>>);
+impl VariableOut for MultiOut {
+    fn set(&mut self, value: Strength) {
+        for out in &mut self.0 {
+            out.set(value);
+        }
+    }
+    fn enable(&mut self) {
+        for out in &mut self.0 {
+            out.enable();
+        }
+    }
+    fn disable(&mut self) {
+        for out in &mut self.0 {
+            out.disable();
+        }
+    }
+    fn prepare(&mut self) {
+        for out in &mut self.0 {
+            out.prepare();
+        }
+    }
+    /// The settings supported by all the outputs, and the values of the first.
+    fn capabilities(&self) -> Capabilities {
+        let mut outputs = self.0.iter().map(|out| out.capabilities());
+        let mut capabilities = outputs.next().unwrap_or_default();
+        for other in outputs {
+            capabilities.frequency_range = capabilities
+                .frequency_range
+                .zip(other.frequency_range)
+                .map(|(a, b)| (a.0.max(b.0), a.1.min(b.1)))
+                .filter(|(min, max)| min <= max);
+            capabilities.resolution = capabilities
+                .resolution
+                .zip(other.resolution)
+                .map(|(a, b)| a.min(b));
+            capabilities.resolution_range = capabilities
+                .resolution_range
+                .zip(other.resolution_range)
+                .map(|(a, b)| (a.0.max(b.0), a.1.min(b.1)))
+                .filter(|(min, max)| min <= max);
+        }
+        capabilities
+    }
+    fn set_frequency(&mut self, frequency: f64) -> Result<(), OutputError> {
+        // don't change only some of them
+        self.capabilities().check_frequency(frequency)?;
+        self.0
+            .iter_mut()
+            .try_for_each(|out| out.set_frequency(frequency))
+    }
+    fn set_resolution(&mut self, bits: u32) -> Result<(), OutputError> {
+        self.capabilities().check_resolution(bits)?;
+        self.0
+            .iter_mut()
+            .try_for_each(|out| out.set_resolution(bits))
+    }
+}
+
+/// Drives a hobby servo or ESC, which takes a pulse of 1-2 ms every 20 ms.
+///
+/// The strength is mapped to a pulse between [`Servo::min`] and [`Servo::max`],
+/// so transitions move the servo smoothly.
+/// The pulses continue when the output is [disabled](VariableOut::disable), to hold the position.
+///
+/// This is only available in the library; `httpwmd` can't be configured to drive a servo.
+#[derive(Debug)]
+pub struct Servo<T> {
+    output: T,
+    /// In Hz.
+    frequency: f64,
+    min: Duration,
+    max: Duration,
+    reversed: bool,
+    strength: Option<Strength>,
+}
+impl<T: VariableOut> Servo<T> {
+    /// The frequency of most servos and ESCs.
+    pub const FREQUENCY: f64 = 50.0;
+
+    /// Sets `output` to [`Servo::FREQUENCY`] and maps the strength to pulses of 1-2 ms.
+    ///
+    /// If the frequency of `output` can't be changed, it has to be created with
+    /// [`Servo::FREQUENCY`]. Returns an error if `output` rejects the frequency
+    /// or has another fixed frequency.
+    /// Returns [`OutputError::Unsupported`] if the frequency can't be changed and `output`
+    /// doesn't report it; see [`Servo::with_assumed_frequency`].
+    pub fn new(output: T) -> Result<Self, OutputError> {
+        Self::with_range(
+            output,
+            Self::FREQUENCY,
+            Duration::from_micros(1000),
+            Duration::from_micros(2000),
+            false,
+        )
+    }
+    /// Like [`Servo::new`], with a calibrated pulse range.
+    /// `min` is sent at strength `0` and `max` at strength `1`, unless `reversed`.
+    ///
+    /// Returns [`OutputError::OutOfRange`] if `max` is shorter than `min`
+    /// or longer than the period of `frequency`.
+    pub fn with_range(
+        output: T,
+        frequency: f64,
+        min: Duration,
+        max: Duration,
+        reversed: bool,
+    ) -> Result<Self, OutputError> {
+        Self::create(output, frequency, min, max, reversed, false)
+    }
+    /// Like [`Servo::with_range`], but accepts an output which doesn't report its frequency
+    /// and can't change it. It has to be created with `frequency`.
+    pub fn with_assumed_frequency(
+        output: T,
+        frequency: f64,
+        min: Duration,
+        max: Duration,
+        reversed: bool,
+    ) -> Result<Self, OutputError> {
+        Self::create(output, frequency, min, max, reversed, true)
+    }
+    fn create(
+        mut output: T,
+        frequency: f64,
+        min: Duration,
+        max: Duration,
+        reversed: bool,
+        assume_frequency: bool,
+    ) -> Result<Self, OutputError> {
+        if min > max || max.as_secs_f64() * frequency > 1.0 {
+            return Err(OutputError::OutOfRange);
+        }
+        match output.set_frequency(frequency) {
+            Ok(()) => {}
+            // a fixed frequency has to be the one we want
+            Err(OutputError::Unsupported) => match output.capabilities().frequency {
+                Some(fixed) if ((fixed - frequency) / frequency).abs() > 0.01 => {
+                    return Err(OutputError::Unsupported)
+                }
+                Some(_) => {}
+                None if assume_frequency => {}
+                None => return Err(OutputError::Unsupported),
+            },
+            Err(err) => return Err(err),
+        }
+        Ok(Self {
+            output,
+            frequency,
+            min,
+            max,
+            reversed,
+            strength: None,
+        })
+    }
+    /// The pulse at strength `0`.
+    pub fn min(&self) -> Duration {
+        self.min
+    }
+    /// The pulse at strength `1`.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+    pub fn into_inner(self) -> T {
+        self.output
+    }
+    /// The duty cycle giving the pulse for `value`.
+    fn duty_cycle(&self, value: Strength) -> Strength {
+        let value = if self.reversed {
+            1.0 - value.into_inner()
+        } else {
+            value.into_inner()
+        };
+        let min = self.min.as_secs_f64();
+        let pulse = min + (self.max.as_secs_f64() - min) * value;
+        Strength::new_clamped(pulse * self.frequency)
+    }
+}
+impl<T: VariableOut> VariableOut for Servo<T> {
+    fn set(&mut self, value: Strength) {
+        self.output.set(self.duty_cycle(value));
+        self.strength = Some(value);
+    }
+    fn enable(&mut self) {
+        self.output.enable();
+    }
+    // keep holding the position
+    fn disable(&mut self) {}
+    fn prepare(&mut self) {
+        self.output.prepare();
+    }
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = self.output.capabilities();
+        capabilities.frequency = capabilities.frequency.or(Some(self.frequency));
+        // the pulse has to fit in the period
+        let max = 1.0 / self.max.as_secs_f64();
+        capabilities.frequency_range = capabilities
+            .frequency_range
+            .map(|(low, high)| (low, high.min(max)))
+            .filter(|(low, high)| low <= high);
+        capabilities
+    }
+    /// Keeps the pulse, not the duty cycle.
+    fn set_frequency(&mut self, frequency: f64) -> Result<(), OutputError> {
+        self.capabilities().check_frequency(frequency)?;
+        self.output.set_frequency(frequency)?;
+        self.frequency = frequency;
+        if let Some(strength) = self.strength {
+            self.output.set(self.duty_cycle(strength));
+        }
+        Ok(())
+    }
+    fn set_resolution(&mut self, bits: u32) -> Result<(), OutputError> {
+        self.output.set_resolution(bits)
+    }
 }
 
 pub struct PrintOut(pub mpsc::SyncSender<f64>);
@@ -424,51 +1163,371 @@ pub fn get_now() -> time::OffsetDateTime {
     get_timezone().map_or(time, |offset| time.to_timezone(offset))
 }
 
+/// The name of the [`WeekScheduler`] given to [`Controller::new`].
+pub const DEFAULT_WEEK_SCHEDULER: &str = "week";
+
+/// A change of the [`SharedState`], sent to the listeners of [`Controller::subscribe`].
+///
+/// Steps of transitions and effects aren't changes; their start and end are.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Change {
+    /// The strength or the strength [`Command::Toggle`] turns on to.
+    Strength,
+    Transition,
+    Effect,
+    /// The scheduler with the name was added or changed.
+    Scheduler(String),
+    SchedulerRemoved(String),
+    DefaultWeekScheduler,
+    DisabledSchedulers,
+    LastRuns,
+    CatchUp,
+    SleepTimer,
+    Scenes,
+    /// The [`Capabilities`] of the output.
+    Output,
+}
+
 #[derive(Debug)]
 pub struct SharedState {
     strength: Strength,
-    transition: Option<Transition>,
-    week_scheduler: WeekScheduler,
+    last_on_strength: Strength,
+    transition: Option<(Transition, OffsetDateTime)>,
+    default_week_scheduler: String,
     schedulers: HashMap<String, Box<dyn Scheduler>>,
+    disabled_schedulers: HashMap<String, Option<OffsetDateTime>>,
+    last_runs: HashMap<String, OffsetDateTime>,
+    catch_up: CatchUp,
+    last_clock_jump: Option<ClockJump>,
+    sleep_timer: Option<SleepTimer>,
+    scenes: HashMap<String, Scene>,
+    active_scene: Option<String>,
+    effect: Option<Effect>,
+    output: Capabilities,
+    /// Not yet sent to the listeners.
+    changes: Vec<Change>,
 }
 impl SharedState {
     pub fn new(scheduler: WeekScheduler) -> Self {
+        let mut schedulers: HashMap<String, Box<dyn Scheduler>> = HashMap::new();
+        schedulers.insert(DEFAULT_WEEK_SCHEDULER.to_string(), Box::new(scheduler));
         Self {
             strength: Strength::new(0.0),
+            last_on_strength: Strength::new(1.0),
             transition: None,
-            week_scheduler: scheduler,
-            schedulers: HashMap::new(),
+            default_week_scheduler: DEFAULT_WEEK_SCHEDULER.to_string(),
+            schedulers,
+            disabled_schedulers: HashMap::new(),
+            last_runs: HashMap::new(),
+            catch_up: CatchUp::default(),
+            last_clock_jump: None,
+            sleep_timer: None,
+            scenes: HashMap::new(),
+            active_scene: None,
+            effect: None,
+            output: Capabilities::default(),
+            changes: Vec::new(),
         }
     }
 
+    fn changed(&mut self, change: Change) {
+        if !self.changes.contains(&change) {
+            self.changes.push(change);
+        }
+    }
+    /// Takes the changes made since the last call.
+    pub fn take_changes(&mut self) -> Vec<Change> {
+        std::mem::take(&mut self.changes)
+    }
+
     pub fn get_strength(&self) -> &Strength {
         &self.strength
     }
     pub fn set_strength(&mut self, strength: Strength) {
+        if !strength.is_off() {
+            self.last_on_strength = strength;
+        }
         self.strength = strength;
-        self.transition = None;
+        if self.transition.take().is_some() {
+            self.changed(Change::Transition);
+        }
+        self.changed(Change::Strength);
+    }
+    /// The last strength which wasn't zero.
+    pub fn get_last_on_strength(&self) -> &Strength {
+        &self.last_on_strength
+    }
+    pub fn set_last_on_strength(&mut self, strength: Strength) {
+        self.last_on_strength = strength;
+        self.changed(Change::Strength);
     }
 
     pub fn get_transition(&self) -> Option<&Transition> {
-        self.transition.as_ref()
+        self.transition.as_ref().map(|(transition, _)| transition)
+    }
+    /// When the current transition started, on the wall clock.
+    pub fn get_transition_start(&self) -> Option<OffsetDateTime> {
+        self.transition.as_ref().map(|(_, start)| *start)
     }
-    pub fn set_transition(&mut self, transition: Option<Transition>) {
+    /// Sets the current transition, which started at the second item.
+    pub fn set_transition(&mut self, transition: Option<(Transition, OffsetDateTime)>) {
         self.transition = transition;
+        self.changed(Change::Transition);
     }
 
-    pub fn ref_week_schedule(&self) -> &WeekScheduler {
-        &self.week_scheduler
+    /// The [`Effect`] currently running.
+    pub fn get_effect(&self) -> Option<&Effect> {
+        self.effect.as_ref()
     }
-    pub fn mut_week_scheduler(&mut self) -> &mut WeekScheduler {
-        &mut self.week_scheduler
+    pub fn set_effect(&mut self, effect: Option<Effect>) {
+        if self.effect != effect {
+            self.effect = effect;
+            self.changed(Change::Effect);
+        }
+    }
+
+    /// The [`Capabilities`] of the output of the [`Controller`].
+    pub fn get_output(&self) -> &Capabilities {
+        &self.output
+    }
+    pub fn set_output(&mut self, output: Capabilities) {
+        if self.output != output {
+            self.output = output;
+            self.changed(Change::Output);
+        }
+    }
+
+    /// The last time the wall clock jumped, if it has since the start.
+    pub fn get_last_clock_jump(&self) -> Option<&ClockJump> {
+        self.last_clock_jump.as_ref()
+    }
+    pub fn set_last_clock_jump(&mut self, jump: ClockJump) {
+        self.last_clock_jump = Some(jump);
+    }
+
+    /// The [`SleepTimer`], until its fade is done.
+    pub fn get_sleep_timer(&self) -> Option<&SleepTimer> {
+        self.sleep_timer.as_ref()
+    }
+    pub fn set_sleep_timer(&mut self, sleep_timer: Option<SleepTimer>) {
+        self.sleep_timer = sleep_timer;
+        self.changed(Change::SleepTimer);
+    }
+
+    pub fn ref_scenes(&self) -> &HashMap<String, Scene> {
+        &self.scenes
+    }
+    pub fn mut_scenes(&mut self) -> &mut HashMap<String, Scene> {
+        self.changed(Change::Scenes);
+        &mut self.scenes
+    }
+    /// The name of the [`Scene`] last activated, if the output hasn't been changed since.
+    pub fn get_active_scene(&self) -> Option<&str> {
+        self.active_scene.as_deref()
+    }
+    pub fn set_active_scene(&mut self, name: Option<String>) {
+        self.active_scene = name;
+    }
+
+    pub fn get_catch_up(&self) -> CatchUp {
+        self.catch_up
+    }
+    pub fn set_catch_up(&mut self, catch_up: CatchUp) {
+        self.catch_up = catch_up;
+        self.changed(Change::CatchUp);
+    }
+
+    /// Get the name of the [`WeekScheduler`] which [`Command::ChangeDayTimer`] and
+    /// [`Command::ChangeDayTimerTransition`] act upon.
+    pub fn get_default_week_scheduler(&self) -> &str {
+        &self.default_week_scheduler
+    }
+    pub fn set_default_week_scheduler(&mut self, name: String) {
+        self.default_week_scheduler = name;
+        self.changed(Change::DefaultWeekScheduler);
+    }
+    /// Get the default [`WeekScheduler`], if it exists.
+    pub fn ref_default_week_scheduler(&self) -> Option<&WeekScheduler> {
+        self.ref_week_scheduler(&self.default_week_scheduler)
+    }
+
+    /// Returns [`None`] if no scheduler with `name` exists or if it isn't a [`WeekScheduler`].
+    pub fn ref_week_scheduler(&self, name: &str) -> Option<&WeekScheduler> {
+        self.schedulers
+            .get(name)
+            .and_then(|scheduler| scheduler.as_week_scheduler())
+    }
+    /// Returns [`None`] if no scheduler with `name` exists or if it isn't a [`WeekScheduler`].
+    pub fn mut_week_scheduler(&mut self, name: &str) -> Option<&mut WeekScheduler> {
+        if self.ref_week_scheduler(name).is_some() {
+            self.changed(Change::Scheduler(name.to_string()));
+        }
+        self.schedulers
+            .get_mut(name)
+            .and_then(|scheduler| scheduler.as_week_scheduler_mut())
+    }
+    /// Gets the [`WeekScheduler`] with `name` or inserts a new, empty, one with the
+    /// default [`Transition`].
+    ///
+    /// If a scheduler of another kind occupies `name`, it's replaced.
+    pub fn mut_or_insert_week_scheduler(&mut self, name: &str) -> &mut WeekScheduler {
+        if self.ref_week_scheduler(name).is_none() {
+            self.schedulers
+                .insert(name.to_string(), Box::new(WeekScheduler::default()));
+        }
+        // unwrap is ok; we just inserted it
+        self.mut_week_scheduler(name).unwrap()
     }
 
     pub fn ref_schedulers(&self) -> &HashMap<String, Box<dyn Scheduler>> {
         &self.schedulers
     }
+    /// Changes made through this aren't sent to the listeners.
+    /// Prefer [`SharedState::insert_scheduler`] and [`SharedState::remove_scheduler`].
     pub fn mut_schedulers(&mut self) -> &mut HashMap<String, Box<dyn Scheduler>> {
         &mut self.schedulers
     }
+
+    /// Adds or replaces the scheduler with `name`.
+    /// It's enabled and it's considered to never have run.
+    pub fn insert_scheduler(&mut self, name: String, scheduler: Box<dyn Scheduler>) {
+        if self.disabled_schedulers.remove(&name).is_some() {
+            self.changed(Change::DisabledSchedulers);
+        }
+        if self.last_runs.remove(&name).is_some() {
+            self.changed(Change::LastRuns);
+        }
+        self.changed(Change::Scheduler(name.clone()));
+        self.schedulers.insert(name, scheduler);
+    }
+    /// Removes the scheduler, if it was disabled, and when it last ran.
+    pub fn remove_scheduler(&mut self, name: &str) -> Option<Box<dyn Scheduler>> {
+        if self.disabled_schedulers.remove(name).is_some() {
+            self.changed(Change::DisabledSchedulers);
+        }
+        if self.last_runs.remove(name).is_some() {
+            self.changed(Change::LastRuns);
+        }
+        let scheduler = self.schedulers.remove(name);
+        if scheduler.is_some() {
+            self.changed(Change::SchedulerRemoved(name.to_string()));
+        }
+        scheduler
+    }
+    /// Retains only the schedulers `f` returns true for.
+    pub fn retain_schedulers(&mut self, mut f: impl FnMut(&str, &dyn Scheduler) -> bool) {
+        let removed: Vec<String> = self
+            .schedulers
+            .iter()
+            .filter(|(name, scheduler)| !f(name, scheduler.as_ref()))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in removed {
+            self.remove_scheduler(&name);
+        }
+    }
+    /// Marks the `occurrence` of the scheduler with `name` as run and calls [`Scheduler::advance`].
+    ///
+    /// Returns false if no scheduler with `name` exists.
+    pub fn advance_scheduler(&mut self, name: &str, occurrence: OffsetDateTime) -> bool {
+        let scheduler = match self.schedulers.get_mut(name) {
+            Some(scheduler) => scheduler,
+            None => return false,
+        };
+        match scheduler.advance() {
+            Keep::Keep => {
+                self.last_runs.insert(name.to_string(), occurrence);
+                self.changed(Change::LastRuns);
+            }
+            Keep::Remove => {
+                self.remove_scheduler(name);
+            }
+        }
+        true
+    }
+
+    /// The occurrence each scheduler last ran.
+    /// Occurrences up to that point aren't run again.
+    pub fn ref_last_runs(&self) -> &HashMap<String, OffsetDateTime> {
+        &self.last_runs
+    }
+    pub fn mut_last_runs(&mut self) -> &mut HashMap<String, OffsetDateTime> {
+        self.changed(Change::LastRuns);
+        &mut self.last_runs
+    }
+
+    /// The names of the disabled schedulers, with the optional time they're enabled again.
+    pub fn ref_disabled_schedulers(&self) -> &HashMap<String, Option<OffsetDateTime>> {
+        &self.disabled_schedulers
+    }
+    pub fn mut_disabled_schedulers(&mut self) -> &mut HashMap<String, Option<OffsetDateTime>> {
+        self.changed(Change::DisabledSchedulers);
+        &mut self.disabled_schedulers
+    }
+    /// Returns true if the scheduler with `name` isn't disabled.
+    /// A scheduler disabled until a time which has passed is enabled.
+    pub fn is_scheduler_enabled(&self, name: &str) -> bool {
+        match self.disabled_schedulers.get(name) {
+            None => true,
+            Some(None) => false,
+            Some(Some(until)) => has_occurred(*until),
+        }
+    }
+    /// Gets the next occurrence of the scheduler with `name` which hasn't run,
+    /// respecting if it's disabled.
+    ///
+    /// If occurrences were missed since it last ran (see [`SharedState::ref_last_runs`]),
+    /// the latest of them is returned. It's then up to the [`CatchUp`] policy what to do.
+    ///
+    /// Returns [`Next::Unknown`] if it doesn't exist or is disabled.
+    /// If it's disabled until a time, the first occurrence after that is returned.
+    pub fn get_next(&self, name: &str, now: &mut scheduler::LazyNow) -> Next {
+        let now = now.now();
+        let last = self.last_runs.get(name).copied();
+        let mut occurrences = self
+            .occurrences(name, last.map_or(now, |last| last.min(now)))
+            .skip_while(|(date_time, _)| matches!(last, Some(last) if *date_time <= last));
+
+        let mut next = match occurrences.next() {
+            Some(next) => next,
+            // a one-shot scheduler which passed before it was added, e.g. while the daemon was down
+            None if last.is_none() && self.is_scheduler_enabled(name) => {
+                match self
+                    .schedulers
+                    .get(name)
+                    .map(|scheduler| scheduler.get_next(&mut scheduler::LazyNow::at(now)))
+                {
+                    Some(Next::At(date_time, command)) if date_time < now => (date_time, command),
+                    _ => return Next::Unknown,
+                }
+            }
+            None => return Next::Unknown,
+        };
+        if next.0 < now {
+            for occurrence in occurrences {
+                if occurrence.0 >= now {
+                    break;
+                }
+                next = occurrence;
+            }
+        }
+        Next::At(next.0, next.1)
+    }
+    /// Gets the occurrences of the scheduler with `name` from `from`, respecting if it's disabled.
+    /// See [`Scheduler::occurrences`].
+    ///
+    /// The iterator is empty if it doesn't exist or is disabled.
+    pub fn occurrences(&self, name: &str, from: OffsetDateTime) -> Occurrences<'_> {
+        let scheduler = match self.schedulers.get(name) {
+            Some(scheduler) => scheduler,
+            None => return Occurrences::empty(),
+        };
+        match self.disabled_schedulers.get(name) {
+            None => scheduler.occurrences(from),
+            Some(None) => Occurrences::empty(),
+            Some(Some(until)) => scheduler.occurrences(from.max(*until)),
+        }
+    }
 }
 
 pub fn weekday_to_lowercase_str(weekday: &Weekday) -> &'static str {
@@ -483,6 +1542,32 @@ pub fn weekday_to_lowercase_str(weekday: &Weekday) -> &'static str {
     }
 }
 
+/// A discontinuity of the wall clock, detected by the [`Controller`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockJump {
+    /// When it was detected, on the new wall clock.
+    pub at: OffsetDateTime,
+    /// How far the wall clock jumped. Negative if it went backwards.
+    pub offset: time::Duration,
+}
+
+/// The longest the [`Controller`] sleeps before checking the clock.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+/// Differences between how much the wall clock and the monotonic clock has advanced
+/// larger than this are [`ClockJump`]s.
+const CLOCK_JUMP_THRESHOLD: Duration = Duration::from_secs(2);
+
+/// Returns how far the wall clock jumped, if it advanced `wall` while the
+/// monotonic clock advanced `monotonic`.
+fn clock_jump(monotonic: Duration, wall: time::Duration) -> Option<time::Duration> {
+    let offset = wall - monotonic;
+    if offset.unsigned_abs() > CLOCK_JUMP_THRESHOLD {
+        Some(offset)
+    } else {
+        None
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 enum Sleeping {
     To(OffsetDateTime),
@@ -513,6 +1598,28 @@ impl ControllerSender {
         }
     }
 }
+type Listener = Box<dyn FnMut(&Change) + Send>;
+/// The listeners of [`Controller::subscribe`].
+#[derive(Default)]
+struct Listeners(Mutex<Vec<Listener>>);
+impl Listeners {
+    fn notify(&self, changes: &[Change]) {
+        let mut listeners = self.0.lock().unwrap();
+        for change in changes {
+            for listener in listeners.iter_mut() {
+                listener(change);
+            }
+        }
+    }
+}
+impl fmt::Debug for Listeners {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Listeners")
+            .field("len", &self.0.lock().unwrap().len())
+            .finish()
+    }
+}
+
 /// The handler's job is to handle [`Scheduler`]s and transitions.
 ///
 /// This is done by spawning a thread and running all code on it.
@@ -521,19 +1628,25 @@ pub struct Controller<T: VariableOut + Send + 'static> {
     channel: mpsc::SyncSender<Command>,
     handle: thread::JoinHandle<T>,
     shared_state: Arc<Mutex<SharedState>>,
+    listeners: Arc<Listeners>,
 }
 impl<T: VariableOut + Send + 'static> Controller<T> {
     pub fn new(mut output: T, scheduler: WeekScheduler) -> Self {
         // make channel
         let (sender, receiver) = mpsc::sync_channel(128);
 
-        let shared_state = Arc::new(Mutex::new(SharedState::new(scheduler)));
+        let mut shared_state = SharedState::new(scheduler);
+        shared_state.set_output(output.capabilities());
+        let shared_state = Arc::new(Mutex::new(shared_state));
 
         let shared = Arc::clone(&shared_state);
+        let listeners = Arc::new(Listeners::default());
+        let thread_listeners = Arc::clone(&listeners);
 
         let handle = thread::spawn(move || {
+            let listeners = thread_listeners;
             let receiver = receiver;
-            let mut state = scheduler::State::new(shared);
+            let mut state = scheduler::State::new(Arc::clone(&shared));
             let mut sleeping: Sleeping = Sleeping::Wake;
             let mut enabled = None;
 
@@ -545,13 +1658,16 @@ impl<T: VariableOut + Send + 'static> Controller<T> {
                 let transition_sleep_duration = Duration::from_millis(10);
                 #[cfg(feature = "test")]
                 let transition_sleep_duration = Duration::from_millis(100);
+                let before = (Instant::now(), get_now());
                 let sleep = match sleeping {
+                    // wake up regularly, to check if the clock has jumped
                     Sleeping::To(date_time) => {
-                        (date_time - get_now() - time::Duration::milliseconds(2))
+                        (date_time - before.1 - time::Duration::milliseconds(2))
                             .max(time::Duration::milliseconds(2))
                             .unsigned_abs()
+                            .min(MAX_SLEEP)
                     }
-                    Sleeping::Forever => Duration::MAX,
+                    Sleeping::Forever => MAX_SLEEP,
                     // this is part of a transition
                     Sleeping::Wake if command.is_none() => transition_sleep_duration,
                     Sleeping::Wake => Duration::ZERO,
@@ -565,6 +1681,21 @@ impl<T: VariableOut + Send + 'static> Controller<T> {
                         receiver.recv_timeout(sleep).ok()
                     }
                 });
+
+                if let Some(offset) = clock_jump(before.0.elapsed(), get_now() - before.1) {
+                    let jump = ClockJump {
+                        at: get_now(),
+                        offset,
+                    };
+                    println!(
+                        "Clock jumped {} at {}, rescheduling",
+                        jump.offset,
+                        jump.at.format(&DATE_TIME_TZ_FORMAT).unwrap()
+                    );
+                    shared.lock().unwrap().set_last_clock_jump(jump);
+                    // recalculate when to wake up
+                    sleeping = Sleeping::Wake;
+                }
                 let command = match rx {
                     Some(r) => {
                         sleeping = Sleeping::Wake;
@@ -577,6 +1708,10 @@ impl<T: VariableOut + Send + 'static> Controller<T> {
                     },
                 };
                 let action = state.process(command);
+                let changes = shared.lock().unwrap().take_changes();
+                if !changes.is_empty() {
+                    listeners.notify(&changes);
+                }
                 match action {
                     Action::Wait(sleep_time) => match sleep_time {
                         scheduler::SleepTime::To(date_time) => {
@@ -599,6 +1734,18 @@ impl<T: VariableOut + Send + 'static> Controller<T> {
                         output.set(s);
                         enabled = Some(s.into_inner());
                     }
+                    Action::SetFrequency(frequency) => {
+                        if let Err(err) = output.set_frequency(frequency) {
+                            println!("Failed to set frequency to {} Hz: {}", frequency, err);
+                        }
+                        shared.lock().unwrap().set_output(output.capabilities());
+                    }
+                    Action::SetResolution(bits) => {
+                        if let Err(err) = output.set_resolution(bits) {
+                            println!("Failed to set resolution to {} bits: {}", bits, err);
+                        }
+                        shared.lock().unwrap().set_output(output.capabilities());
+                    }
                     Action::Break => break,
                 }
             }
@@ -610,9 +1757,18 @@ impl<T: VariableOut + Send + 'static> Controller<T> {
             channel: sender,
             handle,
             shared_state,
+            listeners,
         }
     }
 
+    /// Calls `listener` with every [`Change`] of the [`SharedState`].
+    ///
+    /// It's called on the thread of the controller, so it should return quickly,
+    /// e.g. by sending the change on a channel.
+    pub fn subscribe(&self, listener: impl FnMut(&Change) + Send + 'static) {
+        self.listeners.0.lock().unwrap().push(Box::new(listener));
+    }
+
     pub fn to_sender(&self) -> ControllerSender {
         ControllerSender {
             channel: self.channel.clone(),
@@ -643,3 +1799,148 @@ impl<T: VariableOut + Send + 'static> Controller<T> {
         Arc::clone(&self.shared_state)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "embedded-hal")]
+    use embedded_hal_mock::eh1::{
+        pwm::{Mock, Transaction},
+        MockError,
+    };
+
+    /// An output with a fixed frequency, which only records the last value.
+    struct Fixed {
+        frequency: Option<f64>,
+        value: Option<f64>,
+    }
+    impl VariableOut for Fixed {
+        fn set(&mut self, value: Strength) {
+            self.value = Some(value.into_inner());
+        }
+        fn enable(&mut self) {}
+        fn disable(&mut self) {}
+        fn prepare(&mut self) {}
+        fn capabilities(&self) -> Capabilities {
+            Capabilities {
+                frequency: self.frequency,
+                ..Default::default()
+            }
+        }
+    }
+    fn fixed(frequency: Option<f64>) -> Fixed {
+        Fixed {
+            frequency,
+            value: None,
+        }
+    }
+
+    #[test]
+    fn clock_jumps() {
+        let minute = Duration::from_secs(60);
+        assert_eq!(
+            clock_jump(minute, time::Duration::minutes(61)),
+            Some(time::Duration::hours(1))
+        );
+        assert_eq!(
+            clock_jump(minute, time::Duration::minutes(-59)),
+            Some(time::Duration::hours(-1))
+        );
+        // the sleep overslept, or the clock was slewed
+        assert_eq!(clock_jump(minute, time::Duration::seconds(61)), None);
+        assert_eq!(clock_jump(minute, time::Duration::seconds(58)), None);
+        assert_eq!(
+            clock_jump(minute, time::Duration::seconds(63)),
+            Some(time::Duration::seconds(3))
+        );
+    }
+    #[test]
+    fn servo_fixed_frequency() {
+        let mut servo = Servo::new(fixed(Some(50.0))).unwrap();
+        servo.set(Strength::new(0.5));
+        // 1.5 ms of 20 ms
+        assert!((servo.into_inner().value.unwrap() - 0.075).abs() < 1e-9);
+
+        assert!(matches!(
+            Servo::new(fixed(Some(1000.0))),
+            Err(OutputError::Unsupported)
+        ));
+        // the frequency isn't known
+        assert!(matches!(
+            Servo::new(fixed(None)),
+            Err(OutputError::Unsupported)
+        ));
+        let range = (Duration::from_micros(1000), Duration::from_micros(2000));
+        assert!(Servo::with_assumed_frequency(
+            fixed(None),
+            Servo::<Fixed>::FREQUENCY,
+            range.0,
+            range.1,
+            false
+        )
+        .is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-hal")]
+    fn hal_pwm_duty_cycle() {
+        let mut pwm = HalPwm::new(Mock::new(&[
+            Transaction::max_duty_cycle(1000),
+            Transaction::set_duty_cycle(500),
+            Transaction::max_duty_cycle(1000),
+            Transaction::set_duty_cycle(1000),
+            Transaction::max_duty_cycle(1000),
+            Transaction::set_duty_cycle(333),
+            Transaction::max_duty_cycle(1000),
+            Transaction::set_duty_cycle(0),
+        ]));
+        pwm.set(Strength::new(0.5));
+        pwm.set(Strength::new(1.0));
+        pwm.set(Strength::new(1.0 / 3.0));
+        pwm.disable();
+        pwm.into_inner().done();
+    }
+    #[test]
+    #[cfg(feature = "embedded-hal")]
+    fn hal_pwm_inverted() {
+        let mut pwm = HalPwm::inverted(Mock::new(&[
+            Transaction::max_duty_cycle(255),
+            Transaction::set_duty_cycle(191),
+            Transaction::max_duty_cycle(255),
+            Transaction::set_duty_cycle(0),
+            Transaction::max_duty_cycle(255),
+            Transaction::set_duty_cycle(255),
+        ]));
+        pwm.set(Strength::new(0.25));
+        pwm.set(Strength::new(1.0));
+        pwm.prepare();
+        pwm.into_inner().done();
+    }
+    #[test]
+    #[cfg(feature = "embedded-hal")]
+    fn hal_pwm_error() {
+        let mut pwm = HalPwm::new(Mock::new(&[
+            Transaction::max_duty_cycle(100),
+            Transaction::set_duty_cycle(50)
+                .with_error(MockError::Io(std::io::ErrorKind::NotConnected)),
+            Transaction::max_duty_cycle(100),
+            Transaction::set_duty_cycle(100),
+        ]));
+        // the error is logged, and the output keeps working
+        pwm.set(Strength::new(0.5));
+        pwm.set(Strength::new(1.0));
+        pwm.into_inner().done();
+    }
+    #[test]
+    #[cfg(feature = "embedded-hal")]
+    fn hal_pwm_capabilities() {
+        for (max_duty, bits) in [(1, 1), (255, 8), (1000, 10), (4095, 12), (u16::MAX, 16)] {
+            let mut mock = Mock::new(&[Transaction::max_duty_cycle(max_duty)]);
+            let capabilities = HalPwm::new(mock.clone()).capabilities();
+            assert_eq!(capabilities.resolution, Some(bits));
+            assert_eq!(capabilities.frequency, None);
+            assert!(capabilities.frequency_range.is_none());
+            mock.done();
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! A registry of [`Scheduler`] kinds.
+//!
+//! A kind registers it's name, the parameters it takes and how to build the [`Scheduler`]
+//! from them. The parameters are (de)serialized using [`serde`], so any scheduler registered
+//! can be saved and sent over the network.
+use crate::{ClonableCommand, Scheduler};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::marker::PhantomData;
+
+/// The type of a [`Parameter`]. Used as a tip for users.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ParameterKind {
+    /// `hh:mm:ss` or `hh:mm`
+    Time,
+    /// `yyyy-mm-dd`
+    Date,
+    /// `mon`, `tue`, ...
+    Weekday,
+    Number,
+    String,
+    Bool,
+}
+
+/// A parameter a [`Scheduler`] kind takes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Parameter {
+    pub name: String,
+    pub kind: ParameterKind,
+    pub optional: bool,
+}
+impl Parameter {
+    pub fn new(name: impl Into<String>, kind: ParameterKind) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            optional: false,
+        }
+    }
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// No kind with the name is registered.
+    UnknownKind(String),
+    /// The parameters failed to (de)serialize.
+    Parameters(serde_json::Error),
+    /// The parameters have the correct types, but the values are invalid.
+    Invalid(String),
+}
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownKind(kind) => write!(f, "unknown scheduler kind {:?}", kind),
+            Self::Parameters(err) => write!(f, "invalid parameters: {}", err),
+            Self::Invalid(err) => write!(f, "invalid scheduler: {}", err),
+        }
+    }
+}
+impl std::error::Error for Error {}
+
+/// What's common to all [`Scheduler`]s built from a [`Registry`].
+#[derive(Debug, Clone)]
+pub struct Context {
+    pub description: String,
+    /// The command to run when the scheduler is triggered.
+    pub command: ClonableCommand,
+    /// If schedulers which only occur in the past should be accepted.
+    pub allow_past: bool,
+}
+
+trait Kind: Send + Sync {
+    fn parameters(&self) -> &[Parameter];
+    fn build(
+        &self,
+        parameters: serde_json::Value,
+        context: Context,
+    ) -> Result<(Box<dyn Scheduler>, serde_json::Value), Error>;
+}
+
+struct TypedKind<P, F> {
+    parameters: Vec<Parameter>,
+    build: F,
+    _params: PhantomData<fn() -> P>,
+}
+impl<P, F> Kind for TypedKind<P, F>
+where
+    P: Serialize + DeserializeOwned,
+    F: Fn(&P, Context) -> Result<Box<dyn Scheduler>, Error> + Send + Sync,
+{
+    fn parameters(&self) -> &[Parameter] {
+        &self.parameters
+    }
+    fn build(
+        &self,
+        parameters: serde_json::Value,
+        context: Context,
+    ) -> Result<(Box<dyn Scheduler>, serde_json::Value), Error> {
+        let parameters: P = serde_json::from_value(parameters).map_err(Error::Parameters)?;
+        let normalized = serde_json::to_value(&parameters).map_err(Error::Parameters)?;
+        let scheduler = (self.build)(&parameters, context)?;
+        Ok((scheduler, normalized))
+    }
+}
+
+/// A collection of [`Scheduler`] kinds, identified by their name.
+#[derive(Default)]
+pub struct Registry {
+    kinds: BTreeMap<String, Box<dyn Kind>>,
+}
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a kind of [`Scheduler`] with `name`.
+    /// The scheduler takes the parameters `P`, which are described by `parameters`.
+    ///
+    /// If a kind with `name` is already registered, it's replaced.
+    pub fn register<P, F>(&mut self, name: impl Into<String>, parameters: Vec<Parameter>, build: F)
+    where
+        P: Serialize + DeserializeOwned + 'static,
+        F: Fn(&P, Context) -> Result<Box<dyn Scheduler>, Error> + Send + Sync + 'static,
+    {
+        self.kinds.insert(
+            name.into(),
+            Box::new(TypedKind {
+                parameters,
+                build,
+                _params: PhantomData,
+            }),
+        );
+    }
+
+    /// Builds a [`Scheduler`] of `kind`.
+    ///
+    /// Returns the scheduler and the normalized parameters, to save.
+    pub fn build(
+        &self,
+        kind: &str,
+        parameters: serde_json::Value,
+        context: Context,
+    ) -> Result<(Box<dyn Scheduler>, serde_json::Value), Error> {
+        self.kinds
+            .get(kind)
+            .ok_or_else(|| Error::UnknownKind(kind.to_string()))?
+            .build(parameters, context)
+    }
+
+    /// Get the parameters of `kind`, if it's registered.
+    pub fn get_parameters(&self, kind: &str) -> Option<&[Parameter]> {
+        self.kinds.get(kind).map(|kind| kind.parameters())
+    }
+    /// Iterates the names and parameters of all registered kinds.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[Parameter])> {
+        self.kinds
+            .iter()
+            .map(|(name, kind)| (name.as_str(), kind.parameters()))
+    }
+}
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,341 @@
+//! Slow on/off outputs, for heaters, pumps and lamps behind relays.
+//!
+//! A [`Relay`] turns any [`Switch`] into a [`VariableOut`], either by
+//! [time proportioning](RelayMode::TimeProportioning) (PWM with a period of seconds to minutes)
+//! or by a [threshold with hysteresis](RelayMode::Hysteresis).
+//! The minimum on and off times protect the contacts from switching too often.
+use crate::{Capabilities, Strength, VariableOut};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Something which can be turned on and off, e.g. a GPIO pin driving a relay.
+pub trait Switch {
+    fn switch(&mut self, on: bool);
+}
+impl<F: FnMut(bool)> Switch for F {
+    fn switch(&mut self, on: bool) {
+        self(on)
+    }
+}
+#[cfg(feature = "rpi")]
+impl Switch for rppal::gpio::OutputPin {
+    fn switch(&mut self, on: bool) {
+        if on {
+            self.set_high();
+        } else {
+            self.set_low();
+        }
+    }
+}
+
+/// How a [`Relay`] turns the strength into on and off.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelayMode {
+    /// On for `strength * period` every `period`.
+    ///
+    /// If the on time is shorter than [`Relay`]'s `min_on`, it's off the whole period.
+    /// If the off time is shorter than `min_off`, it's on the whole period.
+    TimeProportioning { period: Duration },
+    /// Turns on when the strength reaches `on` and off when it falls to `off`.
+    /// `off` should be less than `on`, else it switches every time the strength passes `on`.
+    Hysteresis { on: f64, off: f64 },
+}
+
+#[derive(Debug)]
+struct State {
+    strength: f64,
+    enabled: bool,
+    stop: bool,
+}
+
+/// An on/off [`Switch`] driven by a [`Strength`]. See [the module](self).
+///
+/// A thread switches the output. It's turned off when this is dropped.
+#[derive(Debug)]
+pub struct Relay {
+    mode: RelayMode,
+    state: Arc<(Mutex<State>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+impl Relay {
+    /// Starts the thread which drives `switch`. The output starts off.
+    ///
+    /// The output is never on for less than `min_on` or off for less than `min_off`,
+    /// except when the relay is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the period of [`RelayMode::TimeProportioning`] is zero.
+    pub fn new(
+        mut switch: impl Switch + Send + 'static,
+        mode: RelayMode,
+        min_on: Duration,
+        min_off: Duration,
+    ) -> Self {
+        if let RelayMode::TimeProportioning { period } = mode {
+            assert!(!period.is_zero(), "the period of a relay can't be zero");
+        }
+        let state = Arc::new((
+            Mutex::new(State {
+                strength: 0.0,
+                enabled: false,
+                stop: false,
+            }),
+            Condvar::new(),
+        ));
+        let thread_state = Arc::clone(&state);
+        let handle = thread::spawn(move || {
+            let (state, condvar) = &*thread_state;
+            let mut on = false;
+            let mut last_switch: Option<Instant> = None;
+            let mut period_start = Instant::now();
+            switch.switch(false);
+
+            let mut state = state.lock().unwrap();
+            loop {
+                if state.stop {
+                    break;
+                }
+                let now = Instant::now();
+                // when to check again, if nothing changes
+                let mut wake = None;
+                let wanted = match mode {
+                    _ if !state.enabled => false,
+                    RelayMode::Hysteresis {
+                        on: on_at,
+                        off: off_at,
+                    } => {
+                        if on {
+                            state.strength > off_at
+                        } else {
+                            state.strength >= on_at
+                        }
+                    }
+                    RelayMode::TimeProportioning { period } => {
+                        while now.duration_since(period_start) >= period {
+                            period_start += period;
+                        }
+                        let mut on_time = period.mul_f64(state.strength);
+                        if on_time < min_on {
+                            on_time = Duration::ZERO;
+                        } else if period - on_time < min_off {
+                            on_time = period;
+                        }
+                        let elapsed = now.duration_since(period_start);
+                        if elapsed < on_time {
+                            wake = Some(period_start + on_time);
+                            true
+                        } else {
+                            wake = Some(period_start + period);
+                            false
+                        }
+                    }
+                };
+                if wanted != on {
+                    let min = if on { min_on } else { min_off };
+                    match last_switch {
+                        Some(last) if now.duration_since(last) < min => {
+                            wake = Some(last + min);
+                        }
+                        _ => {
+                            switch.switch(wanted);
+                            on = wanted;
+                            last_switch = Some(now);
+                            continue;
+                        }
+                    }
+                }
+                state = match wake {
+                    Some(wake) => {
+                        condvar
+                            .wait_timeout(state, wake.saturating_duration_since(now))
+                            .unwrap()
+                            .0
+                    }
+                    None => condvar.wait(state).unwrap(),
+                };
+            }
+            if on {
+                switch.switch(false);
+            }
+        });
+        Self {
+            mode,
+            state,
+            handle: Some(handle),
+        }
+    }
+    pub fn mode(&self) -> RelayMode {
+        self.mode
+    }
+    fn update(&self, f: impl FnOnce(&mut State)) {
+        let (state, condvar) = &*self.state;
+        f(&mut state.lock().unwrap());
+        condvar.notify_one();
+    }
+}
+impl VariableOut for Relay {
+    fn set(&mut self, value: Strength) {
+        self.update(|state| state.strength = value.into_inner());
+    }
+    fn enable(&mut self) {
+        println!("Enabling relay.");
+        self.update(|state| state.enabled = true);
+    }
+    fn disable(&mut self) {
+        println!("Disabling relay.");
+        self.update(|state| state.enabled = false);
+    }
+    fn prepare(&mut self) {}
+    fn capabilities(&self) -> Capabilities {
+        match self.mode {
+            RelayMode::TimeProportioning { period } => Capabilities {
+                frequency: Some(1.0 / period.as_secs_f64()),
+                ..Default::default()
+            },
+            RelayMode::Hysteresis { .. } => Capabilities::default(),
+        }
+    }
+}
+impl Drop for Relay {
+    fn drop(&mut self) {
+        self.update(|state| state.stop = true);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Switches = Arc<Mutex<Vec<(Instant, bool)>>>;
+
+    fn recorded(mode: RelayMode, min_on: Duration, min_off: Duration) -> (Relay, Switches) {
+        let switches = Switches::default();
+        let record = Arc::clone(&switches);
+        let relay = Relay::new(
+            move |on| record.lock().unwrap().push((Instant::now(), on)),
+            mode,
+            min_on,
+            min_off,
+        );
+        (relay, switches)
+    }
+    fn states(switches: &Switches) -> Vec<bool> {
+        switches.lock().unwrap().iter().map(|(_, on)| *on).collect()
+    }
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+    fn settle() {
+        thread::sleep(ms(30));
+    }
+
+    #[test]
+    fn time_proportioning() {
+        let (mut relay, switches) = recorded(
+            RelayMode::TimeProportioning { period: ms(200) },
+            Duration::ZERO,
+            Duration::ZERO,
+        );
+        relay.enable();
+        relay.set(Strength::new(0.25));
+        thread::sleep(ms(700));
+        drop(relay);
+
+        let switches = switches.lock().unwrap();
+        assert!(!switches[0].1);
+        let switches = &switches[1..];
+        assert!(switches.len() >= 6, "{:?}", switches);
+        for (i, pair) in switches.chunks_exact(2).enumerate() {
+            let ((on_at, on), (off_at, off)) = (pair[0], pair[1]);
+            assert!(on && !off);
+            let on_time = off_at - on_at;
+            assert!(on_time > ms(35) && on_time < ms(80), "{:?}", on_time);
+            if let Some((next, _)) = switches.get(i * 2 + 2) {
+                let period = *next - on_at;
+                assert!(period > ms(180) && period < ms(230), "{:?}", period);
+            }
+        }
+    }
+    #[test]
+    fn time_proportioning_min_times() {
+        let mode = RelayMode::TimeProportioning { period: ms(200) };
+        // on for 40ms, which is shorter than `min_on`
+        let (mut relay, switches) = recorded(mode, ms(60), ms(60));
+        relay.enable();
+        relay.set(Strength::new(0.2));
+        thread::sleep(ms(450));
+        drop(relay);
+        assert_eq!(states(&switches), [false]);
+
+        // off for 40ms, which is shorter than `min_off`
+        let (mut relay, switches) = recorded(mode, ms(60), ms(60));
+        relay.enable();
+        relay.set(Strength::new(0.8));
+        thread::sleep(ms(450));
+        assert_eq!(states(&switches), [false, true]);
+        drop(relay);
+        assert_eq!(states(&switches), [false, true, false]);
+    }
+    #[test]
+    fn min_interval_defers() {
+        let (mut relay, switches) = recorded(
+            RelayMode::Hysteresis { on: 0.5, off: 0.3 },
+            ms(200),
+            ms(200),
+        );
+        relay.enable();
+        relay.set(Strength::new(1.0));
+        settle();
+        relay.set(Strength::new(0.0));
+        settle();
+        assert_eq!(states(&switches), [false, true]);
+        thread::sleep(ms(200));
+        assert_eq!(states(&switches), [false, true, false]);
+        relay.set(Strength::new(1.0));
+        settle();
+        assert_eq!(states(&switches), [false, true, false]);
+        thread::sleep(ms(200));
+        assert_eq!(states(&switches), [false, true, false, true]);
+
+        let switches = switches.lock().unwrap();
+        let on_time = switches[2].0 - switches[1].0;
+        let off_time = switches[3].0 - switches[2].0;
+        assert!(on_time >= ms(200) && on_time < ms(250), "{:?}", on_time);
+        assert!(off_time >= ms(200) && off_time < ms(250), "{:?}", off_time);
+    }
+    #[test]
+    fn hysteresis() {
+        let (mut relay, switches) = recorded(
+            RelayMode::Hysteresis { on: 0.6, off: 0.3 },
+            Duration::ZERO,
+            Duration::ZERO,
+        );
+        relay.set(Strength::new(1.0));
+        settle();
+        // not enabled
+        assert_eq!(states(&switches), [false]);
+        relay.enable();
+        let steps = [
+            (0.5, false),
+            (0.6, true),
+            (0.4, true),
+            (0.31, true),
+            (0.3, false),
+            (0.5, false),
+            (0.7, true),
+        ];
+        for (strength, on) in steps {
+            relay.set(Strength::new(strength));
+            settle();
+            assert_eq!(states(&switches).last(), Some(&on), "at {}", strength);
+        }
+        relay.disable();
+        settle();
+        assert_eq!(states(&switches), [false, true, false, true, false]);
+    }
+}
@@ -0,0 +1,1447 @@
+//! The state saved between restarts.
+//!
+//! [`Data`] is (de)serialized as RON, and can be loaded with [`Data::load`] and
+//! sent to a [`Controller`] using [`Data::apply`].
+//!
+//! The data is versioned (see [`VERSION`]); older versions are migrated when loaded.
+use crate::store::Store;
+use crate::{
+    datas, registry, CatchUp, Change, Command, Controller, ControllerSender, Scene, SharedState,
+    SleepTimer, Strength, Transition, VariableOut, WeekScheduler, Weekday, DEFAULT_WEEK_SCHEDULER,
+    SECOND_FORMAT,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Display};
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use time::format_description::well_known::Rfc3339;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WeekSchedulerData {
+    pub mon: Option<String>,
+    pub tue: Option<String>,
+    pub wed: Option<String>,
+    pub thu: Option<String>,
+    pub fri: Option<String>,
+    pub sat: Option<String>,
+    pub sun: Option<String>,
+    pub transition: datas::TransitionData,
+}
+impl WeekSchedulerData {
+    pub fn get_mut(&mut self, day: Weekday) -> &mut Option<String> {
+        match day {
+            Weekday::Mon => &mut self.mon,
+            Weekday::Tue => &mut self.tue,
+            Weekday::Wed => &mut self.wed,
+            Weekday::Thu => &mut self.thu,
+            Weekday::Fri => &mut self.fri,
+            Weekday::Sat => &mut self.sat,
+            Weekday::Sun => &mut self.sun,
+        }
+    }
+    pub fn from_scheduler(scheduler: &WeekScheduler) -> Self {
+        macro_rules! fmt_time {
+            ($e:expr) => {
+                $e.map(|time| time.format(&SECOND_FORMAT).unwrap())
+            };
+        }
+
+        WeekSchedulerData {
+            mon: fmt_time!(scheduler.mon),
+            tue: fmt_time!(scheduler.tue),
+            wed: fmt_time!(scheduler.wed),
+            thu: fmt_time!(scheduler.thu),
+            fri: fmt_time!(scheduler.fri),
+            sat: fmt_time!(scheduler.sat),
+            sun: fmt_time!(scheduler.sun),
+            transition: datas::TransitionData::from_transition(&scheduler.transition),
+        }
+    }
+    pub fn to_scheduler(&self) -> Option<WeekScheduler> {
+        macro_rules! fmt_time {
+            ($e:expr) => {
+                match $e.as_ref() {
+                    Some(time) => Some(time::Time::parse(time.as_str(), SECOND_FORMAT).ok()?),
+                    None => None,
+                }
+            };
+        }
+
+        let mut scheduler = WeekScheduler::empty(self.transition.to_transition()?);
+
+        scheduler.mon = fmt_time!(self.mon);
+        scheduler.tue = fmt_time!(self.tue);
+        scheduler.wed = fmt_time!(self.wed);
+        scheduler.thu = fmt_time!(self.thu);
+        scheduler.fri = fmt_time!(self.fri);
+        scheduler.sat = fmt_time!(self.sat);
+        scheduler.sun = fmt_time!(self.sun);
+        Some(scheduler)
+    }
+}
+/// How long [`Saver`] waits for more changes before writing.
+pub const DEBOUNCE: Duration = Duration::from_secs(2);
+/// The longest [`Saver`] delays writing when changes keep coming.
+pub const MAX_DELAY: Duration = Duration::from_secs(30);
+
+enum Message {
+    Change(Change),
+    Modified,
+    Flush(mpsc::SyncSender<()>),
+}
+
+/// Writes [`Data`] to a [`Store`] when it changes.
+///
+/// The data is updated as soon as the controller changes, but bursts of changes are
+/// coalesced; the data is written when no changes have been made for [`DEBOUNCE`],
+/// or at most [`MAX_DELAY`] after the first.
+#[derive(Debug)]
+pub struct Saver {
+    data: Arc<Mutex<Data>>,
+    channel: mpsc::SyncSender<Message>,
+}
+impl Saver {
+    /// Spawns a thread writing `data` to `key` in `store`.
+    ///
+    /// `state` is the state of the [`Controller`] given to [`Saver::subscribe`].
+    pub fn new(
+        data: Data,
+        state: Arc<Mutex<SharedState>>,
+        store: Arc<Mutex<Box<dyn Store>>>,
+        key: impl Into<String>,
+    ) -> Self {
+        Self::with_delays(data, state, store, key, DEBOUNCE, MAX_DELAY)
+    }
+    /// Same as [`Saver::new`], but waiting `debounce` for more changes and delaying
+    /// writes at most `max_delay` instead of [`DEBOUNCE`] and [`MAX_DELAY`].
+    pub fn with_delays(
+        data: Data,
+        state: Arc<Mutex<SharedState>>,
+        store: Arc<Mutex<Box<dyn Store>>>,
+        key: impl Into<String>,
+        debounce: Duration,
+        max_delay: Duration,
+    ) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(128);
+        let data = Arc::new(Mutex::new(data));
+        let key = key.into();
+        {
+            let data = Arc::clone(&data);
+            thread::spawn(move || {
+                let write = || {
+                    let data = data.lock().unwrap();
+                    println!("Saving state!");
+                    if let Err(err) = data.write_to_store(&mut **store.lock().unwrap(), &key) {
+                        println!("Failed to save state: {}", err);
+                    }
+                };
+
+                // when the first unsaved change was made
+                let mut first: Option<Instant> = None;
+                loop {
+                    let message = match first {
+                        None => match receiver.recv() {
+                            Ok(message) => message,
+                            Err(_) => break,
+                        },
+                        Some(first_change) => {
+                            let timeout =
+                                debounce.min(max_delay.saturating_sub(first_change.elapsed()));
+                            match receiver.recv_timeout(timeout) {
+                                Ok(message) => message,
+                                Err(mpsc::RecvTimeoutError::Timeout) => {
+                                    write();
+                                    first = None;
+                                    continue;
+                                }
+                                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                                    write();
+                                    break;
+                                }
+                            }
+                        }
+                    };
+                    match message {
+                        Message::Change(change) => {
+                            // update right away, so the data can be read
+                            data.lock().unwrap().update(&change, &state.lock().unwrap());
+                            first.get_or_insert_with(Instant::now);
+                        }
+                        Message::Modified => {
+                            first.get_or_insert_with(Instant::now);
+                        }
+                        Message::Flush(done) => {
+                            if first.take().is_some() {
+                                write();
+                            }
+                            let _ = done.send(());
+                        }
+                    }
+                }
+            });
+        }
+        Self {
+            data,
+            channel: sender,
+        }
+    }
+    /// Saves the data when the [`SharedState`] of `controller` changes.
+    pub fn subscribe<T: VariableOut + Send>(&self, controller: &Controller<T>) {
+        let channel = self.channel.clone();
+        controller.subscribe(move |change| {
+            let _ = channel.send(Message::Change(change.clone()));
+        });
+    }
+    /// Locks the data. Use [`Saver::modify`] to change it.
+    pub fn lock(&self) -> MutexGuard<'_, Data> {
+        self.data.lock().unwrap()
+    }
+    /// Changes the data and saves it.
+    ///
+    /// Only needed for data not in the [`SharedState`], such as [`Data::schedulers`]
+    /// and [`Data::power_on`]; the rest is saved when the controller changes it.
+    pub fn modify<R>(&self, f: impl FnOnce(&mut Data) -> R) -> R {
+        let r = f(&mut self.lock());
+        let _ = self.channel.send(Message::Modified);
+        r
+    }
+    /// Writes any unsaved changes and waits for it to finish.
+    pub fn flush(&self) {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        if self.channel.send(Message::Flush(sender)).is_ok() {
+            let _ = receiver.recv();
+        }
+    }
+}
+
+/// The version of [`Data`] written by this release.
+///
+/// Bump it when the format changes, and add a migration to [`Data::migrate`].
+///
+/// - `0`: no `version` field. A single `week_scheduler` and schedulers with
+///   `time`, `extras` and `transition`.
+/// - `1`: named `week_schedulers` and schedulers with `params` and `action`.
+pub const VERSION: u32 = 1;
+
+/// The key the last state which loaded is kept at.
+pub fn backup_key(key: &str) -> String {
+    format!("{}.bak", key)
+}
+/// The key a state which failed to load is moved to.
+pub fn quarantine_key(key: &str) -> String {
+    format!("{}.bad", key)
+}
+
+fn to_ron<T: Serialize>(value: &T) -> io::Result<String> {
+    let config =
+        ron::ser::PrettyConfig::default().extensions(ron::extensions::Extensions::IMPLICIT_SOME);
+    ron::ser::to_string_pretty(value, config)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn default_week_scheduler_name() -> String {
+    DEFAULT_WEEK_SCHEDULER.to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Data {
+    /// The [`VERSION`] the data was written by. `0` if it's from before versioning.
+    #[serde(default)]
+    pub version: u32,
+    pub strength: Option<f64>,
+    /// The strength [`Command::Toggle`] turns on to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_on_strength: Option<f64>,
+    pub schedulers: Vec<datas::AddSchedulerData>,
+    /// The single week scheduler of old save files.
+    /// Moved to [`Data::week_schedulers`] by [`Data::fill_week_schedulers`].
+    #[serde(default, skip_serializing)]
+    pub week_scheduler: Option<WeekSchedulerData>,
+    #[serde(default)]
+    pub week_schedulers: BTreeMap<String, WeekSchedulerData>,
+    #[serde(default = "default_week_scheduler_name")]
+    pub default_week_scheduler: String,
+    /// The disabled schedulers, with the optional RFC 3339 date time they're enabled again.
+    #[serde(default)]
+    pub disabled_schedulers: BTreeMap<String, Option<String>>,
+    /// The occurrence each scheduler last ran, as RFC 3339.
+    #[serde(default)]
+    pub last_runs: BTreeMap<String, String>,
+    #[serde(default)]
+    pub catch_up: datas::CatchUpData,
+    #[serde(default)]
+    pub power_on: datas::PowerOnData,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sleep_timer: Option<datas::SleepTimerData>,
+    #[serde(default)]
+    pub scenes: BTreeMap<String, datas::SceneData>,
+    /// The effect running when the data was saved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effect: Option<datas::EffectData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_transition: Option<datas::TransitionData>,
+    /// When [`Data::current_transition`] started, as RFC 3339.
+    /// If [`None`], it's restarted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_transition_start: Option<String>,
+}
+impl Data {
+    /// Parses and [migrates](Self::migrate) `bytes`.
+    ///
+    /// Week schedulers which fail to parse are skipped, and the default one
+    /// is replaced by `week_scheduler`.
+    ///
+    /// Returns [`io::ErrorKind::InvalidData`] if the data is corrupt.
+    pub fn parse(bytes: &[u8], week_scheduler: &WeekScheduler) -> io::Result<Self> {
+        let mut data: Self = ron::de::from_bytes(bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        data.migrate(week_scheduler)?;
+        data.week_schedulers.retain(|name, scheduler| {
+            let valid = scheduler.to_scheduler().is_some();
+            if !valid {
+                println!("Failed to load week scheduler {:?}. Skipping it.", name);
+            }
+            valid || name == DEFAULT_WEEK_SCHEDULER
+        });
+        if let Some(default) = data.week_schedulers.get_mut(DEFAULT_WEEK_SCHEDULER) {
+            if default.to_scheduler().is_none() {
+                *default = WeekSchedulerData::from_scheduler(week_scheduler);
+            }
+        }
+        data.fill_week_schedulers(week_scheduler);
+        Ok(data)
+    }
+    pub fn read_from_file<P: AsRef<Path>>(
+        path: P,
+        week_scheduler: &WeekScheduler,
+    ) -> io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        Self::parse(&bytes, week_scheduler)
+    }
+    /// Reads the data at `key` in `store`.
+    ///
+    /// Returns [`io::ErrorKind::NotFound`] if `key` isn't set.
+    pub fn read_from_store(
+        store: &dyn Store,
+        key: &str,
+        week_scheduler: &WeekScheduler,
+    ) -> io::Result<Self> {
+        let bytes = store
+            .get(key)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no saved state"))?;
+        Self::parse(&bytes, week_scheduler)
+    }
+    /// Loads the data at `key` in `store`, without ever losing saved data.
+    ///
+    /// If the data is corrupt, it's moved to [`quarantine_key`] and the
+    /// [backup](backup_key) is loaded instead.
+    /// When the data loads, it's copied to the backup.
+    ///
+    /// Returns [`None`] if neither is usable.
+    /// Keep `key` under 12 bytes, as the backup & quarantine keys are 4 bytes longer.
+    pub fn load(
+        store: &mut dyn Store,
+        key: &str,
+        week_scheduler: &WeekScheduler,
+    ) -> io::Result<Option<Self>> {
+        let backup = backup_key(key);
+        if let Some(bytes) = store.get(key)? {
+            match Self::parse(&bytes, week_scheduler) {
+                Ok(data) => {
+                    store.put(&backup, &bytes)?;
+                    return Ok(Some(data));
+                }
+                Err(err) => {
+                    let quarantine = quarantine_key(key);
+                    println!(
+                        "Failed to load {:?}: {}. Moving it to {:?}.",
+                        key, err, quarantine
+                    );
+                    store.put(&quarantine, &bytes)?;
+                    store.remove(key)?;
+                }
+            }
+        }
+        match store.get(&backup)? {
+            Some(bytes) => match Self::parse(&bytes, week_scheduler) {
+                Ok(data) => {
+                    println!("Restored {:?} from backup {:?}.", key, backup);
+                    Ok(Some(data))
+                }
+                Err(err) => {
+                    println!("Failed to load backup {:?}: {}", backup, err);
+                    Ok(None)
+                }
+            },
+            None => Ok(None),
+        }
+    }
+    /// Migrates data from older [versions](VERSION).
+    ///
+    /// Returns an error if the data is from a newer version.
+    pub fn migrate(&mut self, week_scheduler: &WeekScheduler) -> io::Result<()> {
+        if self.version > VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "saved by a newer version ({}, this is {})",
+                    self.version, VERSION
+                ),
+            ));
+        }
+        if self.version < 1 {
+            for scheduler in &mut self.schedulers {
+                scheduler.normalize();
+            }
+        }
+        self.fill_week_schedulers(week_scheduler);
+        self.version = VERSION;
+        Ok(())
+    }
+    /// Writes the data to `key` in `store`.
+    pub fn write_to_store(&self, store: &mut dyn Store, key: &str) -> io::Result<()> {
+        store.put(key, to_ron(self)?.as_bytes())
+    }
+    /// Gets the configuration to copy to another device.
+    ///
+    /// `timezone` is the timezone offset, as `+hh:mm`.
+    pub fn export(&self, timezone: Option<String>) -> Export {
+        Export {
+            version: VERSION,
+            timezone,
+            week_schedulers: self.week_schedulers.clone(),
+            default_week_scheduler: Some(self.default_week_scheduler.clone()),
+            schedulers: self.schedulers.clone(),
+            scenes: self.scenes.clone(),
+            catch_up: Some(self.catch_up),
+            power_on: Some(self.power_on.clone()),
+        }
+    }
+    pub fn from_week_scheduler(scheduler: &WeekScheduler) -> Self {
+        let mut week_schedulers = BTreeMap::new();
+        week_schedulers.insert(
+            DEFAULT_WEEK_SCHEDULER.to_string(),
+            WeekSchedulerData::from_scheduler(scheduler),
+        );
+        Self {
+            version: VERSION,
+            strength: None,
+            last_on_strength: None,
+            schedulers: Vec::new(),
+            week_scheduler: None,
+            week_schedulers,
+            default_week_scheduler: default_week_scheduler_name(),
+            disabled_schedulers: BTreeMap::new(),
+            last_runs: BTreeMap::new(),
+            catch_up: datas::CatchUpData::default(),
+            power_on: datas::PowerOnData::default(),
+            sleep_timer: None,
+            scenes: BTreeMap::new(),
+            effect: None,
+            current_transition: None,
+            current_transition_start: None,
+        }
+    }
+    /// Moves the week scheduler of old save files to [`Data::week_schedulers`].
+    /// If no week scheduler is saved, `default` is used.
+    pub fn fill_week_schedulers(&mut self, default: &WeekScheduler) {
+        if let Some(week_scheduler) = self.week_scheduler.take() {
+            self.week_schedulers
+                .entry(DEFAULT_WEEK_SCHEDULER.to_string())
+                .or_insert(week_scheduler);
+        }
+        if self.week_schedulers.is_empty() {
+            self.week_schedulers.insert(
+                DEFAULT_WEEK_SCHEDULER.to_string(),
+                WeekSchedulerData::from_scheduler(default),
+            );
+        }
+    }
+    /// Gets the [`WeekScheduler`] to give to [`Controller::new`].
+    ///
+    /// Returns [`None`] if the default week scheduler failed to parse.
+    pub fn default_week_scheduler(&self) -> Option<WeekScheduler> {
+        match self.week_schedulers.get(DEFAULT_WEEK_SCHEDULER) {
+            Some(scheduler) => scheduler.to_scheduler(),
+            None => Some(WeekScheduler::default()),
+        }
+    }
+
+    pub fn apply<T: VariableOut + Send>(
+        &self,
+        controller: &Controller<T>,
+        registry: &registry::Registry,
+    ) {
+        controller.send(Command::SetCatchUp(self.catch_up.to_catch_up()));
+        for (name, scene) in &self.scenes {
+            match scene.to_scene() {
+                Some(scene) => controller.send(Command::SetScene(name.clone(), scene)),
+                None => println!("Failed to load scene {:?}", name),
+            }
+        }
+        if let Some(command) = self.power_on.level.to_command(self.strength) {
+            controller.send(command);
+        }
+        if let Some(s) = self.last_on_strength {
+            controller.send(Command::SetLastOnStrength(Strength::new_clamped(s)));
+        }
+        // the default was given to `Controller::new`
+        if !self.week_schedulers.contains_key(DEFAULT_WEEK_SCHEDULER) {
+            controller.send(Command::RemoveScheduler(DEFAULT_WEEK_SCHEDULER.to_string()));
+        }
+        for (name, scheduler) in &self.week_schedulers {
+            if name == DEFAULT_WEEK_SCHEDULER {
+                continue;
+            }
+            if let Some(scheduler) = scheduler.to_scheduler() {
+                controller.send(Command::AddReplaceScheduler(
+                    name.clone(),
+                    Box::new(scheduler),
+                ));
+            }
+        }
+        controller.send(Command::SetDefaultWeekScheduler(
+            self.default_week_scheduler.clone(),
+        ));
+        for scheduler in &self.schedulers {
+            match scheduler.clone().to_command(true, registry) {
+                Ok(command) => controller.send(command),
+                Err(err) => println!("Failed to load scheduler {:?}: {}", scheduler.name, err),
+            }
+        }
+        // adding the schedulers cleared if they're disabled and when they last ran
+        for (name, until) in self.disabled_schedulers() {
+            controller.send(Command::DisableScheduler(name, until));
+        }
+        controller.send(Command::SetLastRuns(self.last_runs()));
+        if let Some(sleep_timer) = self.sleep_timer() {
+            controller.send(Command::SetSleepTimer(sleep_timer.end, sleep_timer.fade));
+        }
+        if let Some(transition) = self
+            .current_transition
+            .as_ref()
+            .filter(|_| self.power_on.resume_transition)
+            .and_then(datas::TransitionData::to_transition)
+        {
+            let start = self
+                .current_transition_start
+                .as_ref()
+                .and_then(|start| time::OffsetDateTime::parse(start, &Rfc3339).ok());
+            match start {
+                Some(start) => controller.send(Command::ResumeTransition(transition, start)),
+                None => controller.send(Command::SetTransition(transition)),
+            }
+        }
+        if let Some(effect) = self
+            .effect
+            .as_ref()
+            .filter(|_| self.power_on.resume_transition)
+            .and_then(datas::EffectData::to_effect)
+        {
+            controller.send(Command::SetEffect(effect));
+        }
+    }
+    /// Updates the data with `change`, made to `state`.
+    ///
+    /// Only week schedulers can be saved from `state`. The other [`Data::schedulers`]
+    /// have to be added when they're sent to the controller.
+    pub fn update(&mut self, change: &Change, state: &SharedState) {
+        match change {
+            Change::Strength => {
+                self.strength = Some(state.get_strength().into_inner());
+                self.last_on_strength = Some(state.get_last_on_strength().into_inner());
+            }
+            Change::Transition => {
+                self.set_transition(state.get_transition().zip(state.get_transition_start()));
+            }
+            Change::Effect => {
+                self.effect = state.get_effect().map(datas::EffectData::from_effect);
+            }
+            Change::Scheduler(name) => match state.ref_week_scheduler(name) {
+                Some(scheduler) => {
+                    self.schedulers.retain(|scheduler| &scheduler.name != name);
+                    self.set_week_scheduler(name.clone(), scheduler);
+                }
+                None => {
+                    self.week_schedulers.remove(name);
+                }
+            },
+            // it might have been added again since
+            Change::SchedulerRemoved(name) if !state.ref_schedulers().contains_key(name) => {
+                self.schedulers.retain(|scheduler| &scheduler.name != name);
+                self.week_schedulers.remove(name);
+            }
+            Change::SchedulerRemoved(_) => {}
+            Change::DefaultWeekScheduler => {
+                self.default_week_scheduler = state.get_default_week_scheduler().to_string();
+            }
+            Change::DisabledSchedulers => {
+                self.set_disabled_schedulers(state.ref_disabled_schedulers());
+            }
+            Change::LastRuns => self.set_last_runs(state.ref_last_runs()),
+            Change::CatchUp => {
+                self.catch_up = datas::CatchUpData::from_catch_up(state.get_catch_up());
+            }
+            Change::SleepTimer => {
+                self.sleep_timer = state
+                    .get_sleep_timer()
+                    .map(datas::SleepTimerData::from_sleep_timer);
+            }
+            Change::Scenes => {
+                self.scenes = state
+                    .ref_scenes()
+                    .iter()
+                    .map(|(name, scene)| (name.clone(), datas::SceneData::from_scene(scene)))
+                    .collect();
+            }
+            // not saved; it's set by the config when starting
+            Change::Output => {}
+        }
+    }
+
+    pub fn ref_strength(&self) -> Option<f64> {
+        self.strength
+    }
+    pub fn set_strength(&mut self, strength: Strength) -> Option<Strength> {
+        self.strength
+            .replace(strength.into_inner())
+            .map(Strength::new_clamped)
+    }
+
+    pub fn ref_schedulers(&self) -> &Vec<datas::AddSchedulerData> {
+        &self.schedulers
+    }
+    pub fn mut_schedulers(&mut self) -> &mut Vec<datas::AddSchedulerData> {
+        &mut self.schedulers
+    }
+
+    pub fn ref_week_scheduler(&self, name: &str) -> Option<&WeekSchedulerData> {
+        self.week_schedulers.get(name)
+    }
+    /// Gets the week scheduler with `name` or inserts an empty one,
+    /// as [`Command::ChangeWeekDayTimer`] does.
+    pub fn mut_week_scheduler(&mut self, name: &str) -> &mut WeekSchedulerData {
+        self.week_schedulers
+            .entry(name.to_string())
+            .or_insert_with(|| WeekSchedulerData::from_scheduler(&WeekScheduler::default()))
+    }
+    pub fn set_week_scheduler(
+        &mut self,
+        name: String,
+        new: &WeekScheduler,
+    ) -> Option<WeekSchedulerData> {
+        self.week_schedulers
+            .insert(name, WeekSchedulerData::from_scheduler(new))
+    }
+    /// Parses [`Data::disabled_schedulers`].
+    /// Invalid dates are ignored, leaving the scheduler disabled indefinitely.
+    pub fn disabled_schedulers(&self) -> Vec<(String, Option<time::OffsetDateTime>)> {
+        self.disabled_schedulers
+            .iter()
+            .map(|(name, until)| {
+                let until = until
+                    .as_ref()
+                    .and_then(|until| time::OffsetDateTime::parse(until, &Rfc3339).ok());
+                (name.clone(), until)
+            })
+            .collect()
+    }
+    pub fn set_disabled_schedulers(&mut self, new: &HashMap<String, Option<time::OffsetDateTime>>) {
+        self.disabled_schedulers = new
+            .iter()
+            .map(|(name, until)| {
+                (
+                    name.clone(),
+                    until.map(|until| until.format(&Rfc3339).unwrap()),
+                )
+            })
+            .collect();
+    }
+    /// Parses [`Data::sleep_timer`].
+    pub fn sleep_timer(&self) -> Option<SleepTimer> {
+        self.sleep_timer
+            .as_ref()
+            .and_then(datas::SleepTimerData::to_sleep_timer)
+    }
+    /// Parses [`Data::last_runs`]. Invalid dates are ignored.
+    pub fn last_runs(&self) -> HashMap<String, time::OffsetDateTime> {
+        self.last_runs
+            .iter()
+            .filter_map(|(name, last)| {
+                let last = time::OffsetDateTime::parse(last, &Rfc3339).ok()?;
+                Some((name.clone(), last))
+            })
+            .collect()
+    }
+    pub fn set_last_runs(&mut self, new: &HashMap<String, time::OffsetDateTime>) {
+        self.last_runs = new
+            .iter()
+            .map(|(name, last)| (name.clone(), last.format(&Rfc3339).unwrap()))
+            .collect();
+    }
+    pub fn set_transition(
+        &mut self,
+        new: Option<(&Transition, time::OffsetDateTime)>,
+    ) -> Option<datas::TransitionData> {
+        match new {
+            None => {
+                self.current_transition_start = None;
+                self.current_transition.take()
+            }
+            Some((transition, start)) => {
+                self.current_transition_start = Some(start.format(&Rfc3339).unwrap());
+                self.current_transition
+                    .replace(datas::TransitionData::from_transition(transition))
+            }
+        }
+    }
+}
+
+/// The configuration of a device, to copy to another. Get it using [`Data::export`].
+///
+/// Unlike [`Data`], it doesn't contain the current strength, transition or when the
+/// schedulers last ran. The [`VERSION`] is shared with [`Data`].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Export {
+    pub version: u32,
+    /// The timezone offset, as `+hh:mm`.
+    /// Ignored with the `auto-tz` feature, where the timezone is detected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub week_schedulers: BTreeMap<String, WeekSchedulerData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_week_scheduler: Option<String>,
+    #[serde(default)]
+    pub schedulers: Vec<datas::AddSchedulerData>,
+    #[serde(default)]
+    pub scenes: BTreeMap<String, datas::SceneData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub catch_up: Option<datas::CatchUpData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power_on: Option<datas::PowerOnData>,
+}
+impl Export {
+    pub fn from_ron(bytes: &[u8]) -> io::Result<Self> {
+        ron::de::from_bytes(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+    pub fn to_ron(&self) -> io::Result<String> {
+        to_ron(self)
+    }
+    /// Validates the whole configuration and compares it to the current `data`.
+    /// Nothing is changed until [`Import::apply`] is called.
+    ///
+    /// The schedulers are built using `registry`.
+    /// Returns all the problems found if any part is invalid.
+    pub fn prepare(
+        self,
+        data: &Data,
+        mode: ImportMode,
+        registry: &registry::Registry,
+    ) -> Result<Import, ImportError> {
+        if self.version > VERSION {
+            return Err(ImportError(vec![format!(
+                "exported by a newer version ({}, this is {})",
+                self.version, VERSION
+            )]));
+        }
+        let mut errors = Vec::new();
+        let mut report = ImportReport::default();
+
+        #[cfg(not(feature = "auto-tz"))]
+        let timezone =
+            self.timezone.as_deref().map(str::trim).and_then(
+                |timezone| match time::UtcOffset::parse(timezone, &crate::env_timezone::TZ_FORMAT) {
+                    Ok(_) => Some(timezone.to_string()),
+                    Err(_) => {
+                        errors.push(format!(
+                            "invalid timezone {:?}, it needs to have the format '[+-]hh:mm'",
+                            timezone
+                        ));
+                        None
+                    }
+                },
+            );
+        // the timezone is detected
+        #[cfg(feature = "auto-tz")]
+        let timezone = None;
+        report.timezone = timezone.clone();
+
+        let exists = |name: &str| {
+            data.week_schedulers.contains_key(name)
+                || data
+                    .schedulers
+                    .iter()
+                    .any(|scheduler| scheduler.name == name)
+        };
+
+        let mut week_schedulers = Vec::new();
+        for (name, scheduler) in &self.week_schedulers {
+            match scheduler.to_scheduler() {
+                Some(scheduler) => week_schedulers.push((name.clone(), scheduler)),
+                None if scheduler.transition.to_transition().is_none() => errors.push(format!(
+                    "week scheduler {:?} has an invalid transition \
+                    (unknown interpolation or negative time)",
+                    name
+                )),
+                None => errors.push(format!("invalid week scheduler {:?}", name)),
+            }
+            report.week_schedulers.push(name, exists(name));
+        }
+        let mut schedulers: Vec<(datas::AddSchedulerData, Command)> = Vec::new();
+        for mut scheduler in self.schedulers {
+            let name = scheduler.name.clone();
+            if self.week_schedulers.contains_key(&name) {
+                errors.push(format!(
+                    "{:?} is both a week scheduler and a scheduler",
+                    name
+                ));
+                continue;
+            }
+            if schedulers.iter().any(|(other, _)| other.name == name) {
+                errors.push(format!("scheduler {:?} is defined more than once", name));
+                continue;
+            }
+            match scheduler.to_command(true, registry) {
+                Ok(command) => {
+                    report.schedulers.push(&name, exists(&name));
+                    schedulers.push((scheduler, command));
+                }
+                Err(err) => errors.push(format!("scheduler {:?}: {}", name, err)),
+            }
+        }
+        let mut scenes = Vec::new();
+        for (name, scene) in &self.scenes {
+            match scene.to_scene() {
+                Some(scene) => scenes.push((name.clone(), scene)),
+                None => errors.push(format!(
+                    "invalid scene {:?} (invalid effect or negative fade)",
+                    name
+                )),
+            }
+            report.scenes.push(name, data.scenes.contains_key(name));
+        }
+        if let Some(datas::CatchUpData::RunLate { grace }) = self.catch_up {
+            if !grace.is_finite() {
+                errors.push("invalid catch up grace".to_string());
+            }
+        }
+        if let Some(power_on) = &self.power_on {
+            if power_on
+                .sequence
+                .iter()
+                .any(|transition| transition.to_transition().is_none())
+            {
+                errors.push(
+                    "the power on sequence has an invalid transition \
+                    (unknown interpolation or negative time)"
+                        .to_string(),
+                );
+            } else if power_on.validate().is_none() {
+                errors.push("invalid power on configuration".to_string());
+            }
+        }
+        if !errors.is_empty() {
+            return Err(ImportError(errors));
+        }
+
+        let mut remove_schedulers = Vec::new();
+        let mut remove_scenes = Vec::new();
+        let mut default_week_scheduler = self.default_week_scheduler;
+        let mut catch_up = self.catch_up;
+        let mut power_on = self.power_on;
+        if mode == ImportMode::Replace {
+            let imported: BTreeSet<&str> = self
+                .week_schedulers
+                .keys()
+                .map(String::as_str)
+                .chain(
+                    schedulers
+                        .iter()
+                        .map(|(scheduler, _)| scheduler.name.as_str()),
+                )
+                .collect();
+            for name in data.week_schedulers.keys() {
+                if !imported.contains(name.as_str()) {
+                    report.week_schedulers.removed.push(name.clone());
+                    remove_schedulers.push(name.clone());
+                }
+            }
+            for scheduler in &data.schedulers {
+                if !imported.contains(scheduler.name.as_str()) {
+                    report.schedulers.removed.push(scheduler.name.clone());
+                    remove_schedulers.push(scheduler.name.clone());
+                }
+            }
+            for name in data.scenes.keys() {
+                if !self.scenes.contains_key(name) {
+                    report.scenes.removed.push(name.clone());
+                    remove_scenes.push(name.clone());
+                }
+            }
+            default_week_scheduler.get_or_insert_with(default_week_scheduler_name);
+            catch_up.get_or_insert_with(datas::CatchUpData::default);
+            power_on.get_or_insert_with(datas::PowerOnData::default);
+        }
+
+        Ok(Import {
+            mode,
+            timezone,
+            week_schedulers,
+            default_week_scheduler,
+            schedulers,
+            scenes,
+            catch_up: catch_up.map(datas::CatchUpData::to_catch_up),
+            power_on,
+            remove_schedulers,
+            remove_scenes,
+            report,
+        })
+    }
+}
+
+/// How [`Export::prepare`] combines the imported configuration with the current one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Adds the imported week schedulers, schedulers and scenes, replacing those
+    /// with the same names. The others are kept.
+    Merge,
+    /// Also removes the week schedulers, schedulers and scenes not imported,
+    /// and resets the settings not imported to their defaults.
+    Replace,
+}
+impl FromStr for ImportMode {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merge" => Ok(Self::Merge),
+            "replace" => Ok(Self::Replace),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The names an import adds, replaces and removes.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportChanges {
+    pub added: Vec<String>,
+    pub replaced: Vec<String>,
+    pub removed: Vec<String>,
+}
+impl ImportChanges {
+    fn push(&mut self, name: &str, exists: bool) {
+        if exists {
+            self.replaced.push(name.to_string());
+        } else {
+            self.added.push(name.to_string());
+        }
+    }
+}
+/// What an [`Import`] changes.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub week_schedulers: ImportChanges,
+    pub schedulers: ImportChanges,
+    pub scenes: ImportChanges,
+    /// The timezone which is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+/// The problems found by [`Export::prepare`].
+#[derive(Debug)]
+pub struct ImportError(pub Vec<String>);
+impl Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration: {}", self.0.join("; "))
+    }
+}
+impl std::error::Error for ImportError {}
+
+/// A validated [`Export`], ready to be applied.
+#[derive(Debug)]
+pub struct Import {
+    mode: ImportMode,
+    timezone: Option<String>,
+    week_schedulers: Vec<(String, WeekScheduler)>,
+    default_week_scheduler: Option<String>,
+    schedulers: Vec<(datas::AddSchedulerData, Command)>,
+    scenes: Vec<(String, Scene)>,
+    catch_up: Option<CatchUp>,
+    power_on: Option<datas::PowerOnData>,
+    remove_schedulers: Vec<String>,
+    remove_scenes: Vec<String>,
+    report: ImportReport,
+}
+impl Import {
+    pub fn mode(&self) -> ImportMode {
+        self.mode
+    }
+    pub fn report(&self) -> &ImportReport {
+        &self.report
+    }
+    /// The timezone to set. It isn't set by [`Import::apply`], as it's stored separately.
+    pub fn timezone(&self) -> Option<&str> {
+        self.timezone.as_deref()
+    }
+    /// Sends the configuration to the controller and saves it using `saver`.
+    ///
+    /// Returns what was changed.
+    pub fn apply(self, saver: &Saver, controller: &ControllerSender) -> ImportReport {
+        // the schedulers aren't in the `SharedState`, so have to be saved here
+        let remove = &self.remove_schedulers;
+        let schedulers = &self.schedulers;
+        let power_on = self.power_on;
+        saver.modify(|data| {
+            data.schedulers.retain(|scheduler| {
+                !remove.contains(&scheduler.name)
+                    && !schedulers
+                        .iter()
+                        .any(|(imported, _)| imported.name == scheduler.name)
+            });
+            data.schedulers
+                .extend(schedulers.iter().map(|(scheduler, _)| scheduler.clone()));
+            if let Some(power_on) = power_on {
+                data.power_on = power_on;
+            }
+        });
+        for name in self.remove_schedulers {
+            controller.send(Command::RemoveScheduler(name));
+        }
+        for name in self.remove_scenes {
+            controller.send(Command::RemoveScene(name));
+        }
+        for (name, scheduler) in self.week_schedulers {
+            controller.send(Command::AddReplaceScheduler(name, Box::new(scheduler)));
+        }
+        if let Some(name) = self.default_week_scheduler {
+            controller.send(Command::SetDefaultWeekScheduler(name));
+        }
+        for (_, command) in self.schedulers {
+            controller.send(command);
+        }
+        for (name, scene) in self.scenes {
+            controller.send(Command::SetScene(name, scene));
+        }
+        if let Some(catch_up) = self.catch_up {
+            controller.send(Command::SetCatchUp(catch_up));
+        }
+        self.report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn import_rejects_negative_times() {
+        let data = Data::from_week_scheduler(&WeekScheduler::default());
+        let mut export = serde_json::to_value(data.export(None)).unwrap();
+        export["week_schedulers"][DEFAULT_WEEK_SCHEDULER]["transition"]["time"] = (-1.0).into();
+        export["power_on"]["sequence"] =
+            serde_json::to_value(
+                [&export["week_schedulers"][DEFAULT_WEEK_SCHEDULER]["transition"]],
+            )
+            .unwrap();
+        export["scenes"]["evening"] = serde_json::json!({ "strength": 0.5, "fade": -3.0 });
+        let export: Export = serde_json::from_value(export).unwrap();
+
+        let errors = export
+            .prepare(
+                &data,
+                ImportMode::Merge,
+                &crate::extra_schedulers::registry(),
+            )
+            .unwrap_err()
+            .0;
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(errors[0].contains("invalid transition"));
+        assert!(errors[1].contains("scene \"evening\""));
+        assert!(errors[2].contains("power on sequence"));
+    }
+    #[test]
+    fn parse_skips_broken_week_schedulers() {
+        let mut data = Data::from_week_scheduler(&WeekScheduler::default());
+        data.strength = Some(0.4);
+        let mut broken = WeekSchedulerData::from_scheduler(&WeekScheduler::default());
+        broken.mon = Some("25:61:00".to_string());
+        data.week_schedulers
+            .insert("weekend".to_string(), broken.clone());
+        data.week_schedulers.insert(
+            "work".to_string(),
+            WeekSchedulerData::from_scheduler(&WeekScheduler::default()),
+        );
+        data.scenes.insert(
+            "evening".to_string(),
+            datas::SceneData::from_scene(&Scene {
+                strength: Strength::new(0.3),
+                fade: Duration::from_secs(2),
+                effect: None,
+            }),
+        );
+        let bytes = to_ron(&data).unwrap();
+        let fallback = WeekScheduler::same(
+            time::Time::from_hms(8, 0, 0).unwrap(),
+            Transition::default(),
+        );
+
+        let parsed = Data::parse(bytes.as_bytes(), &fallback).unwrap();
+        assert_eq!(
+            parsed.week_schedulers.keys().collect::<Vec<_>>(),
+            [DEFAULT_WEEK_SCHEDULER, "work"]
+        );
+        assert_eq!(parsed.strength, Some(0.4));
+        assert!(parsed.scenes.contains_key("evening"));
+        assert_eq!(
+            parsed.default_week_scheduler().unwrap().mon,
+            WeekScheduler::default().mon
+        );
+
+        // a broken default is replaced
+        data.week_schedulers
+            .insert(DEFAULT_WEEK_SCHEDULER.to_string(), broken);
+        let bytes = to_ron(&data).unwrap();
+        let parsed = Data::parse(bytes.as_bytes(), &fallback).unwrap();
+        assert_eq!(parsed.default_week_scheduler().unwrap().mon, fallback.mon);
+        assert!(parsed.week_schedulers.contains_key("work"));
+
+        assert_eq!(
+            Data::parse(b"(strength: ", &fallback).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    /// A state file from before versioning.
+    const V0: &str = r#"(
+    strength: Some(0.5),
+    schedulers: [
+        (
+            kind: "every-day",
+            name: "morning",
+            description: "",
+            time: Some("07:00:00"),
+            extras: [],
+            transition: Some((from: 0.0, to: 1.0, time: 60.0, interpolation: "linear", extras: [])),
+        ),
+    ],
+    week_scheduler: Some((
+        mon: Some("06:30:00"),
+        tue: None,
+        wed: None,
+        thu: None,
+        fri: None,
+        sat: None,
+        sun: None,
+        transition: (from: 0.0, to: 1.0, time: 900.0, interpolation: "sine", extras: []),
+    )),
+    current_transition: None,
+)"#;
+
+    #[test]
+    fn load_migrates_v0() {
+        let mut store = crate::store::MemoryStore::new();
+        store.put("state.ron", V0.as_bytes()).unwrap();
+        let data = Data::load(&mut store, "state.ron", &WeekScheduler::default())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(data.version, VERSION);
+        assert_eq!(data.strength, Some(0.5));
+        assert_eq!(
+            data.week_schedulers.keys().collect::<Vec<_>>(),
+            [DEFAULT_WEEK_SCHEDULER]
+        );
+        assert_eq!(
+            data.default_week_scheduler().unwrap().mon,
+            time::Time::from_hms(6, 30, 0).ok()
+        );
+        let scheduler = &data.schedulers[0];
+        assert_eq!(scheduler.params, serde_json::json!({ "time": "07:00:00" }));
+        assert!(scheduler.time.is_none() && scheduler.transition.is_none());
+        assert!(matches!(
+            scheduler.action,
+            Some(datas::ActionData::Transition(_))
+        ));
+        assert!(scheduler
+            .clone()
+            .to_command(true, &crate::extra_schedulers::registry())
+            .is_ok());
+        // the file which loaded is backed up as it was
+        assert_eq!(
+            store.get(&backup_key("state.ron")).unwrap().unwrap(),
+            V0.as_bytes()
+        );
+    }
+    #[test]
+    fn load_quarantines_corrupt_file() {
+        let week_scheduler = WeekScheduler::default();
+        let mut backup = Data::from_week_scheduler(&week_scheduler);
+        backup.strength = Some(0.4);
+        let mut store = crate::store::MemoryStore::new();
+        store.put("state.ron", b"(strength: Some(0.").unwrap();
+        backup
+            .write_to_store(&mut store, &backup_key("state.ron"))
+            .unwrap();
+
+        let data = Data::load(&mut store, "state.ron", &week_scheduler)
+            .unwrap()
+            .unwrap();
+        assert_eq!(data.strength, Some(0.4));
+        assert_eq!(store.get("state.ron").unwrap(), None);
+        assert_eq!(
+            store.get(&quarantine_key("state.ron")).unwrap().unwrap(),
+            b"(strength: Some(0."
+        );
+
+        // a newer version isn't overwritten either
+        store
+            .put(
+                "state.ron",
+                b"(version: 1000, strength: None, schedulers: [], current_transition: None)",
+            )
+            .unwrap();
+        let data = Data::load(&mut store, "state.ron", &week_scheduler)
+            .unwrap()
+            .unwrap();
+        assert_eq!(data.strength, Some(0.4));
+        assert!(
+            String::from_utf8(store.get(&quarantine_key("state.ron")).unwrap().unwrap())
+                .unwrap()
+                .contains("version: 1000")
+        );
+
+        // nothing usable
+        store.put(&backup_key("state.ron"), b"").unwrap();
+        store.put("state.ron", b"").unwrap();
+        assert!(Data::load(&mut store, "state.ron", &week_scheduler)
+            .unwrap()
+            .is_none());
+        assert_eq!(store.get(&backup_key("state.ron")).unwrap().unwrap(), b"");
+    }
+
+    /// A [`MemoryStore`](crate::store::MemoryStore) counting the writes.
+    #[derive(Default)]
+    struct Counting {
+        store: crate::store::MemoryStore,
+        writes: Arc<Mutex<usize>>,
+    }
+    impl Store for Counting {
+        fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+            self.store.get(key)
+        }
+        fn put(&mut self, key: &str, value: &[u8]) -> io::Result<()> {
+            *self.writes.lock().unwrap() += 1;
+            self.store.put(key, value)
+        }
+        fn remove(&mut self, key: &str) -> io::Result<()> {
+            self.store.remove(key)
+        }
+    }
+    /// A [`Saver`] writing after 200ms without changes, or at most after 500ms.
+    /// Returns it and the number of writes.
+    fn saver() -> (Saver, Arc<Mutex<usize>>) {
+        let store = Counting::default();
+        let writes = Arc::clone(&store.writes);
+        let week_scheduler = WeekScheduler::default();
+        let saver = Saver::with_delays(
+            Data::from_week_scheduler(&week_scheduler),
+            Arc::new(Mutex::new(SharedState::new(week_scheduler))),
+            Arc::new(Mutex::new(Box::new(store))),
+            "state.ron",
+            Duration::from_millis(200),
+            Duration::from_millis(500),
+        );
+        (saver, writes)
+    }
+
+    #[test]
+    fn saver_coalesces_changes() {
+        let (saver, writes) = saver();
+        for strength in 0..5 {
+            saver.modify(|data| data.strength = Some(strength as f64 / 4.0));
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(*writes.lock().unwrap(), 0);
+        thread::sleep(Duration::from_millis(400));
+        assert_eq!(*writes.lock().unwrap(), 1);
+
+        // nothing left to write
+        saver.flush();
+        assert_eq!(*writes.lock().unwrap(), 1);
+        saver.modify(|data| data.strength = None);
+        saver.flush();
+        assert_eq!(*writes.lock().unwrap(), 2);
+    }
+    #[test]
+    fn saver_writes_at_max_delay() {
+        let (saver, writes) = saver();
+        let start = Instant::now();
+        // changes keep coming, faster than the debounce
+        while start.elapsed() < Duration::from_millis(800) {
+            saver.modify(|data| data.strength = Some(1.0));
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!((1..=2).contains(&*writes.lock().unwrap()));
+    }
+
+    /// An output which does nothing.
+    struct Nothing;
+    impl VariableOut for Nothing {
+        fn set(&mut self, _: Strength) {}
+        fn enable(&mut self) {}
+        fn disable(&mut self) {}
+        fn prepare(&mut self) {}
+        fn capabilities(&self) -> crate::Capabilities {
+            crate::Capabilities::default()
+        }
+    }
+
+    #[test]
+    fn disabled_scheduler_survives_restart() {
+        let registry = crate::extra_schedulers::registry();
+        let store: Arc<Mutex<Box<dyn Store>>> =
+            Arc::new(Mutex::new(Box::new(crate::store::MemoryStore::new())));
+        let week_scheduler = WeekScheduler::default();
+
+        let controller = Controller::new(Nothing, week_scheduler.clone());
+        let saver = Saver::new(
+            Data::from_week_scheduler(&week_scheduler),
+            controller.get_state(),
+            Arc::clone(&store),
+            "state.ron",
+        );
+        saver.subscribe(&controller);
+        let date = (crate::get_now() + time::Duration::days(30)).date();
+        let scheduler = datas::AddSchedulerData {
+            kind: "at".to_string(),
+            name: "trip".to_string(),
+            description: String::new(),
+            params: serde_json::json!({
+                "time": "07:00",
+                "date": date.format(&crate::DATE_FORMAT).unwrap(),
+            }),
+            time: None,
+            extras: Vec::new(),
+            transition: None,
+            action: Some(datas::ActionData::Set { strength: 1.0 }),
+        };
+        let command = scheduler.clone().to_command(false, &registry).unwrap();
+        saver.modify(|data| data.mut_schedulers().push(scheduler));
+        controller.send(command);
+        controller.send(Command::DisableScheduler("trip".to_string(), None));
+        controller.finish();
+        saver.flush();
+
+        let data = Data::load(&mut **store.lock().unwrap(), "state.ron", &week_scheduler)
+            .unwrap()
+            .unwrap();
+        assert!(data.disabled_schedulers.contains_key("trip"));
+
+        let controller = Controller::new(Nothing, data.default_week_scheduler().unwrap());
+        let state = controller.get_state();
+        data.apply(&controller, &registry);
+        controller.finish();
+        let state = state.lock().unwrap();
+        assert!(state.ref_schedulers().contains_key("trip"));
+        assert_eq!(
+            state.ref_disabled_schedulers().get("trip"),
+            Some(&None),
+            "the scheduler was enabled"
+        );
+    }
+    /// An occurrence caught up on isn't run again after a restart.
+    #[test]
+    fn last_runs_survive_restart() {
+        use crate::extra_schedulers::{Common, EveryDay};
+        use crate::scheduler::{SleepTime, State};
+        use crate::{primitive_to_tz, Action, ClonableCommand};
+        use time::macros::{date, time};
+
+        let daily = || {
+            let command = ClonableCommand::new(Command::Set(Strength::new(1.0))).unwrap();
+            Box::new(EveryDay::new(
+                Common::new(String::new(), command),
+                time!(7:00),
+            ))
+        };
+        let today = primitive_to_tz(date!(2030 - 01 - 08).with_time(time!(7:00)));
+        let tomorrow = primitive_to_tz(date!(2030 - 01 - 09).with_time(time!(7:00)));
+        let now = today + time::Duration::minutes(30);
+
+        let mut shared = SharedState::new(WeekScheduler::default());
+        shared.insert_scheduler("daily".to_string(), daily());
+        shared
+            .mut_last_runs()
+            .insert("daily".to_string(), today - time::Duration::days(1));
+        let shared = Arc::new(Mutex::new(shared));
+        let mut state = State::new(Arc::clone(&shared));
+        state.set_now(now);
+        assert_eq!(state.process(None), Action::Wait(SleepTime::To(today)));
+        assert_eq!(state.process(None), Action::Set(Strength::new(1.0)));
+
+        let mut data = Data::from_week_scheduler(&WeekScheduler::default());
+        data.update(&Change::LastRuns, &shared.lock().unwrap());
+        let data =
+            Data::parse(to_ron(&data).unwrap().as_bytes(), &WeekScheduler::default()).unwrap();
+
+        let mut shared = SharedState::new(WeekScheduler::default());
+        shared.insert_scheduler("daily".to_string(), daily());
+        let mut state = State::new(Arc::new(Mutex::new(shared)));
+        state.set_now(now);
+        assert_eq!(
+            state.process(Some(Command::SetLastRuns(data.last_runs()))),
+            Action::Wait(SleepTime::To(tomorrow))
+        );
+    }
+    /// A sleep timer which started to fade continues after a restart.
+    #[test]
+    fn sleep_timer_fade_survives_restart() {
+        use crate::scheduler::{SleepTime, State};
+        use crate::{primitive_to_tz, Action};
+        use time::macros::{date, time};
+
+        let start = primitive_to_tz(date!(2030 - 01 - 08).with_time(time!(22:00)));
+        let minutes = |minutes| start + time::Duration::minutes(minutes);
+
+        let shared = Arc::new(Mutex::new(SharedState::new(WeekScheduler::default())));
+        let mut state = State::new(Arc::clone(&shared));
+        state.set_now(start);
+        state.process(Some(Command::Set(Strength::new(1.0))));
+        state.process(Some(Command::SetSleepTimer(
+            minutes(10),
+            Duration::from_secs(5 * 60),
+        )));
+        state.set_now(minutes(6));
+        assert!(matches!(state.process(None), Action::Set(_)));
+
+        let mut data = Data::from_week_scheduler(&WeekScheduler::default());
+        data.update(&Change::SleepTimer, &shared.lock().unwrap());
+        let data =
+            Data::parse(to_ron(&data).unwrap().as_bytes(), &WeekScheduler::default()).unwrap();
+        let sleep_timer = data.sleep_timer().unwrap();
+        assert_eq!(sleep_timer.end, minutes(10));
+
+        let shared = Arc::new(Mutex::new(SharedState::new(WeekScheduler::default())));
+        let mut state = State::new(Arc::clone(&shared));
+        state.set_now(minutes(7));
+        assert_eq!(
+            state.process(Some(Command::SetSleepTimer(
+                sleep_timer.end,
+                sleep_timer.fade
+            ))),
+            Action::Wait(SleepTime::To(minutes(5)))
+        );
+        assert!(matches!(state.process(None), Action::Set(_)));
+        // the rest of the fade
+        assert_eq!(
+            shared.lock().unwrap().get_transition().unwrap().time,
+            Duration::from_secs(3 * 60)
+        );
+    }
+}
@@ -2,7 +2,7 @@ use std::fmt::Debug;
 
 use crate::{
     get_now, has_occurred, primitive_to_tz, Action, Command, Duration, Effect, Instant,
-    SharedState, Strength, Transition, TransitionInterpolation, Weekday,
+    SharedState, SleepTimer, Strength, Transition, TransitionInterpolation, Weekday,
 };
 use std::convert::TryFrom;
 use std::sync::{Arc, Mutex};
@@ -19,6 +19,8 @@ pub enum Keep {
 }
 pub enum Next {
     At(OffsetDateTime, Command),
+    /// The scheduler is dormant.
+    /// It will be asked again when anything changes.
     Unknown,
 }
 /// Now, represented as a [`time::OffsetDateTime`], being lazily evaluated.
@@ -30,6 +32,11 @@ impl LazyNow {
     pub fn new() -> Self {
         Self { now: None }
     }
+    /// Makes [`LazyNow::now()`] return `now`.
+    /// Used to get the next occurrence of a [`Scheduler`] from another point in time.
+    pub fn at(now: OffsetDateTime) -> Self {
+        Self { now: Some(now) }
+    }
     pub fn now(&mut self) -> OffsetDateTime {
         match self.now {
             Some(now) => now,
@@ -50,6 +57,36 @@ impl Default for LazyNow {
     }
 }
 
+/// An iterator over the occurrences of a [`Scheduler`], in chronological order.
+/// See [`Scheduler::occurrences()`].
+///
+/// Most schedulers repeat forever, so limit the iterator using e.g. [`Iterator::take`].
+pub struct Occurrences<'a> {
+    next: Box<dyn FnMut() -> Option<(OffsetDateTime, Command)> + 'a>,
+}
+impl<'a> Occurrences<'a> {
+    /// Calls `next` for each item, until it returns [`None`].
+    pub fn new(next: impl FnMut() -> Option<(OffsetDateTime, Command)> + 'a) -> Self {
+        Self {
+            next: Box::new(next),
+        }
+    }
+    pub fn empty() -> Self {
+        Self::new(|| None)
+    }
+}
+impl<'a> Iterator for Occurrences<'a> {
+    type Item = (OffsetDateTime, Command);
+    fn next(&mut self) -> Option<Self::Item> {
+        (self.next)()
+    }
+}
+impl<'a> Debug for Occurrences<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Occurrences").finish_non_exhaustive()
+    }
+}
+
 pub trait Scheduler: Debug + Send + Sync {
     /// Advances the internal state when the scheduled time in [`Scheduler::get_next()`] is reached.
     /// You can specify if you want to persist in the list of schedulers or be removed.
@@ -62,6 +99,46 @@ pub trait Scheduler: Debug + Send + Sync {
     /// Which type this scheduler is of.
     /// Should be used as a tip for users.
     fn kind(&self) -> &str;
+    /// The occurrences of this scheduler, starting with the one [`Scheduler::get_next()`]
+    /// returns at `from`.
+    ///
+    /// The default implementation asks [`Scheduler::get_next()`] at the time of the previous
+    /// occurrence, and stops when it doesn't return a later time.
+    /// Occurrences before `from` (e.g. of a one-shot scheduler) are skipped.
+    /// Override this if [`Scheduler::get_next()`] depends on the internal state.
+    fn occurrences(&self, from: OffsetDateTime) -> Occurrences<'_> {
+        let mut now = from;
+        let mut last = None;
+        let mut finished = false;
+        Occurrences::new(move || {
+            if finished {
+                return None;
+            }
+            match self.get_next(&mut LazyNow::at(now)) {
+                Next::At(date_time, command)
+                    if date_time >= from && !matches!(last, Some(last) if date_time <= last) =>
+                {
+                    last = Some(date_time);
+                    now = now.max(date_time);
+                    Some((date_time, command))
+                }
+                _ => {
+                    finished = true;
+                    None
+                }
+            }
+        })
+    }
+
+    /// Returns `Some` if this is a [`WeekScheduler`].
+    /// Used to change the days of named week schedulers.
+    fn as_week_scheduler(&self) -> Option<&WeekScheduler> {
+        None
+    }
+    /// Returns `Some` if this is a [`WeekScheduler`].
+    fn as_week_scheduler_mut(&mut self) -> Option<&mut WeekScheduler> {
+        None
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -74,7 +151,6 @@ pub struct WeekScheduler {
     pub sat: Option<Time>,
     pub sun: Option<Time>,
     pub transition: Transition,
-    last: Option<OffsetDateTime>,
 }
 impl WeekScheduler {
     pub fn empty(transition: Transition) -> Self {
@@ -90,7 +166,6 @@ impl WeekScheduler {
             sat: time,
             sun: time,
             transition,
-            last: None,
         }
     }
 
@@ -109,6 +184,20 @@ impl WeekScheduler {
         }
         None
     }
+    /// Gets the time of the first day after `date` which has a time set.
+    fn get_next_after_date(&self, date: time::Date) -> Option<time::PrimitiveDateTime> {
+        let (time, day) = self.get_next_from_day(Weekday::from(date.weekday()).succ())?;
+        // Since we get the next day from function
+        let day = day + 1;
+        Some(date.with_time(*time) + time::Duration::days(day as i64))
+    }
+    /// The start of the transition which should finish at `finish`.
+    fn start_of(&self, finish: time::PrimitiveDateTime) -> OffsetDateTime {
+        // if your transition time is larger than what std can handle, you have other problems
+        primitive_to_tz(
+            finish - time::Duration::try_from(self.transition.time).unwrap_or(time::Duration::MAX),
+        )
+    }
     pub fn get(&self, day: Weekday) -> &Option<time::Time> {
         match day {
             Weekday::Mon => &self.mon,
@@ -134,43 +223,46 @@ impl WeekScheduler {
 }
 impl Scheduler for WeekScheduler {
     fn advance(&mut self) -> Keep {
-        self.last = Some(get_now());
         Keep::Keep
     }
     fn get_next(&self, now: &mut LazyNow) -> Next {
         let now = now.now();
-        let next_today = match self.get(now.weekday().into()) {
-            Some(t) => *t,
-            None => return Next::Unknown,
-        };
 
-        // Check if last was not today, then abort.
-        let next = if now.time() < next_today
-            && self
-                .last
-                .map(|l| l.date() < get_now().date())
-                .unwrap_or(true)
-        {
-            now.date().with_time(next_today)
-        } else {
-            // Unwrap is ok; we checked the same function above and return if it was `None`.
-            // If it returns `Some` for Weekday x, it will also return `Some` for Weekday x.succ(); it's a cycle.
-            let (time, day) = self
-                .get_next_from_day(Weekday::from(now.weekday()).succ())
-                .unwrap();
-            // Since we get the next day from function
-            let day = day + 1;
-
-            now.date().with_time(*time) + time::Duration::days(day as i64)
+        // If we're in the middle of today's transition, the start (in the past) is returned.
+        // The controller knows if it has already run, see `SharedState::get_next`.
+        let next = match self.get(now.weekday().into()) {
+            Some(next_today) if now.time() < *next_today => now.date().with_time(*next_today),
+            _ => match self.get_next_after_date(now.date()) {
+                Some(next) => next,
+                None => return Next::Unknown,
+            },
         };
-        // if your transition time is larger than what std can handle, you have other problems
-        let next =
-            next - time::Duration::try_from(self.transition.time).unwrap_or(time::Duration::MAX);
         Next::At(
-            primitive_to_tz(next),
+            self.start_of(next),
             Command::SetTransition(Transition::clone(&self.transition)),
         )
     }
+    /// Starts with the transition running at `from`, if any.
+    fn occurrences(&self, from: OffsetDateTime) -> Occurrences<'_> {
+        // The date of the previous finish.
+        let mut date = None;
+        Occurrences::new(move || {
+            let start = match date {
+                None => match self.get_next(&mut LazyNow::at(from)) {
+                    Next::At(start, _) => start,
+                    Next::Unknown => return None,
+                },
+                Some(previous) => self.start_of(self.get_next_after_date(previous)?),
+            };
+            let finish = start
+                + time::Duration::try_from(self.transition.time).unwrap_or(time::Duration::MAX);
+            date = Some(finish.date());
+            Some((
+                start,
+                Command::SetTransition(Transition::clone(&self.transition)),
+            ))
+        })
+    }
 
     fn description(&self) -> &str {
         "Can schedule once per weekday, repeating every week."
@@ -179,6 +271,13 @@ impl Scheduler for WeekScheduler {
     fn kind(&self) -> &str {
         "Weekly cycle"
     }
+
+    fn as_week_scheduler(&self) -> Option<&WeekScheduler> {
+        Some(self)
+    }
+    fn as_week_scheduler_mut(&mut self) -> Option<&mut WeekScheduler> {
+        Some(self)
+    }
 }
 impl Default for WeekScheduler {
     fn default() -> Self {
@@ -232,10 +331,16 @@ pub struct TransitionState {
 }
 impl TransitionState {
     pub fn new(transition: Transition) -> Self {
-        Self {
+        Self::new_at(transition, Duration::ZERO)
+    }
+    /// Creates a state which has already run for `elapsed`.
+    pub fn new_at(transition: Transition, elapsed: Duration) -> Self {
+        let mut me = Self {
             transition,
             progress: 0.0,
-        }
+        };
+        me.progress = me.calculate_delta_progress(&elapsed);
+        me
     }
 
     pub fn process(&mut self, delta_time: &Duration) -> TransitionStateOut {
@@ -282,7 +387,7 @@ impl TransitionState {
         delta_progress: f64,
     ) -> TransitionStateOut {
         self.progress += delta_progress;
-        self.remap_and_check_finish(strength(self.progress), 1.0)
+        self.remap_and_check_finish(strength(self.progress.min(1.0)), 1.0)
     }
     fn and_back_interpolation<F: Fn(f64) -> f64>(
         &mut self,
@@ -316,7 +421,14 @@ pub struct State {
     transition: Option<TransitionState>,
     last_instance: Instant,
     last_scheduler: Option<String>,
+    /// If [`State::wake_up`] is the fade of the [`SleepTimer`].
+    sleep_timer_queued: bool,
+    /// If [`State::transition`] is the fade of the [`SleepTimer`].
+    /// The sleep timer is kept in the [`SharedState`] until the fade is done.
+    sleep_timer_fading: bool,
     effect: Option<Effect>,
+    /// A fixed time to use instead of the clock, for tests.
+    now: Option<OffsetDateTime>,
 }
 impl State {
     pub fn new(state: Arc<Mutex<SharedState>>) -> Self {
@@ -328,6 +440,23 @@ impl State {
             effect: None,
             last_instance: Instant::now(),
             last_scheduler: None,
+            sleep_timer_queued: false,
+            sleep_timer_fading: false,
+            now: None,
+        }
+    }
+    /// Makes the state act as if it's `now`.
+    #[cfg(test)]
+    pub(crate) fn set_now(&mut self, now: OffsetDateTime) {
+        self.now = Some(now);
+    }
+    fn get_now(&self) -> OffsetDateTime {
+        self.now.unwrap_or_else(get_now)
+    }
+    fn has_occurred(&self, date_time: OffsetDateTime) -> bool {
+        match self.now {
+            Some(now) => date_time < now,
+            None => has_occurred(date_time),
         }
     }
 
@@ -347,83 +476,298 @@ impl State {
             Some(Command::Set(strength)) => {
                 // clear animation
                 self.transition = None;
-                self.effect = None;
-                self.shared.lock().unwrap().set_strength(strength);
+                self.set_effect(None);
+                {
+                    let mut lock = self.shared.lock().unwrap();
+                    lock.set_strength(strength);
+                    lock.set_active_scene(None);
+                    // the fade of the sleep timer was overridden
+                    if self.sleep_timer_fading {
+                        self.sleep_timer_fading = false;
+                        lock.set_sleep_timer(None);
+                    }
+                }
                 // send back set
                 Action::Set(strength)
             }
+            Some(Command::Toggle) => {
+                let current = self.get_current_strength();
+                let strength = if !current.is_off() {
+                    // remember where a transition or effect was
+                    self.shared.lock().unwrap().set_last_on_strength(current);
+                    Strength::new(0.0)
+                } else {
+                    *self.shared.lock().unwrap().get_last_on_strength()
+                };
+                self.process(Some(Command::Set(strength)))
+            }
+            Some(Command::Adjust(delta)) => {
+                let current = self.get_current_strength().into_inner();
+                self.process(Some(Command::Set(Strength::new_clamped(current + delta))))
+            }
+            Some(Command::Scale(factor)) => {
+                let current = self.get_current_strength().into_inner();
+                self.process(Some(Command::Set(Strength::new_clamped(current * factor))))
+            }
+            Some(Command::SetLastOnStrength(strength)) => {
+                self.shared.lock().unwrap().set_last_on_strength(strength);
+                self.get_next()
+            }
             Some(Command::ChangeDayTimer(day, time)) => {
+                let name = self
+                    .shared
+                    .lock()
+                    .unwrap()
+                    .get_default_week_scheduler()
+                    .to_string();
+                self.process(Some(Command::ChangeWeekDayTimer(name, day, time)))
+            }
+            Some(Command::ChangeDayTimerTransition(new_transition)) => {
+                let name = self
+                    .shared
+                    .lock()
+                    .unwrap()
+                    .get_default_week_scheduler()
+                    .to_string();
+                self.process(Some(Command::ChangeWeekTransition(name, new_transition)))
+            }
+            Some(Command::ChangeWeekDayTimer(name, day, time)) => {
                 // change time of day
                 {
                     let mut lock = self.shared.lock().unwrap();
-                    *lock.mut_week_scheduler().get_mut(day) = time;
-                    lock.mut_week_scheduler().last = None;
+                    let scheduler = lock.mut_or_insert_week_scheduler(&name);
+                    *scheduler.get_mut(day) = time;
                 }
                 self.get_next()
             }
-            Some(Command::ChangeDayTimerTransition(new_transition)) => {
+            Some(Command::ChangeWeekTransition(name, new_transition)) => {
                 {
-                    self.shared.lock().unwrap().mut_week_scheduler().transition = new_transition;
+                    self.shared
+                        .lock()
+                        .unwrap()
+                        .mut_or_insert_week_scheduler(&name)
+                        .transition = new_transition;
                 }
                 self.get_next()
             }
+            Some(Command::SetDefaultWeekScheduler(name)) => {
+                self.shared.lock().unwrap().set_default_week_scheduler(name);
+                self.get_next()
+            }
             Some(Command::AddReplaceScheduler(name, scheduler)) => {
                 self.shared
                     .lock()
                     .unwrap()
-                    .mut_schedulers()
-                    .insert(name, scheduler);
+                    .insert_scheduler(name, scheduler);
                 self.get_next()
             }
             Some(Command::RemoveScheduler(name)) => {
-                self.shared.lock().unwrap().mut_schedulers().remove(&name);
+                self.shared.lock().unwrap().remove_scheduler(&name);
+                self.get_next()
+            }
+            Some(Command::EnableScheduler(name)) => {
+                {
+                    let mut lock = self.shared.lock().unwrap();
+                    // don't catch up on the occurrences while it was disabled
+                    if lock.mut_disabled_schedulers().remove(&name).is_some() {
+                        lock.mut_last_runs().insert(name, self.get_now());
+                    }
+                }
+                self.get_next()
+            }
+            Some(Command::DisableScheduler(name, until)) => {
+                {
+                    let mut lock = self.shared.lock().unwrap();
+                    if lock.ref_schedulers().contains_key(&name) {
+                        lock.mut_disabled_schedulers().insert(name, until);
+                    }
+                }
                 self.get_next()
             }
             Some(Command::ClearAllSchedulers) => {
-                self.shared.lock().unwrap().mut_schedulers().clear();
+                // week schedulers are kept; they have to be removed by name
+                self.shared
+                    .lock()
+                    .unwrap()
+                    .retain_schedulers(|_, scheduler| scheduler.as_week_scheduler().is_some());
                 self.get_next()
             }
-            Some(Command::SetTransition(transition)) => {
+            Some(Command::SetLastRuns(last_runs)) => {
                 self.shared
                     .lock()
                     .unwrap()
-                    .set_transition(Some(Transition::clone(&transition)));
-                self.transition = Some(TransitionState::new(transition));
+                    .mut_last_runs()
+                    .extend(last_runs);
+                self.get_next()
+            }
+            Some(Command::SetSleepTimer(end, fade)) => {
+                self.stop_sleep_fade();
+                self.shared
+                    .lock()
+                    .unwrap()
+                    .set_sleep_timer(Some(SleepTimer { end, fade }));
+                self.get_next()
+            }
+            Some(Command::ExtendSleepTimer(duration)) => {
+                {
+                    let mut lock = self.shared.lock().unwrap();
+                    if let Some(mut sleep_timer) = lock.get_sleep_timer().copied() {
+                        sleep_timer.end += duration;
+                        lock.set_sleep_timer(Some(sleep_timer));
+                    }
+                }
+                // it's started again if the fade should still run
+                self.stop_sleep_fade();
+                self.get_next()
+            }
+            Some(Command::CancelSleepTimer) => {
+                self.shared.lock().unwrap().set_sleep_timer(None);
+                match self.stop_sleep_fade() {
+                    Some(strength) => Action::Set(strength),
+                    None => self.get_next(),
+                }
+            }
+            Some(Command::SetCatchUp(catch_up)) => {
+                self.shared.lock().unwrap().set_catch_up(catch_up);
+                self.get_next()
+            }
+            Some(Command::SetTransition(transition)) => {
+                let now = self.get_now();
+                self.process(Some(Command::ResumeTransition(transition, now)))
+            }
+            Some(Command::ResumeTransition(transition, start)) => {
+                {
+                    let mut lock = self.shared.lock().unwrap();
+                    lock.set_transition(Some((Transition::clone(&transition), start)));
+                    lock.set_active_scene(None);
+                    // the fade of the sleep timer was overridden
+                    if self.sleep_timer_fading {
+                        self.sleep_timer_fading = false;
+                        lock.set_sleep_timer(None);
+                    }
+                }
+                let elapsed = (self.get_now() - start)
+                    .max(time::Duration::ZERO)
+                    .unsigned_abs();
+                self.transition = Some(TransitionState::new_at(transition, elapsed));
                 self.last_instance = Instant::now();
                 // unwrap() is ok; we've just set transition to be `Some`
                 Action::Set(self.get_transition_output().unwrap())
             }
+            Some(Command::FadeTo(to, time, interpolation)) => {
+                let from = self.get_current_strength();
+                self.set_effect(None);
+                self.process(Some(Command::SetTransition(Transition {
+                    from,
+                    to,
+                    time,
+                    interpolation,
+                })))
+            }
+            Some(Command::SetScene(name, scene)) => {
+                self.shared.lock().unwrap().mut_scenes().insert(name, scene);
+                self.get_next()
+            }
+            Some(Command::RemoveScene(name)) => {
+                {
+                    let mut lock = self.shared.lock().unwrap();
+                    lock.mut_scenes().remove(&name);
+                    if lock.get_active_scene() == Some(name.as_str()) {
+                        lock.set_active_scene(None);
+                    }
+                }
+                self.get_next()
+            }
+            Some(Command::ActivateScene(name)) => {
+                let scene = self.shared.lock().unwrap().ref_scenes().get(&name).cloned();
+                let scene = match scene {
+                    Some(scene) => scene,
+                    None => return self.get_next(),
+                };
+                let action = if scene.fade.is_zero() {
+                    self.process(Some(Command::Set(scene.strength)))
+                } else {
+                    self.process(Some(Command::FadeTo(
+                        scene.strength,
+                        scene.fade,
+                        TransitionInterpolation::Sine,
+                    )))
+                };
+                self.shared.lock().unwrap().set_active_scene(Some(name));
+                match scene.effect {
+                    Some(effect) => {
+                        self.set_effect(Some(effect));
+                        // unwrap() is ok; we've just set effect to be `Some`
+                        Action::Set(self.get_transition_output().unwrap())
+                    }
+                    None => action,
+                }
+            }
             Some(Command::SetEffect(e)) => {
-                self.effect = Some(e);
+                self.set_effect(Some(e));
+                self.shared.lock().unwrap().set_active_scene(None);
                 Action::Set(self.get_transition_output().unwrap())
             }
+            Some(Command::ClearEffect) => {
+                self.set_effect(None);
+                self.shared.lock().unwrap().set_active_scene(None);
+                match self.get_transition_output() {
+                    Some(s) => Action::Set(s),
+                    None => Action::Set(*self.shared.lock().unwrap().get_strength()),
+                }
+            }
+            Some(Command::SetFrequency(frequency)) => Action::SetFrequency(frequency),
+            Some(Command::SetResolution(bits)) => Action::SetResolution(bits),
             None | Some(Command::UpdateWake) => {
                 // check wake up Option<>
                 match self.wake() {
-                    Some(command) => {
+                    Some((date_time, mut command)) => {
+                        let mut sleep_fade = false;
                         {
                             let mut lock = self.shared.lock().unwrap();
-                            match self.last_scheduler.as_ref() {
-                                Some(name) => match lock.mut_schedulers().get_mut(name) {
-                                    Some(scheduler) => match scheduler.advance() {
-                                        Keep::Keep => {}
-                                        Keep::Remove => {
-                                            lock.mut_schedulers().remove(name);
+                            if let Some(name) = self.last_scheduler.as_ref() {
+                                if !lock.advance_scheduler(name, date_time) {
+                                    panic!("attempting to get scheduler not existing. Did you clear the list?");
+                                }
+                            }
+                            self.last_scheduler = None;
+                            if self.sleep_timer_queued {
+                                self.sleep_timer_queued = false;
+                                // the timer might have been extended since it was queued
+                                match lock.get_sleep_timer().copied() {
+                                    Some(sleep_timer)
+                                        if self.has_occurred(sleep_timer.fade_start()) =>
+                                    {
+                                        command = sleep_timer.command(self.get_now());
+                                        match command {
+                                            Command::FadeTo(..) => sleep_fade = true,
+                                            _ => lock.set_sleep_timer(None),
                                         }
-                                    },
-                                    None => {
-                                        panic!("attempting to get scheduler not existing. Did you clear the list?");
                                     }
-                                },
-                                None => {
-                                    // Discarding, because we know it'll want to continue.
-                                    lock.mut_week_scheduler().advance();
+                                    _ => {
+                                        drop(lock);
+                                        return self.get_next();
+                                    }
                                 }
                             }
-                            self.last_scheduler = None;
                         }
 
-                        self.process(Some(command))
+                        let command = match command {
+                            // start from when it's scheduled, in case we're late
+                            Command::SetTransition(transition) => {
+                                Command::ResumeTransition(transition, date_time)
+                            }
+                            command => command,
+                        };
+                        let action = self.process(Some(command));
+                        if sleep_fade {
+                            if self.transition.is_some() {
+                                self.sleep_timer_fading = true;
+                            } else {
+                                self.shared.lock().unwrap().set_sleep_timer(None);
+                            }
+                        }
+                        action
                     }
                     // check internal transition state; get_output()
                     None => self.get_next(),
@@ -446,10 +790,14 @@ impl State {
             let transition = self.transition.as_mut().unwrap();
             return match transition.process(&delta_time) {
                 TransitionStateOut::Finished(s) => {
-                    self.shared
-                        .lock()
-                        .unwrap()
-                        .set_strength(Strength::clone(&s));
+                    {
+                        let mut lock = self.shared.lock().unwrap();
+                        lock.set_strength(Strength::clone(&s));
+                        if self.sleep_timer_fading {
+                            self.sleep_timer_fading = false;
+                            lock.set_sleep_timer(None);
+                        }
+                    }
                     self.transition = None;
                     Some(s)
                 }
@@ -457,7 +805,7 @@ impl State {
             };
         }
         if let Some(effect) = &self.effect {
-            let now = (get_now() - OffsetDateTime::UNIX_EPOCH).as_seconds_f64();
+            let now = (self.get_now() - OffsetDateTime::UNIX_EPOCH).as_seconds_f64();
             let s = match effect {
                 Effect::Radar { offset, speed } => {
                     // - offset because we are taking 1-↓, offset should always make a remote
@@ -470,49 +818,90 @@ impl State {
         }
         None
     }
+    /// Stops the fade of the [`SleepTimer`] where it is, if it's running.
+    /// Returns the strength it stopped at.
+    fn stop_sleep_fade(&mut self) -> Option<Strength> {
+        if !self.sleep_timer_fading {
+            return None;
+        }
+        self.sleep_timer_fading = false;
+        let strength = self.get_current_strength();
+        self.transition = None;
+        self.shared.lock().unwrap().set_strength(strength);
+        Some(strength)
+    }
+    fn set_effect(&mut self, effect: Option<Effect>) {
+        self.shared.lock().unwrap().set_effect(effect.clone());
+        self.effect = effect;
+    }
+    /// The strength currently being output.
+    fn get_current_strength(&mut self) -> Strength {
+        match self.get_transition_output() {
+            Some(s) => s,
+            None => *self.shared.lock().unwrap().get_strength(),
+        }
+    }
     fn queue_sleep(&mut self) -> SleepTime {
         self.last_scheduler = None;
-        let (date_time, cmd, name) = {
-            let lock = self.shared.lock().unwrap();
-
-            let mut now = LazyNow::new();
-
-            let schedulers_next = lock
-                .ref_schedulers()
-                .iter()
-                .map(|(name, s)| (name, s.get_next(&mut now)))
-                .min_by_key(|(_, next)| match next {
-                    Next::At(d, _) => *d,
-                    Next::Unknown => unreachable!(".retain() call above"),
-                });
-
-            let week_next = Scheduler::get_next(lock.ref_week_schedule(), &mut now);
-            match week_next {
-                Next::At(week_dur, week_cmd) => match schedulers_next {
-                    Some((name, schedulers_next)) => match schedulers_next {
-                        Next::At(schedulers_dur, schedulers_cmd) => match schedulers_dur < week_dur
-                        {
-                            true => (schedulers_dur, schedulers_cmd, Some(name.to_string())),
-                            false => (week_dur, week_cmd, None),
-                        },
-                        Next::Unknown => unreachable!(".retain() call above"),
-                    },
-                    None => (week_dur, week_cmd, None),
-                },
-                Next::Unknown => match schedulers_next {
-                    Some((name, next)) => match next {
-                        Next::At(dur, cmd) => (dur, cmd, Some(name.to_string())),
-                        Next::Unknown => unreachable!(".retain() call above"),
-                    },
-                    None => return SleepTime::Forever,
-                },
+        self.sleep_timer_queued = false;
+        let (next, sleep_timer) = {
+            let mut lock = self.shared.lock().unwrap();
+
+            // enable the schedulers which were disabled until now
+            let expired = |until: &Option<OffsetDateTime>| matches!(until, Some(until) if self.has_occurred(*until));
+            if lock.ref_disabled_schedulers().values().any(expired) {
+                lock.mut_disabled_schedulers()
+                    .retain(|_, until| !expired(until));
             }
+
+            let mut now = self.now.map_or_else(LazyNow::new, LazyNow::at);
+
+            let next = loop {
+                let next = lock
+                    .ref_schedulers()
+                    .keys()
+                    .filter_map(|name| match lock.get_next(name, &mut now) {
+                        Next::At(date_time, cmd) => Some((date_time, cmd, name.to_string())),
+                        Next::Unknown => None,
+                    })
+                    .min_by_key(|(date_time, _, _)| *date_time);
+
+                let (date_time, cmd, name) = match next {
+                    Some(next) => next,
+                    None => break None,
+                };
+                let late = (now.now() - date_time).max(time::Duration::ZERO);
+                if lock.get_catch_up().should_run(late.unsigned_abs()) {
+                    break Some((date_time, cmd, name));
+                }
+                println!(
+                    "Skipping occurrence of {:?} at {}, missed by {}",
+                    name,
+                    date_time.format(&crate::DATE_TIME_TZ_FORMAT).unwrap(),
+                    late
+                );
+                lock.advance_scheduler(&name, date_time);
+            };
+            (next, lock.get_sleep_timer().copied())
         };
 
-        if let Some(name) = name {
-            self.last_scheduler = Some(name);
+        if let Some(sleep_timer) = sleep_timer {
+            let start = sleep_timer.fade_start();
+            if !matches!(next, Some((date_time, _, _)) if date_time < start) {
+                self.sleep_timer_queued = true;
+                // the fade is built when it starts, from the sleep timer at that time
+                self.wake_up = Some((start, Command::UpdateWake));
+                return SleepTime::To(start);
+            }
         }
 
+        let (date_time, cmd, name) = match next {
+            Some(next) => next,
+            None => return SleepTime::Forever,
+        };
+
+        self.last_scheduler = Some(name);
+
         self.wake_up = Some((date_time, cmd));
         SleepTime::To(date_time)
     }
@@ -525,10 +914,360 @@ impl State {
             },
         }
     }
-    fn wake(&mut self) -> Option<Command> {
-        match has_occurred(self.wake_up.as_ref()?.0) {
+    fn wake(&mut self) -> Option<(OffsetDateTime, Command)> {
+        match self.has_occurred(self.wake_up.as_ref()?.0) {
             false => None,
-            true => Some(self.wake_up.take().unwrap().1),
+            true => self.wake_up.take(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CatchUp;
+    use time::macros::{date, time};
+
+    fn local(date: time::Date, time: Time) -> OffsetDateTime {
+        primitive_to_tz(date.with_time(time))
+    }
+    fn mon_fri() -> WeekScheduler {
+        let mut scheduler = WeekScheduler::empty(Transition::default());
+        scheduler.mon = Some(time!(7:00));
+        scheduler.fri = Some(time!(7:00));
+        scheduler
+    }
+
+    /// The next 3 occurrences of every scheduler, merged.
+    fn timeline(state: &SharedState, from: OffsetDateTime) -> Vec<(OffsetDateTime, &str)> {
+        let mut timeline: Vec<_> = state
+            .ref_schedulers()
+            .keys()
+            .flat_map(|name| {
+                state
+                    .occurrences(name, from)
+                    .take(3)
+                    .map(move |(date_time, _)| (date_time, name.as_str()))
+            })
+            .collect();
+        timeline.sort();
+        timeline
+    }
+
+    /// Runs once, at the time.
+    #[derive(Debug)]
+    struct Once(OffsetDateTime);
+    impl Scheduler for Once {
+        fn advance(&mut self) -> Keep {
+            Keep::Remove
+        }
+        fn get_next(&self, _: &mut LazyNow) -> Next {
+            Next::At(self.0, Command::Toggle)
+        }
+        fn description(&self) -> &str {
+            ""
+        }
+        fn kind(&self) -> &str {
+            "Once"
+        }
+    }
+
+    /// Sets full strength every day at the time.
+    #[derive(Debug)]
+    struct Daily(Time);
+    impl Scheduler for Daily {
+        fn advance(&mut self) -> Keep {
+            Keep::Keep
+        }
+        fn get_next(&self, now: &mut LazyNow) -> Next {
+            let now = now.now();
+            let date = if now.time() < self.0 {
+                now.date()
+            } else {
+                now.date() + time::Duration::days(1)
+            };
+            Next::At(
+                primitive_to_tz(date.with_time(self.0)),
+                Command::Set(Strength::new(1.0)),
+            )
+        }
+        fn description(&self) -> &str {
+            ""
+        }
+        fn kind(&self) -> &str {
+            "Daily"
+        }
+    }
+
+    /// A [`State`] at `now` with a [`Daily`] scheduler at 07:00 which last ran at `last_run`.
+    fn daily(
+        now: OffsetDateTime,
+        last_run: OffsetDateTime,
+        catch_up: CatchUp,
+    ) -> (State, Arc<Mutex<SharedState>>) {
+        let mut shared = SharedState::new(WeekScheduler::default());
+        shared.insert_scheduler("daily".to_string(), Box::new(Daily(time!(7:00))));
+        shared.mut_last_runs().insert("daily".to_string(), last_run);
+        shared.set_catch_up(catch_up);
+        let shared = Arc::new(Mutex::new(shared));
+        let mut state = State::new(Arc::clone(&shared));
+        state.set_now(now);
+        (state, shared)
+    }
+    fn last_run(shared: &Mutex<SharedState>) -> Option<OffsetDateTime> {
+        shared.lock().unwrap().ref_last_runs().get("daily").copied()
+    }
+    fn wait_to(date_time: OffsetDateTime) -> Action {
+        Action::Wait(SleepTime::To(date_time))
+    }
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    #[test]
+    fn week_scheduler_timeline() {
+        // 2030-01-07 is a Monday; the transitions take 15 minutes
+        let scheduler = mon_fri();
+        let starts: Vec<_> = scheduler
+            .occurrences(local(date!(2030 - 01 - 07), time!(0:00)))
+            .take(4)
+            .map(|(date_time, command)| {
+                assert!(matches!(command, Command::SetTransition(_)));
+                date_time
+            })
+            .collect();
+        assert_eq!(
+            starts,
+            [
+                local(date!(2030 - 01 - 07), time!(6:45)),
+                local(date!(2030 - 01 - 11), time!(6:45)),
+                local(date!(2030 - 01 - 14), time!(6:45)),
+                local(date!(2030 - 01 - 18), time!(6:45)),
+            ]
+        );
+        // the transition running at `from`
+        let next = |from| scheduler.occurrences(from).next().unwrap().0;
+        assert_eq!(
+            next(local(date!(2030 - 01 - 07), time!(6:50))),
+            local(date!(2030 - 01 - 07), time!(6:45))
+        );
+        assert_eq!(
+            next(local(date!(2030 - 01 - 07), time!(7:10))),
+            local(date!(2030 - 01 - 11), time!(6:45))
+        );
+        assert!(WeekScheduler::default()
+            .occurrences(local(date!(2030 - 01 - 07), time!(0:00)))
+            .next()
+            .is_none());
+    }
+    #[test]
+    fn occurrences_start_at_from() {
+        let at = local(date!(2030 - 01 - 10), time!(7:00));
+        let once = Once(at);
+        let occurrences = |from| {
+            once.occurrences(from)
+                .map(|(date_time, _)| date_time)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(occurrences(local(date!(2030 - 01 - 09), time!(0:00))), [at]);
+        assert_eq!(occurrences(at), [at]);
+        assert!(occurrences(local(date!(2030 - 01 - 11), time!(0:00))).is_empty());
+    }
+    #[test]
+    fn shared_state_timeline() {
+        let mut state = SharedState::new(mon_fri());
+        let at = local(date!(2030 - 01 - 08), time!(12:00));
+        state.insert_scheduler("once".to_string(), Box::new(Once(at)));
+        let week = crate::DEFAULT_WEEK_SCHEDULER;
+
+        assert_eq!(
+            timeline(&state, local(date!(2030 - 01 - 07), time!(12:00))),
+            [
+                (at, "once"),
+                (local(date!(2030 - 01 - 11), time!(6:45)), week),
+                (local(date!(2030 - 01 - 14), time!(6:45)), week),
+                (local(date!(2030 - 01 - 18), time!(6:45)), week),
+            ]
+        );
+        // a window after the one-shot
+        assert_eq!(
+            timeline(&state, local(date!(2030 - 01 - 09), time!(0:00)))[0],
+            (local(date!(2030 - 01 - 11), time!(6:45)), week)
+        );
+
+        state
+            .mut_disabled_schedulers()
+            .insert(week.to_string(), None);
+        assert_eq!(
+            timeline(&state, local(date!(2030 - 01 - 07), time!(12:00))),
+            [(at, "once")]
+        );
+    }
+    #[test]
+    fn missed_one_shot_is_next() {
+        let mut state = SharedState::new(WeekScheduler::default());
+        let now = local(date!(2030 - 01 - 08), time!(12:00));
+        let at = now - time::Duration::minutes(5);
+        state.insert_scheduler("once".to_string(), Box::new(Once(at)));
+        assert!(matches!(
+            state.get_next("once", &mut LazyNow::at(now)),
+            Next::At(date_time, Command::Toggle) if date_time == at
+        ));
+        // it ran
+        state.mut_last_runs().insert("once".to_string(), at);
+        assert!(matches!(
+            state.get_next("once", &mut LazyNow::at(now)),
+            Next::Unknown
+        ));
+    }
+    #[test]
+    fn runs_missed_occurrence_late() {
+        let today = local(date!(2030 - 01 - 08), time!(7:00));
+        let tomorrow = local(date!(2030 - 01 - 09), time!(7:00));
+        let (mut state, shared) = daily(
+            local(date!(2030 - 01 - 08), time!(7:30)),
+            local(date!(2030 - 01 - 07), time!(7:00)),
+            CatchUp::RunLate(HOUR),
+        );
+        assert_eq!(state.process(None), wait_to(today));
+        assert_eq!(state.process(None), Action::Set(Strength::new(1.0)));
+        assert_eq!(last_run(&shared), Some(today));
+        assert_eq!(state.process(None), wait_to(tomorrow));
+    }
+    #[test]
+    fn skips_occurrence_missed_by_too_much() {
+        let today = local(date!(2030 - 01 - 08), time!(7:00));
+        let tomorrow = local(date!(2030 - 01 - 09), time!(7:00));
+        let (mut state, shared) = daily(
+            local(date!(2030 - 01 - 08), time!(9:00)),
+            local(date!(2030 - 01 - 07), time!(7:00)),
+            CatchUp::RunLate(HOUR),
+        );
+        assert_eq!(state.process(None), wait_to(tomorrow));
+        // it's recorded as run, so it isn't considered missed again
+        assert_eq!(last_run(&shared), Some(today));
+
+        let (mut state, shared) = daily(
+            local(date!(2030 - 01 - 08), time!(7:30)),
+            local(date!(2030 - 01 - 07), time!(7:00)),
+            CatchUp::Skip,
+        );
+        assert_eq!(state.process(None), wait_to(tomorrow));
+        assert_eq!(last_run(&shared), Some(today));
+
+        // within the slack, it isn't missed
+        let (mut state, _) = daily(
+            today + time::Duration::seconds(3),
+            local(date!(2030 - 01 - 07), time!(7:00)),
+            CatchUp::Skip,
+        );
+        assert_eq!(state.process(None), wait_to(today));
+    }
+    #[test]
+    fn runs_only_latest_missed_occurrence() {
+        let today = local(date!(2030 - 01 - 08), time!(7:00));
+        let tomorrow = local(date!(2030 - 01 - 09), time!(7:00));
+        let (mut state, shared) = daily(
+            local(date!(2030 - 01 - 08), time!(7:30)),
+            local(date!(2030 - 01 - 05), time!(7:00)),
+            CatchUp::RunLate(HOUR),
+        );
+        assert_eq!(state.process(None), wait_to(today));
+        assert_eq!(state.process(None), Action::Set(Strength::new(1.0)));
+        assert_eq!(last_run(&shared), Some(today));
+        assert_eq!(state.process(None), wait_to(tomorrow));
+    }
+    /// A [`State`] at `now` at full strength, without schedulers.
+    fn full(now: OffsetDateTime) -> (State, Arc<Mutex<SharedState>>) {
+        let shared = Arc::new(Mutex::new(SharedState::new(WeekScheduler::default())));
+        let mut state = State::new(Arc::clone(&shared));
+        state.set_now(now);
+        state.process(Some(Command::Set(Strength::new(1.0))));
+        (state, shared)
+    }
+    fn sleep_timer(shared: &Mutex<SharedState>) -> Option<SleepTimer> {
+        shared.lock().unwrap().get_sleep_timer().copied()
+    }
+    const MINUTE: time::Duration = time::Duration::minutes(1);
+
+    #[test]
+    fn sleep_timer_extended() {
+        let now = local(date!(2030 - 01 - 08), time!(22:00));
+        let (mut state, shared) = full(now);
+        let fade = Duration::from_secs(5 * 60);
+        assert_eq!(
+            state.process(Some(Command::SetSleepTimer(now + 10 * MINUTE, fade))),
+            wait_to(now + 5 * MINUTE)
+        );
+        assert_eq!(
+            state.process(Some(Command::ExtendSleepTimer(Duration::from_secs(
+                10 * 60
+            )))),
+            wait_to(now + 15 * MINUTE)
+        );
+
+        // the fade starts, with the timer kept until it's done
+        state.set_now(now + 16 * MINUTE);
+        assert!(matches!(state.process(None), Action::Set(_)));
+        assert_eq!(
+            shared.lock().unwrap().get_transition().unwrap().time,
+            Duration::from_secs(4 * 60)
+        );
+        assert_eq!(sleep_timer(&shared).unwrap().end, now + 20 * MINUTE);
+
+        // extending during the fade stops it until the new fade start
+        assert_eq!(
+            state.process(Some(Command::ExtendSleepTimer(Duration::from_secs(
+                10 * 60
+            )))),
+            wait_to(now + 25 * MINUTE)
+        );
+        assert!(shared.lock().unwrap().get_transition().is_none());
+        assert_eq!(sleep_timer(&shared).unwrap().end, now + 30 * MINUTE);
+
+        // the fade has passed
+        state.set_now(now + 31 * MINUTE);
+        assert_eq!(state.process(None), Action::Set(Strength::new(0.0)));
+        assert_eq!(sleep_timer(&shared), None);
+    }
+    #[test]
+    fn sleep_timer_cancelled_during_fade() {
+        let now = local(date!(2030 - 01 - 08), time!(22:00));
+        let (mut state, shared) = full(now);
+        state.process(Some(Command::SetSleepTimer(
+            now + 10 * MINUTE,
+            Duration::from_secs(5 * 60),
+        )));
+        state.set_now(now + 6 * MINUTE);
+        assert!(matches!(state.process(None), Action::Set(_)));
+        assert!(shared.lock().unwrap().get_transition().is_some());
+
+        let strength = match state.process(Some(Command::CancelSleepTimer)) {
+            Action::Set(strength) => strength,
+            action => panic!("the fade wasn't stopped: {:?}", action),
+        };
+        assert!(!strength.is_off());
+        {
+            let shared = shared.lock().unwrap();
+            assert!(shared.get_transition().is_none());
+            assert_eq!(*shared.get_strength(), strength);
+            assert_eq!(shared.get_sleep_timer(), None);
         }
+        assert_eq!(state.process(None), Action::Wait(SleepTime::Forever));
+    }
+    #[test]
+    fn sleep_timer_kept_until_fade_is_done() {
+        let now = local(date!(2030 - 01 - 08), time!(22:00));
+        let (mut state, shared) = full(now);
+        state.process(Some(Command::SetSleepTimer(
+            now + 10 * MINUTE,
+            Duration::from_millis(50),
+        )));
+        state.set_now(now + 10 * MINUTE - time::Duration::milliseconds(20));
+        assert!(matches!(state.process(None), Action::Set(_)));
+        assert!(sleep_timer(&shared).is_some());
+
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(state.process(None), Action::Set(Strength::new(0.0)));
+        assert_eq!(sleep_timer(&shared), None);
+        assert_eq!(state.process(None), Action::Wait(SleepTime::Forever));
     }
 }
@@ -0,0 +1,204 @@
+//! Persistent key-value storage.
+//!
+//! All data which should survive a restart (the state, timezone and networks) is saved in a
+//! [`Store`], so the daemon doesn't have to care if it's written to disk or flash.
+#[cfg(feature = "esp32")]
+use embedded_svc::storage::*;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A persistent key-value store.
+///
+/// Keys are short ASCII names, such as `state.ron`.
+/// Keep them under 16 bytes, as [`NvsStore`] can't store longer keys.
+pub trait Store: Send {
+    /// Get the value of `key`. Returns [`None`] if it isn't set.
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+    /// Set `key` to `value`, replacing any previous value.
+    fn put(&mut self, key: &str, value: &[u8]) -> io::Result<()>;
+    /// Removes `key`. Succeeds if the key isn't set.
+    fn remove(&mut self, key: &str) -> io::Result<()>;
+}
+
+/// Stores every key as a file in a directory.
+#[derive(Debug, Clone)]
+pub struct FsStore {
+    root: PathBuf,
+}
+impl FsStore {
+    /// The directory at `root` is created when the first value is put.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+    /// Gets the path of `key`.
+    ///
+    /// Returns an error if `key` isn't a plain file name.
+    pub fn path(&self, key: &str) -> io::Result<PathBuf> {
+        if key.is_empty() || key == "." || key == ".." || key.contains(['/', '\\'].as_ref()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid key {:?}", key),
+            ));
+        }
+        Ok(self.root.join(key))
+    }
+}
+impl Store for FsStore {
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.path(key)?) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+    /// Writes `value` to a temporary file, which then replaces the old one.
+    /// A crash or power cut never leaves a partially written value.
+    fn put(&mut self, key: &str, value: &[u8]) -> io::Result<()> {
+        let path = self.path(key)?;
+        let temp = self.path(&format!(".{}.tmp", key))?;
+        std::fs::create_dir_all(&self.root)?;
+        {
+            let mut file = std::fs::File::create(&temp)?;
+            file.write_all(value)?;
+            file.sync_all()?;
+        }
+        std::fs::rename(&temp, &path)?;
+        // make the rename durable
+        #[cfg(unix)]
+        std::fs::File::open(&self.root)?.sync_all()?;
+        Ok(())
+    }
+    fn remove(&mut self, key: &str) -> io::Result<()> {
+        match std::fs::remove_file(self.path(key)?) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Keeps the values in memory. Useful for testing and when nothing should be persisted.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    map: HashMap<String, Vec<u8>>,
+}
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+impl Store for MemoryStore {
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.map.get(key).cloned())
+    }
+    fn put(&mut self, key: &str, value: &[u8]) -> io::Result<()> {
+        self.map.insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+    fn remove(&mut self, key: &str) -> io::Result<()> {
+        self.map.remove(key);
+        Ok(())
+    }
+}
+
+/// Stores the values in the non-volatile storage of the esp32.
+#[cfg(feature = "esp32")]
+pub struct NvsStore(esp_idf_svc::nvs_storage::EspNvsStorage);
+#[cfg(feature = "esp32")]
+impl NvsStore {
+    pub fn new(storage: esp_idf_svc::nvs_storage::EspNvsStorage) -> Self {
+        Self(storage)
+    }
+}
+#[cfg(feature = "esp32")]
+fn nvs_error(err: esp_idf_sys::EspError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err.to_string())
+}
+#[cfg(feature = "esp32")]
+impl Store for NvsStore {
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let len = match self.0.len(key).map_err(nvs_error)? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        let mut buf = vec![0; len as usize];
+        self.0.get_raw(key, &mut buf).map_err(nvs_error)?;
+        Ok(Some(buf))
+    }
+    fn put(&mut self, key: &str, value: &[u8]) -> io::Result<()> {
+        self.0.put_raw(key, value).map_err(nvs_error)?;
+        Ok(())
+    }
+    fn remove(&mut self, key: &str) -> io::Result<()> {
+        self.0.remove(key).map_err(nvs_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fs_store_rejects_paths() {
+        let store = FsStore::new("data");
+        for key in [
+            "",
+            ".",
+            "..",
+            "../state.ron",
+            "dir/state.ron",
+            "/state.ron",
+            "dir\\state.ron",
+        ] {
+            assert!(store.path(key).is_err(), "{:?} was accepted", key);
+        }
+        assert_eq!(
+            store.path("state.ron").unwrap(),
+            Path::new("data").join("state.ron")
+        );
+    }
+    #[test]
+    fn fs_store_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = FsStore::new(dir.path().join("data"));
+        assert_eq!(store.get("state.ron").unwrap(), None);
+        // the directory is created on the first put
+        store.put("state.ron", b"first").unwrap();
+        assert_eq!(
+            store.get("state.ron").unwrap().as_deref(),
+            Some(&b"first"[..])
+        );
+        store.put("state.ron", b"second").unwrap();
+        assert_eq!(
+            store.get("state.ron").unwrap().as_deref(),
+            Some(&b"second"[..])
+        );
+
+        let files: Vec<_> = std::fs::read_dir(store.root())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, ["state.ron"]);
+
+        store.remove("state.ron").unwrap();
+        assert_eq!(store.get("state.ron").unwrap(), None);
+        store.remove("state.ron").unwrap();
+        assert!(store.put("../state.ron", b"").is_err());
+    }
+    #[test]
+    fn memory_store_round_trip() {
+        let mut store = MemoryStore::new();
+        assert_eq!(store.get("tz").unwrap(), None);
+        store.put("tz", b"+02:00").unwrap();
+        assert_eq!(store.get("tz").unwrap().as_deref(), Some(&b"+02:00"[..]));
+        store.put("tz", b"+01:00").unwrap();
+        assert_eq!(store.get("tz").unwrap().as_deref(), Some(&b"+01:00"[..]));
+        store.remove("tz").unwrap();
+        assert_eq!(store.get("tz").unwrap(), None);
+        store.remove("tz").unwrap();
+    }
+}
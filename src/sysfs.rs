@@ -0,0 +1,601 @@
+//! Outputs through the Linux sysfs interface: PWM, as found on the BeagleBone, Rockchip
+//! and Allwinner boards, LEDs and display backlights.
+//!
+//! Every PWM controller is a directory `pwmchipN` in the sysfs root (usually [`DEFAULT_ROOT`]).
+//! A channel `M` is exported by writing it to `pwmchipN/export`, which creates `pwmchipN/pwmM`
+//! with the files `period`, `duty_cycle`, `polarity` and `enable`.
+//!
+//! LEDs ([`LEDS_ROOT`]) and backlights ([`BACKLIGHT_ROOT`]) are directories with the files
+//! `brightness` and `max_brightness`. See [`Brightness`].
+use crate::{Capabilities, OutputError, Strength, VariableOut};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// Where the PWM controllers are in a Linux system.
+pub const DEFAULT_ROOT: &str = "/sys/class/pwm";
+/// Where the LEDs are in a Linux system.
+pub const LEDS_ROOT: &str = "/sys/class/leds";
+/// Where the display backlights are in a Linux system.
+pub const BACKLIGHT_ROOT: &str = "/sys/class/backlight";
+/// The frequencies [`SysfsPwm`] accepts. The driver might not support all of them.
+const FREQUENCY: (f64, f64) = (1.0, 10_000_000.0);
+/// How long to wait for the channel to appear after exporting it.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A PWM channel exported through sysfs.
+///
+/// The channel is disabled and unexported (if it was exported by us) when dropped.
+#[derive(Debug)]
+pub struct SysfsPwm {
+    chip: PathBuf,
+    channel: u32,
+    dir: PathBuf,
+    /// In nanoseconds.
+    period: u64,
+    /// In nanoseconds.
+    duty_cycle: u64,
+    exported: bool,
+}
+impl SysfsPwm {
+    /// Opens `channel` of `pwmchip{chip}` in [`DEFAULT_ROOT`].
+    ///
+    /// See [`SysfsPwm::with_root`].
+    pub fn new(chip: u32, channel: u32, frequency: f64, inverted: bool) -> io::Result<Self> {
+        Self::with_root(DEFAULT_ROOT, chip, channel, frequency, inverted)
+    }
+    /// Opens `channel` of `pwmchip{chip}` in `root`, exporting it if needed.
+    /// The channel is left disabled with a duty cycle of 0.
+    ///
+    /// If `inverted`, the output is low when the strength is `1`.
+    /// Returns an error if the chip or channel doesn't exist or the driver rejects the settings.
+    pub fn with_root(
+        root: impl AsRef<Path>,
+        chip: u32,
+        channel: u32,
+        frequency: f64,
+        inverted: bool,
+    ) -> io::Result<Self> {
+        if !(FREQUENCY.0..=FREQUENCY.1).contains(&frequency) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported frequency {} Hz", frequency),
+            ));
+        }
+        let chip_dir = root.as_ref().join(format!("pwmchip{}", chip));
+        if !chip_dir.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no PWM chip at {}", chip_dir.display()),
+            ));
+        }
+        if let Ok(count) = read(&chip_dir.join("npwm")) {
+            if matches!(count.parse::<u32>(), Ok(count) if channel >= count) {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} only has {} channels", chip_dir.display(), count),
+                ));
+            }
+        }
+
+        let dir = chip_dir.join(format!("pwm{}", channel));
+        let exported = !dir.is_dir();
+        if exported {
+            write(&chip_dir.join("export"), channel)?;
+            // udev might have to set the permissions first
+            let start = std::time::Instant::now();
+            while !dir.join("enable").is_file() {
+                if start.elapsed() > EXPORT_TIMEOUT {
+                    let _ = write(&chip_dir.join("unexport"), channel);
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("{} didn't appear after exporting it", dir.display()),
+                    ));
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        let mut pwm = Self {
+            chip: chip_dir,
+            channel,
+            dir,
+            period: 0,
+            duty_cycle: 0,
+            exported,
+        };
+        // the polarity can only be changed when disabled
+        pwm.write("enable", 0)?;
+        // the duty cycle can't be longer than the period
+        pwm.write("duty_cycle", 0)?;
+        pwm.period = period(frequency);
+        pwm.write("period", pwm.period)?;
+        let polarity = if inverted { "inversed" } else { "normal" };
+        match pwm.write("polarity", polarity) {
+            // not all drivers support setting the polarity
+            Err(err) if !inverted && err.kind() == io::ErrorKind::NotFound => {}
+            result => result?,
+        }
+        Ok(pwm)
+    }
+    /// The directory of the channel, `pwmchipN/pwmM`.
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+    fn write(&self, file: &str, value: impl ToString) -> io::Result<()> {
+        write(&self.dir.join(file), value)
+    }
+    fn set_duty_cycle(&mut self, duty_cycle: u64) -> io::Result<()> {
+        self.write("duty_cycle", duty_cycle)?;
+        self.duty_cycle = duty_cycle;
+        Ok(())
+    }
+}
+impl VariableOut for SysfsPwm {
+    fn set(&mut self, value: Strength) {
+        let duty_cycle = (self.period as f64 * value.into_inner()).round() as u64;
+        self.set_duty_cycle(duty_cycle)
+            .expect("failed to set sysfs PWM duty cycle");
+    }
+    fn enable(&mut self) {
+        println!("Enabling sysfs PWM.");
+        self.write("enable", 1).expect("failed to enable sysfs PWM");
+    }
+    fn disable(&mut self) {
+        println!("Disabling sysfs PWM.");
+        self.write("enable", 0)
+            .expect("failed to disable sysfs PWM");
+    }
+    fn prepare(&mut self) {
+        self.set_duty_cycle(0)
+            .expect("failed to set sysfs PWM duty cycle");
+    }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            frequency: Some(1e9 / self.period as f64),
+            frequency_range: Some(FREQUENCY),
+            ..Default::default()
+        }
+    }
+    fn set_frequency(&mut self, frequency: f64) -> Result<(), OutputError> {
+        self.capabilities().check_frequency(frequency)?;
+        let device = |err: io::Error| OutputError::Device(err.to_string());
+        let new_period = period(frequency);
+        let duty_cycle = (self.duty_cycle as f64 * new_period as f64 / self.period as f64) as u64;
+        // the duty cycle can never be longer than the period
+        if new_period < self.period {
+            self.set_duty_cycle(duty_cycle).map_err(device)?;
+            self.write("period", new_period).map_err(device)?;
+        } else {
+            self.write("period", new_period).map_err(device)?;
+            self.set_duty_cycle(duty_cycle).map_err(device)?;
+        }
+        self.period = new_period;
+        Ok(())
+    }
+}
+impl Drop for SysfsPwm {
+    fn drop(&mut self) {
+        let _ = self.write("enable", 0);
+        if self.exported {
+            let _ = write(&self.chip.join("unexport"), self.channel);
+        }
+    }
+}
+
+/// The kind of device a [`Brightness`] controls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrightnessKind {
+    /// An LED in [`LEDS_ROOT`].
+    Led,
+    /// A display backlight in [`BACKLIGHT_ROOT`].
+    Backlight,
+}
+
+/// An LED or backlight, controlled through its `brightness` file.
+///
+/// The strength is scaled to `max_brightness`, so LEDs which are only on or off
+/// (`max_brightness` is `1`) turn on at half the strength.
+///
+/// The trigger of an LED (e.g. `mmc0` or `heartbeat`) is set to `none` when opened,
+/// so the kernel doesn't change the brightness. It's restored when dropped.
+#[derive(Debug)]
+pub struct Brightness {
+    dir: PathBuf,
+    kind: BrightnessKind,
+    max: u32,
+    trigger: Option<String>,
+}
+impl Brightness {
+    /// Opens the LED `name` in [`LEDS_ROOT`]. See [`Brightness::with_root`].
+    pub fn led(name: &str) -> io::Result<Self> {
+        Self::with_root(LEDS_ROOT, name, BrightnessKind::Led)
+    }
+    /// Opens the backlight `name` in [`BACKLIGHT_ROOT`]. See [`Brightness::with_root`].
+    pub fn backlight(name: &str) -> io::Result<Self> {
+        Self::with_root(BACKLIGHT_ROOT, name, BrightnessKind::Backlight)
+    }
+    /// Opens the device `name` in `root`, e.g. `led0` in `/sys/class/leds`.
+    /// The brightness is left at 0.
+    ///
+    /// Returns an error if the device doesn't exist or the brightness can't be written.
+    pub fn with_root(root: impl AsRef<Path>, name: &str, kind: BrightnessKind) -> io::Result<Self> {
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid device name {:?}", name),
+            ));
+        }
+        let dir = root.as_ref().join(name);
+        let max = read(&dir.join("max_brightness"))?;
+        let max = max.parse().ok().filter(|max| *max > 0).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid max_brightness of {}: {:?}", dir.display(), max),
+            )
+        })?;
+
+        let trigger = match kind {
+            // the selected trigger is in brackets: `none [mmc0] timer`
+            BrightnessKind::Led => read(&dir.join("trigger")).ok().and_then(|triggers| {
+                let trigger = triggers
+                    .split(' ')
+                    .find(|trigger| trigger.starts_with('['))?
+                    .trim_matches(|c| c == '[' || c == ']')
+                    .to_string();
+                if trigger == "none" {
+                    None
+                } else {
+                    Some(trigger)
+                }
+            }),
+            BrightnessKind::Backlight => None,
+        };
+        let out = Self {
+            dir,
+            kind,
+            max,
+            trigger,
+        };
+        if out.trigger.is_some() {
+            out.write("trigger", "none")?;
+        }
+        out.write("brightness", 0)?;
+        Ok(out)
+    }
+    /// The directory of the device.
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+    pub fn kind(&self) -> BrightnessKind {
+        self.kind
+    }
+    /// The `max_brightness` of the device.
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+    fn write(&self, file: &str, value: impl ToString) -> io::Result<()> {
+        write(&self.dir.join(file), value)
+    }
+    /// Writes `bl_power` of a backlight. Not all drivers have it.
+    fn power(&self, on: bool) -> io::Result<()> {
+        // FB_BLANK_UNBLANK and FB_BLANK_POWERDOWN
+        match self.write("bl_power", if on { 0 } else { 4 }) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+impl VariableOut for Brightness {
+    fn set(&mut self, value: Strength) {
+        let brightness = (self.max as f64 * value.into_inner()).round() as u32;
+        self.write("brightness", brightness)
+            .expect("failed to set brightness");
+    }
+    fn enable(&mut self) {
+        if self.kind == BrightnessKind::Backlight {
+            println!("Enabling backlight.");
+            self.power(true).expect("failed to enable backlight");
+        }
+    }
+    fn disable(&mut self) {
+        match self.kind {
+            BrightnessKind::Led => self.write("brightness", 0),
+            BrightnessKind::Backlight => {
+                println!("Disabling backlight.");
+                self.power(false)
+            }
+        }
+        .expect("failed to disable output");
+    }
+    fn prepare(&mut self) {
+        self.write("brightness", 0)
+            .expect("failed to set brightness");
+    }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            resolution: Some(32 - self.max.leading_zeros()),
+            ..Default::default()
+        }
+    }
+}
+impl Drop for Brightness {
+    fn drop(&mut self) {
+        if let Some(trigger) = &self.trigger {
+            let _ = self.write("trigger", trigger);
+        }
+    }
+}
+
+/// The period of `frequency`, in nanoseconds.
+fn period(frequency: f64) -> u64 {
+    (1e9 / frequency).round() as u64
+}
+fn read(path: &Path) -> io::Result<String> {
+    std::fs::read_to_string(path)
+        .map(|s| s.trim().to_string())
+        .map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("failed to read {}: {}", path.display(), err),
+            )
+        })
+}
+/// Doesn't create the file, as sysfs attributes can't be created.
+fn write(path: &Path, value: impl ToString) -> io::Result<()> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(value.to_string().as_bytes()))
+        .map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("failed to write {}: {}", path.display(), err),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A `pwmchip0` with `channels` channels, none exported.
+    fn chip(root: &Path, channels: u32) -> PathBuf {
+        let chip = root.join("pwmchip0");
+        fs::create_dir_all(&chip).unwrap();
+        fs::write(chip.join("npwm"), format!("{}\n", channels)).unwrap();
+        fs::write(chip.join("export"), "").unwrap();
+        fs::write(chip.join("unexport"), "").unwrap();
+        chip
+    }
+    /// Creates the files of an exported channel, as the kernel does.
+    fn export(chip: &Path, channel: u32, polarity: bool) -> PathBuf {
+        let dir = chip.join(format!("pwm{}", channel));
+        fs::create_dir_all(&dir).unwrap();
+        for file in ["period", "duty_cycle", "enable"] {
+            fs::write(dir.join(file), "0").unwrap();
+        }
+        if polarity {
+            fs::write(dir.join("polarity"), "normal").unwrap();
+        }
+        dir
+    }
+    fn read(path: impl AsRef<Path>) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn export_waits_for_channel() {
+        let root = tempfile::tempdir().unwrap();
+        let chip = chip(root.path(), 2);
+        let kernel = {
+            let chip = chip.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                export(&chip, 1, true)
+            })
+        };
+        let pwm = SysfsPwm::with_root(root.path(), 0, 1, 1000.0, true).unwrap();
+        let dir = kernel.join().unwrap();
+        assert_eq!(read(chip.join("export")), "1");
+        assert_eq!(pwm.path(), dir);
+        assert_eq!(read(dir.join("period")), "1000000");
+        assert_eq!(read(dir.join("duty_cycle")), "0");
+        assert_eq!(read(dir.join("polarity")), "inversed");
+
+        drop(pwm);
+        assert_eq!(read(chip.join("unexport")), "1");
+    }
+    #[test]
+    fn export_timeout_unexports() {
+        let root = tempfile::tempdir().unwrap();
+        let chip = chip(root.path(), 1);
+        let err = SysfsPwm::with_root(root.path(), 0, 0, 1000.0, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(read(chip.join("unexport")), "0");
+    }
+    #[test]
+    fn exported_channel_stays_exported() {
+        let root = tempfile::tempdir().unwrap();
+        let chip = chip(root.path(), 1);
+        let dir = export(&chip, 0, true);
+        fs::write(dir.join("enable"), "1").unwrap();
+        let mut pwm = SysfsPwm::with_root(root.path(), 0, 0, 50.0, false).unwrap();
+        assert_eq!(read(dir.join("enable")), "0");
+        assert_eq!(read(dir.join("period")), "20000000");
+        assert_eq!(read(dir.join("polarity")), "normal");
+
+        pwm.enable();
+        assert_eq!(read(dir.join("enable")), "1");
+        drop(pwm);
+        assert_eq!(read(dir.join("enable")), "0");
+        assert_eq!(read(chip.join("export")), "");
+        assert_eq!(read(chip.join("unexport")), "");
+    }
+    #[test]
+    fn channel_bounds() {
+        let root = tempfile::tempdir().unwrap();
+        let chip = chip(root.path(), 2);
+        export(&chip, 1, true);
+        export(&chip, 2, true);
+        let err = SysfsPwm::with_root(root.path(), 0, 2, 1000.0, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(SysfsPwm::with_root(root.path(), 0, 1, 1000.0, false).is_ok());
+
+        let err = SysfsPwm::with_root(root.path(), 1, 0, 1000.0, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let err = SysfsPwm::with_root(root.path(), 0, 1, 0.0, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+    #[test]
+    fn polarity_fallback() {
+        let root = tempfile::tempdir().unwrap();
+        let chip = chip(root.path(), 1);
+        let dir = export(&chip, 0, false);
+        // not all drivers have `polarity`, which is fine unless it should be inverted
+        let err = SysfsPwm::with_root(root.path(), 0, 0, 1000.0, true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        SysfsPwm::with_root(root.path(), 0, 0, 1000.0, false).unwrap();
+        assert!(!dir.join("polarity").exists());
+    }
+    #[test]
+    fn frequency_write_order() {
+        let root = tempfile::tempdir().unwrap();
+        let chip = chip(root.path(), 1);
+        let dir = export(&chip, 0, true);
+        // `period` and `duty_cycle` are the same FIFO, so the writes are read in order
+        let fifo = dir.join("duty_cycle");
+        fs::remove_file(&fifo).unwrap();
+        fs::remove_file(dir.join("period")).unwrap();
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        std::os::unix::fs::symlink(&fifo, dir.join("period")).unwrap();
+        let kernel = {
+            let fifo = fifo.clone();
+            thread::spawn(move || {
+                let mut writes = String::new();
+                while !writes.ends_with('.') {
+                    writes.push_str(&read(&fifo));
+                }
+                writes
+            })
+        };
+
+        let mut pwm = SysfsPwm::with_root(root.path(), 0, 0, 1000.0, false).unwrap();
+        pwm.set(Strength::new(0.5));
+        // shorter period: the duty cycle is written first
+        pwm.set_frequency(4000.0).unwrap();
+        // longer period: the period is written first
+        pwm.set_frequency(100.0).unwrap();
+        assert_eq!(pwm.capabilities().frequency, Some(100.0));
+        assert!(matches!(
+            pwm.set_frequency(0.5),
+            Err(OutputError::OutOfRange)
+        ));
+        drop(pwm);
+        write(&fifo, '.').unwrap();
+
+        let writes = [
+            "0", "1000000", "500000", "125000", "250000", "10000000", "5000000", ".",
+        ];
+        assert_eq!(kernel.join().unwrap(), writes.concat());
+    }
+
+    /// A device `name` in `root`, with `files`.
+    fn device(root: &Path, name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("brightness"), "0").unwrap();
+        for (file, content) in files {
+            fs::write(dir.join(file), content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn brightness_scales_to_max() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = device(root.path(), "lcd", &[("max_brightness", "255\n")]);
+        let mut out = Brightness::with_root(root.path(), "lcd", BrightnessKind::Backlight).unwrap();
+        assert_eq!(out.max(), 255);
+        assert_eq!(out.capabilities().resolution, Some(8));
+        for (strength, brightness) in [(1.0, "255"), (0.5, "128"), (0.1, "26"), (0.0, "0")] {
+            out.set(Strength::new(strength));
+            assert_eq!(read(dir.join("brightness")), brightness);
+        }
+    }
+    #[test]
+    fn brightness_on_off() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = device(root.path(), "led0", &[("max_brightness", "1")]);
+        let mut out = Brightness::with_root(root.path(), "led0", BrightnessKind::Led).unwrap();
+        assert_eq!(out.capabilities().resolution, Some(1));
+        for (strength, brightness) in [(0.4, "0"), (0.5, "1"), (1.0, "1")] {
+            out.set(Strength::new(strength));
+            assert_eq!(read(dir.join("brightness")), brightness);
+        }
+        out.disable();
+        assert_eq!(read(dir.join("brightness")), "0");
+    }
+    #[test]
+    fn brightness_rejects_devices() {
+        let root = tempfile::tempdir().unwrap();
+        device(root.path(), "zero", &[("max_brightness", "0")]);
+        device(root.path(), "garbage", &[("max_brightness", "bright")]);
+        for name in ["zero", "garbage"] {
+            let err = Brightness::with_root(root.path(), name, BrightnessKind::Led).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        for name in ["missing", "", "..", "a/b"] {
+            assert!(Brightness::with_root(root.path(), name, BrightnessKind::Led).is_err());
+        }
+    }
+    #[test]
+    fn led_trigger_restored() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = device(
+            root.path(),
+            "led0",
+            &[
+                ("max_brightness", "255"),
+                ("trigger", "none rc-feedback [mmc0] timer heartbeat\n"),
+            ],
+        );
+        let out = Brightness::with_root(root.path(), "led0", BrightnessKind::Led).unwrap();
+        assert_eq!(read(dir.join("trigger")), "none");
+        drop(out);
+        assert_eq!(read(dir.join("trigger")), "mmc0");
+
+        fs::write(dir.join("trigger"), "[none] timer heartbeat").unwrap();
+        let out = Brightness::with_root(root.path(), "led0", BrightnessKind::Led).unwrap();
+        drop(out);
+        assert_eq!(read(dir.join("trigger")), "[none] timer heartbeat");
+    }
+    #[test]
+    fn backlight_power() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = device(
+            root.path(),
+            "lcd",
+            &[("max_brightness", "100"), ("bl_power", "0")],
+        );
+        let mut out = Brightness::with_root(root.path(), "lcd", BrightnessKind::Backlight).unwrap();
+        out.disable();
+        assert_eq!(read(dir.join("bl_power")), "4");
+        out.enable();
+        assert_eq!(read(dir.join("bl_power")), "0");
+
+        // not all drivers have `bl_power`
+        let dir = device(root.path(), "dsi", &[("max_brightness", "100")]);
+        let mut out = Brightness::with_root(root.path(), "dsi", BrightnessKind::Backlight).unwrap();
+        out.disable();
+        out.enable();
+        assert!(!dir.join("bl_power").exists());
+    }
+}